@@ -0,0 +1,196 @@
+use biblatex::{Entry, Person};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use super::{get_date_parts, normalize_title, DEFAULT_TITLE_NORMALIZATION};
+
+/// An author (or editor) of an entry, as compared when looking for repeated entries
+///
+/// Names are normalized like titles (see `normalize_title`), and without diacritics, so that
+/// `G{\"o}del, Kurt`, `Gödel, K.` and `Godel, Kurt` are the same author.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AuthorName {
+    /// Family name, with its prefix (e.g. "van fraassen")
+    pub family: String,
+    /// First letter of each given name (e.g. "bc" for "Bas C.")
+    pub initials: String,
+}
+
+/// The authors (or, if it has none, the editors) of an entry, sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthorList {
+    pub names: Vec<AuthorName>,
+    /// Whether the list ends in "and others", so that there are more authors than those named
+    pub incomplete: bool,
+}
+
+/// How the authors of two entries compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorsComparison {
+    Same,
+    Different,
+    /// Either entry has no authors or editors
+    Unknown,
+}
+
+/// Normalize a name for comparison: as a title, and without diacritics
+fn normalize_name(name: &str) -> String {
+    normalize_title(name, &DEFAULT_TITLE_NORMALIZATION)
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect()
+}
+
+impl AuthorName {
+    fn new(person: &Person) -> Self {
+        let family = normalize_name(&format!("{} {}", person.prefix, person.name));
+        let initials = normalize_name(&person.given_name)
+            .split_whitespace()
+            .filter_map(|name| name.chars().next())
+            .collect();
+        AuthorName { family, initials }
+    }
+
+    /// Whether two names can be of the same person: they have the same family name, and the
+    /// initials of one begin with those of the other (so "Prior, A." is "Prior, Arthur N.")
+    pub fn matches(&self, other: &AuthorName) -> bool {
+        self.family == other.family
+            && (self.initials.starts_with(&other.initials)
+                || other.initials.starts_with(&self.initials))
+    }
+}
+
+impl AuthorList {
+    /// The authors of an entry, or its editors if it has no authors
+    pub fn new(entry: &Entry) -> Self {
+        let persons = entry
+            .author()
+            .filter(|authors| !authors.is_empty())
+            .or_else(|| {
+                entry
+                    .editors()
+                    .into_iter()
+                    .next()
+                    .map(|(editors, _)| editors)
+            })
+            .unwrap_or_default();
+        let mut list = AuthorList::default();
+        for person in persons.iter() {
+            let name = AuthorName::new(person);
+            match name.family.as_str() {
+                "others" if name.initials.is_empty() => list.incomplete = true,
+                "" => {}
+                _ => list.names.push(name),
+            }
+        }
+        list.names.sort();
+        list
+    }
+
+    /// Number of authors of this list that match a different author of the other (see
+    /// `AuthorName::matches`)
+    fn matched(&self, other: &AuthorList) -> usize {
+        let mut unmatched: Vec<&AuthorName> = other.names.iter().collect();
+        self.names
+            .iter()
+            .filter(
+                |name| match unmatched.iter().position(|other| name.matches(other)) {
+                    Some(idx) => {
+                        unmatched.remove(idx);
+                        true
+                    }
+                    None => false,
+                },
+            )
+            .count()
+    }
+
+    /// Compare two lists of authors, regardless of their order
+    ///
+    /// They are the same if every author of one matches a different author of the other (see
+    /// `AuthorName::matches`), and both have the same number of authors (unless the shorter list
+    /// ends in "and others").
+    pub fn compare(&self, other: &AuthorList) -> AuthorsComparison {
+        match self.overlap(other) {
+            None => AuthorsComparison::Unknown,
+            Some(overlap) => match overlap >= 1.0 {
+                true => AuthorsComparison::Same,
+                false => AuthorsComparison::Different,
+            },
+        }
+    }
+
+    /// Fraction, from 0 to 1, of the authors that both lists have in common (of those of the
+    /// shorter list, if it ends in "and others", or of the longer list otherwise)
+    ///
+    /// Returns None if either list is empty.
+    pub fn overlap(&self, other: &AuthorList) -> Option<f64> {
+        if self.names.is_empty() || other.names.is_empty() {
+            return None;
+        }
+        let (shorter, longer) = match self.names.len() <= other.names.len() {
+            true => (self, other),
+            false => (other, self),
+        };
+        let total = match shorter.incomplete {
+            true => shorter.names.len(),
+            false => longer.names.len(),
+        };
+        Some(shorter.matched(longer) as f64 / total as f64)
+    }
+}
+
+/// Compare the authors (or editors) of two entries
+pub fn compare_authors(prev_entry: &Entry, entry: &Entry) -> AuthorsComparison {
+    AuthorList::new(prev_entry).compare(&AuthorList::new(entry))
+}
+
+/// Whether two entries have the same year (None if either has no date)
+pub fn same_year(prev_entry: &Entry, entry: &Entry) -> Option<bool> {
+    let year = |entry: &Entry| get_date_parts(entry).map(|(year, _, _)| year);
+    Some(year(prev_entry)? == year(entry)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biblatex::Bibliography;
+
+    #[test]
+    fn test_compare_authors() {
+        let bibliography = Bibliography::parse(
+            r#"@article{a1, author = {Carnielli, Walter and Coniglio, Marcelo E.}, year = {2016}}
+            @article{a2, author = {M. Coniglio and W. A. Carnielli}, year = {2016}}
+            @article{a3, author = {Carnielli, Walter and Rodrigues, Abilio}, year = {2019}}
+            @article{a4, author = {Carnielli, Walter and others}, date = {2016-05}}
+            @article{a5, author = {Carnielli, Walter}}
+            @book{b1, author = {G{\"o}del, Kurt}}
+            @book{b2, author = {Godel, K.}}
+            @book{b3, author = {Gödel, Rudolf}}
+            @book{c1, author = {van Fraassen, Bas C.}}
+            @book{c2, editor = {{van Fraassen}, Bas}}
+            @book{d1, title = {No authors}}"#,
+        )
+        .unwrap();
+        let entry = |key: &str| bibliography.get(key).unwrap();
+        let compare = |key1: &str, key2: &str| compare_authors(entry(key1), entry(key2));
+        assert_eq!(compare("a1", "a2"), AuthorsComparison::Same);
+        assert_eq!(compare("a1", "a3"), AuthorsComparison::Different);
+        assert_eq!(compare("a1", "a4"), AuthorsComparison::Same);
+        assert_eq!(compare("a4", "a3"), AuthorsComparison::Same);
+        assert_eq!(compare("a1", "a5"), AuthorsComparison::Different);
+        assert_eq!(compare("b1", "b2"), AuthorsComparison::Same);
+        assert_eq!(compare("b1", "b3"), AuthorsComparison::Different);
+        assert_eq!(compare("c1", "c2"), AuthorsComparison::Same);
+        assert_eq!(compare("c1", "d1"), AuthorsComparison::Unknown);
+        let authors = |key: &str| AuthorList::new(entry(key));
+        assert_eq!(authors("a1").overlap(&authors("a3")), Some(0.5));
+        assert_eq!(authors("a5").overlap(&authors("a1")), Some(0.5));
+        assert_eq!(authors("a1").overlap(&authors("d1")), None);
+
+        assert_eq!(same_year(entry("a1"), entry("a2")), Some(true));
+        assert_eq!(same_year(entry("a1"), entry("a4")), Some(true));
+        assert_eq!(same_year(entry("a1"), entry("a3")), Some(false));
+        assert_eq!(same_year(entry("a1"), entry("a5")), None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use biblatex::{Bibliography, Chunk, ChunksExt, Entry};
+
+/// Fields whose value is the key of another entry
+pub const SINGLE_REFERENCE_FIELDS: [&str; 2] = ["crossref", "xref"];
+
+/// Fields whose value is a comma-separated list of keys of other entries
+pub const MULTIPLE_REFERENCE_FIELDS: [&str; 2] = ["xdata", "related"];
+
+/// Fields that make an entry inherit data from another (its parent), which BibTeX requires to
+/// come after the entry in the file
+pub const PARENT_FIELDS: [&str; 2] = ["crossref", "xdata"];
+
+/// Keys referenced by an entry through the given fields
+pub fn referenced_keys(entry: &Entry, fields: &[&str]) -> Vec<String> {
+    fields
+        .iter()
+        .filter_map(|field| entry.get(field))
+        .flat_map(|value| {
+            value
+                .format_verbatim()
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect::<Vec<String>>()
+        })
+        .collect()
+}
+
+/// Rewrite the references of an entry to other entries (crossref, xref, xdata and related) whose
+/// key has changed, given a map from old to new keys
+///
+/// Returns true if anything was rewritten.
+pub fn rewrite_references(entry: &mut Entry, renames: &HashMap<String, String>) -> bool {
+    let mut rewritten = false;
+    for field in SINGLE_REFERENCE_FIELDS
+        .iter()
+        .chain(MULTIPLE_REFERENCE_FIELDS.iter())
+    {
+        let old_keys = referenced_keys(entry, &[field]);
+        if old_keys.is_empty() {
+            continue;
+        }
+        let new_keys: Vec<&str> = old_keys
+            .iter()
+            .map(|key| renames.get(key).unwrap_or(key).as_str())
+            .collect();
+        if new_keys != old_keys {
+            entry.set(field, vec![Chunk::Normal(new_keys.join(","))]);
+            rewritten = true;
+        }
+    }
+    rewritten
+}
+
+/// Reorder a bibliography so that every entry comes before the entries it inherits data from
+/// (through crossref or xdata)
+///
+/// Entries that are not parents of any other entry keep their relative order, and go first.
+/// Then come the parents, then their parents, and so on.
+pub fn order_parents_after_children(bibliography: Bibliography) -> Bibliography {
+    let entries = bibliography.into_vec();
+    let indices: HashMap<&str, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| (entry.key.as_str(), idx))
+        .collect();
+
+    // The level of an entry is the length of the longest chain of children that reference it
+    // Computed by relaxation, bounded by the number of entries in case of cycles
+    let mut levels = vec![0; entries.len()];
+    let parents: Vec<Vec<usize>> = entries
+        .iter()
+        .map(|entry| {
+            referenced_keys(entry, &PARENT_FIELDS)
+                .iter()
+                .filter_map(|key| indices.get(key.as_str()).copied())
+                .collect()
+        })
+        .collect();
+    for _ in 0..entries.len() {
+        let mut changed = false;
+        for (child, child_parents) in parents.iter().enumerate() {
+            for &parent in child_parents {
+                if parent != child && levels[parent] < levels[child] + 1 {
+                    levels[parent] = (levels[child] + 1).min(entries.len());
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut leveled: Vec<(usize, Entry)> = levels.into_iter().zip(entries).collect();
+    // The sort is stable, so entries of the same level keep their order
+    leveled.sort_by_key(|(level, _)| *level);
+    let mut ordered = Bibliography::new();
+    for (_, entry) in leveled {
+        ordered.insert(entry);
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_references() {
+        let mut bibliography = Bibliography::parse(
+            "@incollection{child, crossref = {parent}, related = {a, parent,b}, xdata = {c}}",
+        )
+        .unwrap();
+        let entry = bibliography.get_mut("child").unwrap();
+        let renames = HashMap::from([
+            (String::from("parent"), String::from("parent_1")),
+            (String::from("b"), String::from("Other")),
+        ]);
+        assert!(rewrite_references(entry, &renames));
+        assert_eq!(referenced_keys(entry, &["crossref"]), vec!["parent_1"]);
+        assert_eq!(
+            referenced_keys(entry, &["related"]),
+            vec!["a", "parent_1", "Other"]
+        );
+        assert_eq!(referenced_keys(entry, &["xdata"]), vec!["c"]);
+        assert!(!rewrite_references(entry, &renames));
+    }
+
+    #[test]
+    fn test_order_parents_after_children() {
+        let bibliography = Bibliography::parse(
+            "@xdata{grandparent, publisher = {Springer}}
+            @collection{parent, title = {Collection}, xdata = {grandparent}}
+            @book{other, title = {Other}}
+            @incollection{child1, crossref = {parent}}
+            @incollection{child2, crossref = {parent}, related = {other}}",
+        )
+        .unwrap();
+        let ordered = order_parents_after_children(bibliography);
+        let keys: Vec<&str> = ordered.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["other", "child1", "child2", "parent", "grandparent"]);
+        assert!(ordered.get("parent").is_some());
+    }
+}
@@ -0,0 +1,549 @@
+use biblatex::{Bibliography, Chunk, ChunksExt, DateValue, Entry, EntryType, Person};
+use serde_json::value::RawValue;
+use serde_json::{json, Map, Value};
+
+use super::{generate_citation_key, RecordError};
+
+/// Get the entry type corresponding to a CSL item type
+pub fn entry_type_from_csl(csl_type: &str) -> EntryType {
+    match csl_type {
+        "article" | "article-journal" | "article-magazine" | "article-newspaper" => {
+            EntryType::Article
+        }
+        "book" => EntryType::Book,
+        "collection" => EntryType::Collection,
+        "chapter" => EntryType::InCollection,
+        "paper-conference" => EntryType::InProceedings,
+        "thesis" => EntryType::PhdThesis,
+        "report" => EntryType::TechReport,
+        "manuscript" => EntryType::Unpublished,
+        "webpage" | "post" | "post-weblog" => EntryType::Online,
+        "patent" => EntryType::Patent,
+        "dataset" => EntryType::Dataset,
+        "software" => EntryType::Software,
+        "entry" | "entry-dictionary" | "entry-encyclopedia" => EntryType::InReference,
+        "pamphlet" => EntryType::Booklet,
+        _ => EntryType::Misc,
+    }
+}
+
+/// Get the CSL item type corresponding to an entry type
+pub fn csl_type_from_entry(entry_type: &EntryType) -> &'static str {
+    match entry_type {
+        EntryType::Article => "article-journal",
+        EntryType::Book | EntryType::MvBook | EntryType::Proceedings | EntryType::MvProceedings => {
+            "book"
+        }
+        EntryType::Collection | EntryType::MvCollection => "collection",
+        EntryType::InBook
+        | EntryType::BookInBook
+        | EntryType::SuppBook
+        | EntryType::InCollection
+        | EntryType::SuppCollection => "chapter",
+        EntryType::InProceedings => "paper-conference",
+        EntryType::Thesis | EntryType::PhdThesis | EntryType::MastersThesis => "thesis",
+        EntryType::Report | EntryType::TechReport => "report",
+        EntryType::Unpublished => "manuscript",
+        EntryType::Online => "webpage",
+        EntryType::Patent => "patent",
+        EntryType::Dataset => "dataset",
+        EntryType::Software => "software",
+        EntryType::InReference => "entry-encyclopedia",
+        EntryType::Booklet => "pamphlet",
+        EntryType::Periodical | EntryType::SuppPeriodical => "periodical",
+        _ => "document",
+    }
+}
+
+/// Fields whose value is copied as is between CSL variables and entry fields
+/// (CSL variable, entry field)
+const PLAIN_FIELDS: [(&str, &str); 14] = [
+    ("title", "title"),
+    ("collection-title", "series"),
+    ("volume", "volume"),
+    ("edition", "edition"),
+    ("DOI", "doi"),
+    ("ISBN", "isbn"),
+    ("ISSN", "issn"),
+    ("URL", "url"),
+    ("PMID", "pmid"),
+    ("PMCID", "pmcid"),
+    ("language", "language"),
+    ("abstract", "abstract"),
+    ("note", "note"),
+    ("number-of-pages", "pagetotal"),
+];
+
+/// Parse the contents of a CSL-JSON file, returning an entry (or an error) for each item
+///
+/// The file must contain an array of items (or a single item). The citation key is taken from
+/// the id of each item, or generated from the first author and the year if there is none.
+pub fn parse_csl_json(src: &str) -> Vec<Result<Entry, RecordError>> {
+    let invalid = |e: serde_json::Error| RecordError {
+        line: e.line(),
+        text: src
+            .lines()
+            .nth(e.line().saturating_sub(1))
+            .unwrap_or_default()
+            .to_string(),
+        message: format!("Invalid CSL-JSON: {}", e),
+    };
+    // Keep the raw text of each item, to report where the problematic ones are
+    let items: Vec<&RawValue> = match serde_json::from_str(src) {
+        Ok(items) => items,
+        Err(_) => match serde_json::from_str::<&RawValue>(src) {
+            Ok(item) if item.get().starts_with('{') => vec![item],
+            Ok(_) => {
+                return vec![Err(RecordError {
+                    line: 1,
+                    text: src.lines().next().unwrap_or_default().to_string(),
+                    message: String::from("A CSL-JSON file must contain an array of items"),
+                })]
+            }
+            Err(e) => return vec![Err(invalid(e))],
+        },
+    };
+
+    items
+        .into_iter()
+        .map(|raw| {
+            let offset = raw.get().as_ptr() as usize - src.as_ptr() as usize;
+            let error = |message: &str| RecordError {
+                line: src[..offset].matches('\n').count() + 1,
+                text: raw.get().to_string(),
+                message: message.to_string(),
+            };
+            match serde_json::from_str::<Value>(raw.get()) {
+                Ok(Value::Object(item)) => {
+                    item_to_entry(&item).ok_or_else(|| error("Item has no author, title or date"))
+                }
+                _ => Err(error("Item is not a JSON object")),
+            }
+        })
+        .collect()
+}
+
+/// Convert a CSL item into an entry
+///
+/// Returns None if the item has no author, title or date at all.
+fn item_to_entry(item: &Map<String, Value>) -> Option<Entry> {
+    let get = |variable: &str| item.get(variable).and_then(value_to_string);
+
+    let csl_type = get("type").unwrap_or_default();
+    let entry_type = entry_type_from_csl(&csl_type);
+    let mut entry = Entry::new(String::new(), entry_type.clone());
+    let mut set = |field: &str, value: String| entry.set(field, vec![Chunk::Normal(value)]);
+
+    for (variable, field) in [
+        ("author", "author"),
+        ("editor", "editor"),
+        ("translator", "translator"),
+    ] {
+        if let Some(Value::Array(names)) = item.get(variable) {
+            let names: Vec<String> = names.iter().filter_map(name_from_csl).collect();
+            if !names.is_empty() {
+                set(field, names.join(" and "));
+            }
+        }
+    }
+    for (variable, field) in PLAIN_FIELDS {
+        if let Some(value) = get(variable) {
+            set(field, value);
+        }
+    }
+
+    // The container is the journal for articles and the book for everything else
+    if let Some(container_title) = get("container-title") {
+        match entry_type {
+            EntryType::Article => set("journal", container_title),
+            _ => set("booktitle", container_title),
+        }
+    }
+    if let Some(number) = get("issue").or_else(|| get("number")) {
+        set("number", number);
+    }
+    if let Some(pages) = get("page") {
+        set("pages", pages.replace('-', "--").replace("----", "--"));
+    }
+    if let Some(publisher) = get("publisher") {
+        match entry_type {
+            EntryType::PhdThesis => set("school", publisher),
+            EntryType::TechReport => set("institution", publisher),
+            _ => set("publisher", publisher),
+        }
+    }
+    if let Some(address) = get("publisher-place").or_else(|| get("event-place")) {
+        set("address", address);
+    }
+    if let Some(genre) = get("genre") {
+        set("type", genre);
+    }
+    if let Some(keywords) = get("keyword") {
+        set("keywords", keywords);
+    }
+
+    match item.get("issued").and_then(date_from_csl) {
+        // The full date only fits in the date field, which is split back when writing BibTeX
+        Some((year, Some(month), Some(day))) => {
+            set("date", format!("{}-{:02}-{:02}", year, month, day))
+        }
+        Some((year, month, _)) => {
+            set("year", year.to_string());
+            if let Some(month) = month {
+                set("month", month.to_string());
+            }
+        }
+        None => {}
+    }
+    if let Some((year, month, day)) = item.get("accessed").and_then(date_from_csl) {
+        let date = match (month, day) {
+            (Some(month), Some(day)) => format!("{}-{:02}-{:02}", year, month, day),
+            (Some(month), None) => format!("{}-{:02}", year, month),
+            _ => year.to_string(),
+        };
+        set("urldate", date);
+    }
+
+    if entry.get("author").is_none()
+        && entry.get("title").is_none()
+        && entry.get("year").is_none()
+        && entry.get("date").is_none()
+    {
+        return None;
+    }
+    entry.key = match get("id") {
+        Some(id) if !id.is_empty() && !id.contains(char::is_whitespace) => id,
+        _ => generate_citation_key(&entry),
+    };
+    Some(entry)
+}
+
+/// The value of a CSL variable as a string (CSL allows numbers for things like volumes)
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) if !string.trim().is_empty() => Some(string.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// Convert a CSL name into a name in BibTeX format ("von Last, Jr., First")
+fn name_from_csl(name: &Value) -> Option<String> {
+    let part = |key: &str| name.get(key).and_then(value_to_string);
+    if let Some(literal) = part("literal") {
+        // Braces keep institutions from being split into first and last names
+        return Some(format!("{{{}}}", literal));
+    }
+    let family = [
+        part("dropping-particle"),
+        part("non-dropping-particle"),
+        part("family"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<String>>()
+    .join(" ");
+    if family.is_empty() {
+        return part("given");
+    }
+    Some(
+        [Some(family), part("suffix"), part("given")]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join(", "),
+    )
+}
+
+/// Get the year, month and day (starting from 1) of a CSL date
+///
+/// Dates can be given as "date-parts" (e.g. `[[1960, 12]]`), as a "raw" or "literal" string, or
+/// directly as a string (e.g. "1960-12-01"). Only the first date of a range is taken.
+fn date_from_csl(date: &Value) -> Option<(i32, Option<u8>, Option<u8>)> {
+    let parts: Vec<String> = match date {
+        Value::String(string) => split_date(string),
+        Value::Object(date) => match date.get("date-parts") {
+            Some(Value::Array(ranges)) => ranges
+                .first()?
+                .as_array()?
+                .iter()
+                .filter_map(value_to_string)
+                .collect(),
+            _ => split_date(
+                &date
+                    .get("raw")
+                    .or_else(|| date.get("literal"))
+                    .and_then(value_to_string)?,
+            ),
+        },
+        _ => return None,
+    };
+    let mut parts = parts.iter().map(|part| part.trim().parse::<i32>().ok());
+    let year = parts.next()??;
+    let month = parts
+        .next()
+        .flatten()
+        .and_then(|month| u8::try_from(month).ok());
+    let day = parts
+        .next()
+        .flatten()
+        .and_then(|day| u8::try_from(day).ok());
+    Some((year, month.filter(|month| (1..=12).contains(month)), day))
+}
+
+fn split_date(date: &str) -> Vec<String> {
+    let start = date.split('/').next().unwrap_or_default();
+    start.split('-').map(str::to_string).collect()
+}
+
+/// Serialize a bibliography into a CSL-JSON array
+///
+/// Entries inherit the fields of the entries they reference through crossref or xdata, since
+/// CSL has no way of expressing those references.
+pub fn to_csl_json_string(bibliography: &Bibliography) -> String {
+    let items: Vec<Value> = bibliography
+        .iter()
+        .map(|entry| {
+            let entry = bibliography
+                .get_resolved(&entry.key)
+                .unwrap_or_else(|| entry.clone());
+            Value::Object(entry_to_item(&entry))
+        })
+        .collect();
+    let mut json = serde_json::to_string_pretty(&items).unwrap_or_else(|_| String::from("[]"));
+    json.push('\n');
+    json
+}
+
+/// Convert an entry into a CSL item
+fn entry_to_item(entry: &Entry) -> Map<String, Value> {
+    let mut item = Map::new();
+    // Field names keep the case they were written in (e.g. "ISSN")
+    let get = |field: &str| {
+        entry
+            .fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field))
+            .map(|(_, value)| value.format_verbatim())
+            .filter(|value| !value.is_empty())
+    };
+
+    item.insert(String::from("id"), json!(entry.key));
+    item.insert(
+        String::from("type"),
+        json!(csl_type_from_entry(&entry.entry_type)),
+    );
+    for (field, variable) in [
+        ("author", "author"),
+        ("editor", "editor"),
+        ("translator", "translator"),
+    ] {
+        if let Some(names) = entry.get_as::<Vec<Person>>(field) {
+            let names: Vec<Value> = names.iter().map(name_to_csl).collect();
+            item.insert(variable.to_string(), Value::Array(names));
+        }
+    }
+    for (variable, field) in PLAIN_FIELDS {
+        if let Some(value) = get(field) {
+            item.insert(variable.to_string(), json!(value));
+        }
+    }
+
+    if let Some(container_title) = get("journaltitle")
+        .or_else(|| get("journal"))
+        .or_else(|| get("booktitle"))
+    {
+        item.insert(String::from("container-title"), json!(container_title));
+    }
+    if let Some(number) = get("number").or_else(|| get("issue")) {
+        let variable = match entry.entry_type {
+            EntryType::Article | EntryType::Periodical => "issue",
+            _ => "number",
+        };
+        item.insert(variable.to_string(), json!(number));
+    }
+    if let Some(pages) = get("pages") {
+        item.insert(String::from("page"), json!(pages.replace("--", "-")));
+    }
+    if let Some(publisher) = get("publisher")
+        .or_else(|| get("school"))
+        .or_else(|| get("institution"))
+    {
+        item.insert(String::from("publisher"), json!(publisher));
+    }
+    if let Some(address) = get("address").or_else(|| get("location")) {
+        item.insert(String::from("publisher-place"), json!(address));
+    }
+    let genre = get("type").or_else(|| match entry.entry_type {
+        EntryType::PhdThesis => Some(String::from("PhD thesis")),
+        EntryType::MastersThesis => Some(String::from("Master's thesis")),
+        _ => None,
+    });
+    if let Some(genre) = genre {
+        item.insert(String::from("genre"), json!(genre));
+    }
+    if let Some(keywords) = get("keywords") {
+        item.insert(String::from("keyword"), json!(keywords));
+    }
+
+    if let Some(mut date) = entry.date() {
+        // biblatex only understands month names, but months are often written as numbers
+        let month = get("month").and_then(|month| month.parse::<u8>().ok());
+        if let (DateValue::At(datetime), Some(month)) = (&mut date.value, month) {
+            if datetime.month.is_none() && (1..=12).contains(&month) {
+                datetime.month = Some(month - 1);
+            }
+        }
+        item.insert(String::from("issued"), date_to_csl(&date.value));
+    }
+    if let Some(date) = entry.url_date() {
+        item.insert(String::from("accessed"), date_to_csl(&date.value));
+    }
+    item
+}
+
+/// Convert a name into a CSL name
+fn name_to_csl(person: &Person) -> Value {
+    let mut name = Map::new();
+    if person.given_name.is_empty() && person.prefix.is_empty() && person.suffix.is_empty() {
+        name.insert(String::from("literal"), json!(person.name));
+        return Value::Object(name);
+    }
+    name.insert(String::from("family"), json!(person.name));
+    if !person.given_name.is_empty() {
+        name.insert(String::from("given"), json!(person.given_name));
+    }
+    if !person.prefix.is_empty() {
+        name.insert(String::from("non-dropping-particle"), json!(person.prefix));
+    }
+    if !person.suffix.is_empty() {
+        name.insert(String::from("suffix"), json!(person.suffix));
+    }
+    Value::Object(name)
+}
+
+/// Convert a date (or date range) into CSL date-parts
+fn date_to_csl(date: &DateValue) -> Value {
+    let datetimes = match date {
+        DateValue::At(datetime) | DateValue::After(datetime) | DateValue::Before(datetime) => {
+            vec![datetime]
+        }
+        DateValue::Between(start, end) => vec![start, end],
+    };
+    let parts: Vec<Value> = datetimes
+        .into_iter()
+        .map(|datetime| {
+            // biblatex counts months and days from zero, CSL from one
+            let mut parts = vec![json!(datetime.year)];
+            if let Some(month) = datetime.month {
+                parts.push(json!(month + 1));
+                if let Some(day) = datetime.day {
+                    parts.push(json!(day + 1));
+                }
+            }
+            Value::Array(parts)
+        })
+        .collect();
+    json!({ "date-parts": parts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_csl_json() {
+        let src = fs::read_to_string("bib_files/csl_files/references.json").unwrap();
+        let items = parse_csl_json(&src);
+        assert_eq!(items.len(), 3);
+
+        let prior = items[0].as_ref().unwrap();
+        assert_eq!(prior.key, "Prior1960");
+        assert_eq!(prior.entry_type, EntryType::Article);
+        assert_eq!(prior.journal().unwrap().format_verbatim(), "Analysis");
+        assert_eq!(prior.get("volume").unwrap().format_verbatim(), "21");
+        assert_eq!(prior.get("pages").unwrap().format_verbatim(), "38--39");
+        assert_eq!(prior.get("month").unwrap().format_verbatim(), "12");
+
+        let von_wright = items[1].as_ref().unwrap();
+        let author = &von_wright.author().unwrap()[0];
+        assert_eq!(
+            (author.prefix.as_str(), author.name.as_str()),
+            ("von", "Wright")
+        );
+        assert_eq!(von_wright.get("year").unwrap().format_verbatim(), "1951");
+
+        // Without an id, the key is generated
+        let chapter = items[2].as_ref().unwrap();
+        assert_eq!(chapter.key, "Barrio2018");
+        assert_eq!(chapter.entry_type, EntryType::InCollection);
+        assert_eq!(chapter.editors()[0].0.len(), 2);
+        assert_eq!(chapter.get("date").unwrap().format_verbatim(), "2018-10-03");
+        assert_eq!(chapter.get("pmid").unwrap().format_verbatim(), "12345678");
+
+        // Writing the items gives them back
+        let mut bibliography = Bibliography::new();
+        for item in items.into_iter().flatten() {
+            bibliography.insert(item);
+        }
+        let written = parse_csl_json(&to_csl_json_string(&bibliography));
+        assert_eq!(written.len(), 3);
+        assert!(written
+            .iter()
+            .flatten()
+            .any(|entry| entry.key == "vonWright1951"));
+    }
+
+    #[test]
+    fn test_parse_csl_json_errors() {
+        let src = fs::read_to_string("bib_files/lenient_files/incomplete.json").unwrap();
+        let items = parse_csl_json(&src);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap().key, "Dummett1973");
+        let error = items[1].as_ref().unwrap_err();
+        assert_eq!(error.line, 10);
+        assert_eq!(error.message, "Item has no author, title or date");
+
+        assert_eq!(parse_csl_json("[1, 2").len(), 1);
+        assert!(parse_csl_json("[1, 2").remove(0).is_err());
+    }
+
+    #[test]
+    fn test_to_csl_json_string() {
+        let bibliography = Bibliography::parse(
+            "@phdthesis{thesis, author = {de la Vega, Jr., Juan and {ACME}}, title = {Title},
+                school = {UBA}, date = {2020-03-05}, pages = {1--10}}
+            @inproceedings{paper, title = {Paper}, crossref = {proc}, year = {2019}}
+            @proceedings{proc, title = {Proceedings}, publisher = {ACM}}",
+        )
+        .unwrap();
+        let json = to_csl_json_string(&bibliography);
+        let items: Vec<Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(items.len(), 3);
+
+        let thesis = &items[0];
+        assert_eq!(thesis["type"], "thesis");
+        assert_eq!(thesis["genre"], "PhD thesis");
+        assert_eq!(thesis["publisher"], "UBA");
+        assert_eq!(thesis["page"], "1-10");
+        assert_eq!(thesis["issued"], json!({ "date-parts": [[2020, 3, 5]] }));
+        assert_eq!(
+            thesis["author"][0],
+            json!({ "family": "Vega", "given": "Juan", "non-dropping-particle": "de la", "suffix": "Jr." })
+        );
+        assert_eq!(thesis["author"][1], json!({ "literal": "ACME" }));
+
+        // The paper inherits from the proceedings
+        let paper = &items[1];
+        assert_eq!(paper["container-title"], "Proceedings");
+        assert_eq!(paper["publisher"], "ACM");
+
+        // Reading the output back gives the same entries
+        let entries: Vec<Entry> = parse_csl_json(&json).into_iter().flatten().collect();
+        assert_eq!(entries[0].key, "thesis");
+        assert_eq!(entries[0].entry_type, EntryType::PhdThesis);
+        assert_eq!(entries[0].author().unwrap().len(), 2);
+        assert_eq!(entries[1].get("year").unwrap().format_verbatim(), "2019");
+    }
+}
@@ -0,0 +1,265 @@
+use biblatex::{ChunksExt, Entry};
+use serde_json::{json, Value};
+
+use super::{ComparisonResult, MatchReport};
+
+/// Which of two repeated entries to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    First,
+    Second,
+    Both,
+}
+
+/// One of the entries of a recorded decision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEntry {
+    /// Fingerprint of the entry, as returned by `entry_fingerprint`
+    pub fingerprint: String,
+    /// Key of the entry when the decision was made (only to make the file readable)
+    pub key: String,
+}
+
+/// A decision about two repeated entries, made by the user on a previous run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedDecision {
+    pub first: RecordedEntry,
+    pub second: RecordedEntry,
+    pub keep: Keep,
+}
+
+/// Decisions about repeated entries made on previous runs, which are applied instead of asking
+/// again
+///
+/// They are stored as a JSON array of objects such as
+/// `{"first": {"fingerprint": "...", "key": "..."}, "second": {...}, "keep": "first"}`, where
+/// `keep` is "first", "second" or "both". Entries are identified by their fingerprint, so the
+/// decisions still apply when the order of the files or the keys of the entries change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordedDecisions {
+    pub decisions: Vec<RecordedDecision>,
+}
+
+/// A stable fingerprint of the contents of an entry: its type and fields, but not its key
+///
+/// It is the 64-bit FNV-1a hash (in hexadecimal) of the entry type followed by each field name
+/// (in lowercase) and value, so it is the same on every run and platform.
+pub fn entry_fingerprint(entry: &Entry) -> String {
+    let mut contents = entry.entry_type.to_string().to_lowercase();
+    let mut fields: Vec<(String, String)> = entry
+        .fields
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.format_verbatim()))
+        .collect();
+    fields.sort();
+    for (name, value) in fields {
+        contents.push_str(&format!("\n{}={}", name, value));
+    }
+    let hash = contents.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+impl RecordedDecisions {
+    /// Parse the contents of a decisions file
+    pub fn parse(src: &str) -> Result<Self, String> {
+        if src.trim().is_empty() {
+            return Ok(RecordedDecisions::default());
+        }
+        let items: Vec<Value> = serde_json::from_str(src).map_err(|e| e.to_string())?;
+        let mut decisions = vec![];
+        for (idx, item) in items.iter().enumerate() {
+            let error = |message: &str| format!("Decision {}: {}", idx + 1, message);
+            let recorded_entry = |name: &str| -> Result<RecordedEntry, String> {
+                let fingerprint = item[name]["fingerprint"]
+                    .as_str()
+                    .ok_or_else(|| error(&format!("\"{}\" has no fingerprint", name)))?;
+                Ok(RecordedEntry {
+                    fingerprint: fingerprint.to_string(),
+                    key: item[name]["key"].as_str().unwrap_or_default().to_string(),
+                })
+            };
+            let keep = match item["keep"].as_str() {
+                Some("first") => Keep::First,
+                Some("second") => Keep::Second,
+                Some("both") => Keep::Both,
+                _ => return Err(error("\"keep\" must be \"first\", \"second\" or \"both\"")),
+            };
+            decisions.push(RecordedDecision {
+                first: recorded_entry("first")?,
+                second: recorded_entry("second")?,
+                keep,
+            });
+        }
+        Ok(RecordedDecisions { decisions })
+    }
+
+    /// Serialize the decisions as JSON (the contents of a decisions file)
+    pub fn to_json(&self) -> String {
+        let items: Vec<Value> = self
+            .decisions
+            .iter()
+            .map(|decision| {
+                let keep = match decision.keep {
+                    Keep::First => "first",
+                    Keep::Second => "second",
+                    Keep::Both => "both",
+                };
+                json!({
+                    "first": {
+                        "fingerprint": decision.first.fingerprint,
+                        "key": decision.first.key,
+                    },
+                    "second": {
+                        "fingerprint": decision.second.fingerprint,
+                        "key": decision.second.key,
+                    },
+                    "keep": keep,
+                })
+            })
+            .collect();
+        let mut json = serde_json::to_string_pretty(&items).unwrap_or_else(|_| String::from("[]"));
+        json.push('\n');
+        json
+    }
+
+    /// The decision recorded for two entries, in either order (None if there is none)
+    pub fn lookup(&self, prev_entry: &Entry, entry: &Entry) -> Option<ComparisonResult> {
+        self.lookup_fingerprints(&entry_fingerprint(prev_entry), &entry_fingerprint(entry))
+    }
+
+    /// Same as `lookup`, for the fingerprints of the entries
+    pub fn lookup_fingerprints(
+        &self,
+        prev_fingerprint: &str,
+        entry_fingerprint: &str,
+    ) -> Option<ComparisonResult> {
+        self.decisions.iter().find_map(|decision| {
+            let (first, second) = (&decision.first.fingerprint, &decision.second.fingerprint);
+            // If the entries are in the opposite order, so is the decision
+            let swapped = if first == prev_fingerprint && second == entry_fingerprint {
+                false
+            } else if first == entry_fingerprint && second == prev_fingerprint {
+                true
+            } else {
+                return None;
+            };
+            Some(match (decision.keep, swapped) {
+                (Keep::Both, _) => ComparisonResult::KeepBoth,
+                (Keep::First, false) | (Keep::Second, true) => ComparisonResult::KeepPrev,
+                (Keep::Second, false) | (Keep::First, true) => ComparisonResult::KeepEntry,
+            })
+        })
+    }
+
+    /// Record the decision made about a match, replacing any previous one about the same entries
+    pub fn record(&mut self, entry_match: &MatchReport) {
+        let (prev, entry) = (
+            &entry_match.prev_fingerprint,
+            &entry_match.entry_fingerprint,
+        );
+        self.decisions.retain(|decision| {
+            let fingerprints = (&decision.first.fingerprint, &decision.second.fingerprint);
+            fingerprints != (prev, entry) && fingerprints != (entry, prev)
+        });
+        let keep = match entry_match.result {
+            ComparisonResult::KeepPrev => Keep::First,
+            ComparisonResult::KeepEntry => Keep::Second,
+            ComparisonResult::KeepBoth => Keep::Both,
+        };
+        self.decisions.push(RecordedDecision {
+            first: RecordedEntry {
+                fingerprint: prev.clone(),
+                key: entry_match.prev_key.clone(),
+            },
+            second: RecordedEntry {
+                fingerprint: entry.clone(),
+                key: entry_match.entry_key.clone(),
+            },
+            keep,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biblatex::Bibliography;
+
+    #[test]
+    fn test_entry_fingerprint() {
+        let bibliography = Bibliography::parse(
+            "@book{carnap1942, title = {Introduction to Semantics}, year = {1942}}
+            @book{other_key, year = {1942}, title = {Introduction to Semantics}}
+            @book{carnap1942b, title = {An Introduction to Semantics}, year = {1942}}",
+        )
+        .unwrap();
+        let fingerprints: Vec<String> = bibliography.iter().map(entry_fingerprint).collect();
+        // The key does not matter, but the contents do
+        assert_eq!(fingerprints[0], fingerprints[1]);
+        assert_ne!(fingerprints[0], fingerprints[2]);
+        assert_eq!(fingerprints[0].len(), 16);
+    }
+
+    #[test]
+    fn test_recorded_decisions() {
+        let src = r#"[
+            {
+                "first": {"fingerprint": "aaaa", "key": "Prior1960"},
+                "second": {"fingerprint": "bbbb", "key": "Prior1961"},
+                "keep": "second"
+            },
+            {
+                "first": {"fingerprint": "cccc"},
+                "second": {"fingerprint": "dddd"},
+                "keep": "both"
+            }
+        ]"#;
+        let mut decisions = RecordedDecisions::parse(src).unwrap();
+        assert_eq!(decisions.decisions.len(), 2);
+        assert_eq!(
+            decisions.lookup_fingerprints("aaaa", "bbbb"),
+            Some(ComparisonResult::KeepEntry)
+        );
+        // The same decision applies when the entries are found in the opposite order
+        assert_eq!(
+            decisions.lookup_fingerprints("bbbb", "aaaa"),
+            Some(ComparisonResult::KeepPrev)
+        );
+        assert_eq!(
+            decisions.lookup_fingerprints("dddd", "cccc"),
+            Some(ComparisonResult::KeepBoth)
+        );
+        assert_eq!(decisions.lookup_fingerprints("aaaa", "cccc"), None);
+
+        // A new decision about the same entries replaces the old one
+        decisions.record(&MatchReport {
+            prev_key: String::from("Prior1961"),
+            prev_source: 0,
+            prev_fingerprint: String::from("bbbb"),
+            entry_key: String::from("Prior1960"),
+            entry_source: 1,
+            entry_fingerprint: String::from("aaaa"),
+            criterion: crate::MatchCriterion::Title,
+            similarity: Some(1.0),
+            score: None,
+            result: ComparisonResult::KeepPrev,
+            decided_by: crate::Decider::User,
+            kept: Some(String::from("Prior1961")),
+        });
+        assert_eq!(decisions.decisions.len(), 2);
+        assert_eq!(
+            decisions.lookup_fingerprints("aaaa", "bbbb"),
+            Some(ComparisonResult::KeepEntry)
+        );
+        assert_eq!(
+            RecordedDecisions::parse(&decisions.to_json()).unwrap(),
+            decisions
+        );
+
+        let error = RecordedDecisions::parse(r#"[{"first": {}, "keep": "first"}]"#).unwrap_err();
+        assert_eq!(error, "Decision 1: \"first\" has no fingerprint");
+        assert!(RecordedDecisions::parse("").unwrap().decisions.is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use biblatex::BibliographyError;
+
+use super::{delimiter_depths, split_raw_blocks};
+
+/// Fields that hold a list of names (and thus, have to follow the "Last, First and ..." format)
+const NAME_FIELDS: [&str; 8] = [
+    "author",
+    "editor",
+    "editora",
+    "editorb",
+    "editorc",
+    "translator",
+    "bookauthor",
+    "holder",
+];
+
+/// A record of a file in another format (e.g. RIS) that could not be converted into an entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordError {
+    /// Line (starting from 1) where the problem was found
+    pub line: usize,
+    /// Raw text of the record
+    pub text: String,
+    /// What went wrong
+    pub message: String,
+}
+
+/// What went wrong when parsing a .bib file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// An error reported by the biblatex parser
+    Bibliography(BibliographyError),
+    /// An entry whose opening brace is never closed
+    UnclosedEntry,
+    /// A record of a file in another format that could not be read
+    InvalidRecord(RecordError),
+}
+
+/// An error found while parsing a .bib file, with the location where it happened
+///
+/// Its `Display` implementation shows the file, line and column, the offending line with the
+/// problematic part underlined, and (when possible) a hint about the likely cause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// What went wrong
+    pub kind: ParseErrorKind,
+    /// File where the error was found
+    pub filepath: PathBuf,
+    /// Line of the error, starting from 1
+    pub line: usize,
+    /// Column of the error (in characters), starting from 1
+    pub column: usize,
+    /// The line of the file where the error was found
+    pub snippet: String,
+    /// How many characters (starting from `column`) to underline in the snippet
+    pub underline: usize,
+    /// Likely cause of the error, if it could be guessed
+    pub hint: Option<String>,
+}
+
+impl ParseError {
+    /// Build a ParseError, looking for the location of the error in the source of the file
+    pub fn new(kind: ParseErrorKind, filepath: PathBuf, src: &str) -> Self {
+        let (start, end, hint) = match &kind {
+            ParseErrorKind::Bibliography(BibliographyError::MalformedField(key, field)) => {
+                locate_field(src, key, field)
+            }
+            ParseErrorKind::Bibliography(BibliographyError::DuplicateKey(key)) => {
+                locate_duplicate_key(src, key)
+            }
+            ParseErrorKind::Bibliography(_) => (0, 0, None),
+            ParseErrorKind::UnclosedEntry => locate_unclosed_entry(src),
+            ParseErrorKind::InvalidRecord(record_error) => locate_line(src, record_error.line),
+        };
+
+        let line_start = src[..start].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = src[start..]
+            .find('\n')
+            .map(|idx| start + idx)
+            .unwrap_or(src.len());
+        // Only underline up to the end of the line
+        let end = end.clamp(start, line_end);
+        ParseError {
+            kind,
+            filepath,
+            line: src[..start].matches('\n').count() + 1,
+            column: src[line_start..start].chars().count() + 1,
+            snippet: src[line_start..line_end].trim_end().to_string(),
+            underline: src[start..end].chars().count().max(1),
+            hint,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match &self.kind {
+            ParseErrorKind::Bibliography(e) => e.to_string(),
+            ParseErrorKind::UnclosedEntry => String::from("Entry is never closed"),
+            ParseErrorKind::InvalidRecord(record_error) => record_error.message.clone(),
+        };
+        let line_number = self.line.to_string();
+        let margin = " ".repeat(line_number.len());
+        writeln!(f, "{}", message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            margin,
+            self.filepath.display(),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{} |", margin)?;
+        writeln!(f, "{} | {}", line_number, self.snippet)?;
+        write!(
+            f,
+            "{} | {}{}",
+            margin,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.underline)
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", margin, hint)?;
+        }
+        Ok(())
+    }
+}
+
+// The BibliographyError (if any) is not exposed as source, since its message is already part of
+// the one displayed (and would otherwise be printed twice)
+impl Error for ParseError {}
+
+/// Byte offsets (start, end) of the blocks of a file with the given key, in order
+fn find_entries(src: &str, key: &str) -> Vec<(usize, usize)> {
+    split_raw_blocks(src)
+        .into_iter()
+        .filter(|block| {
+            let key_start = block.text.find(&['{', '('][..]).map(|idx| idx + 1);
+            let key_end = block.text.find(',');
+            match (key_start, key_end) {
+                (Some(key_start), Some(key_end)) if key_start <= key_end => {
+                    block.text[key_start..key_end].trim() == key
+                }
+                _ => false,
+            }
+        })
+        .map(|block| {
+            // Blocks borrow from src, so their offset can be recovered from the pointers
+            let start = block.text.as_ptr() as usize - src.as_ptr() as usize;
+            (start, start + block.text.len())
+        })
+        .collect()
+}
+
+/// Locate the value of a field of an entry, and guess why it might be malformed
+fn locate_field(src: &str, key: &str, field: &str) -> (usize, usize, Option<String>) {
+    let (entry_start, entry_end) = match find_entries(src, key).first() {
+        Some(&offsets) => offsets,
+        None => return (0, 0, None),
+    };
+    let entry = &src[entry_start..entry_end];
+    let lowercase_entry = entry.to_ascii_lowercase();
+
+    // Look for the field name, followed by =
+    let mut search_from = 0;
+    while let Some(idx) = lowercase_entry[search_from..].find(field) {
+        let name_start = search_from + idx;
+        let name_end = name_start + field.len();
+        search_from = name_end;
+        let preceded_ok = match lowercase_entry[..name_start].chars().last() {
+            Some(c) => c.is_whitespace() || c == ',',
+            None => true,
+        };
+        let rest = entry[name_end..].trim_start();
+        if !preceded_ok || !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = entry.len() - rest[1..].trim_start().len();
+        let value_end = value_start + field_value_len(&entry[value_start..]);
+        let value = &entry[value_start..value_end];
+        let hint = guess_field_problem(field, value);
+        return (entry_start + value_start, entry_start + value_end, Some(hint));
+    }
+    (entry_start, entry_start + 1, None)
+}
+
+/// Length of a field value, i.e. up to the first comma or closing brace outside of braces
+/// (counted as BibTeX does, see `delimiter_depths`)
+fn field_value_len(value: &str) -> usize {
+    delimiter_depths(value, '{', '}')
+        .find(|&(_, c, depth)| depth == 0 && (c == ',' || c == '}'))
+        .map_or(value.len(), |(idx, _, _)| idx)
+}
+
+/// Guess why the value of a field could not be parsed
+fn guess_field_problem(field: &str, value: &str) -> String {
+    // As in BibTeX, a backslash does not escape a brace
+    if value.matches('{').count() != value.matches('}').count() {
+        return String::from("The braces in this field are unbalanced");
+    }
+
+    // If a line of the value looks like the beginning of another field, the comma is missing
+    let missing_comma = value.lines().skip(1).any(|line| {
+        let line = line.trim_start();
+        let name_len = line
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(line.len());
+        name_len > 0 && line[name_len..].trim_start().starts_with('=')
+    });
+    if missing_comma {
+        return String::from("There seems to be a missing comma after this field");
+    }
+
+    if NAME_FIELDS.contains(&field) {
+        return String::from(
+            "Names must be separated by \"and\", and LaTeX commands inside them must be well \
+            formed (e.g. {\\'i} instead of {\\'{\\i}})",
+        );
+    }
+    String::from(
+        "Values must be enclosed in braces or quotes, or be numbers or previously defined @string's",
+    )
+}
+
+/// Locate the second entry with a repeated key
+fn locate_duplicate_key(src: &str, key: &str) -> (usize, usize, Option<String>) {
+    let entries = find_entries(src, key);
+    match (entries.first(), entries.get(1)) {
+        (Some(&(first_start, _)), Some(&(second_start, _))) => {
+            let first_line = src[..first_start].matches('\n').count() + 1;
+            let hint = format!(
+                "The key was already used by the entry in line {}. Keys must be unique within a file",
+                first_line
+            );
+            let header_end = src[second_start..]
+                .find(',')
+                .map_or(second_start + 1, |idx| second_start + idx);
+            (second_start, header_end, Some(hint))
+        }
+        _ => (0, 0, None),
+    }
+}
+
+/// Locate a whole line of the file (given starting from 1)
+fn locate_line(src: &str, line: usize) -> (usize, usize, Option<String>) {
+    let start: usize = src
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let end = src[start..]
+        .find('\n')
+        .map_or(src.len(), |idx| start + idx);
+    (start, src[start..end].trim_end().len() + start, None)
+}
+
+/// Locate the first entry of the file that is never closed
+fn locate_unclosed_entry(src: &str) -> (usize, usize, Option<String>) {
+    match split_raw_blocks(src).into_iter().find(|block| !block.closed) {
+        Some(block) => {
+            let start = block.text.as_ptr() as usize - src.as_ptr() as usize;
+            let header_end = block.text.find(',').unwrap_or(1);
+            let hint = String::from(
+                "Some opening brace in this entry is never closed (or there is one closing brace less than needed)",
+            );
+            (start, start + header_end, Some(hint))
+        }
+        None => (0, 0, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_malformed_name_list() {
+        let src = fs::read_to_string("bib_files/incorrect.bib").unwrap();
+        let error = ParseError::new(
+            ParseErrorKind::Bibliography(BibliographyError::MalformedField(
+                "conigliocorbalan".into(),
+                "author".into(),
+            )),
+            PathBuf::from("bib_files/incorrect.bib"),
+            &src,
+        );
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, 15);
+        assert_eq!(
+            error.underline,
+            "{Marcelo Coniglio and Mar{\\'{\\i}}a I. Corbal{\\'{a}}n}".len()
+        );
+        assert!(error.hint.as_ref().unwrap().starts_with("Names must be"));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Key \"conigliocorbalan\" contains malformed field \"author\"\n \
+                --> bib_files/incorrect.bib:3:15\n  |\n3 | {}\n  | {}{}\n  = hint: {}",
+                error.snippet,
+                " ".repeat(14),
+                "^".repeat(error.underline),
+                error.hint.as_ref().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_missing_comma() {
+        let src = "@book{key,\n  title = {A title}\n  year = {2000},\n}\n";
+        let error = ParseError::new(
+            ParseErrorKind::Bibliography(BibliographyError::MalformedField(
+                "key".into(),
+                "title".into(),
+            )),
+            PathBuf::from("missing_comma.bib"),
+            src,
+        );
+        assert_eq!((error.line, error.column), (2, 11));
+        assert_eq!(
+            error.hint.unwrap(),
+            "There seems to be a missing comma after this field"
+        );
+
+        // As in BibTeX, the backslash does not escape the brace that closes the value
+        let src = "@misc{key,\n  note = {In C:\\}\n  year = {2000},\n}\n";
+        let error = ParseError::new(
+            ParseErrorKind::Bibliography(BibliographyError::MalformedField(
+                "key".into(),
+                "note".into(),
+            )),
+            PathBuf::from("missing_comma.bib"),
+            src,
+        );
+        assert_eq!((error.line, error.column), (2, 10));
+        assert_eq!(error.underline, "{In C:\\}".len());
+        assert_eq!(
+            error.hint.unwrap(),
+            "There seems to be a missing comma after this field"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_key() {
+        let src = "@book{key,\n  title = {A}\n}\n\n@book{key,\n  title = {B}\n}\n";
+        let error = ParseError::new(
+            ParseErrorKind::Bibliography(BibliographyError::DuplicateKey("key".into())),
+            PathBuf::from("duplicate.bib"),
+            src,
+        );
+        assert_eq!((error.line, error.column, error.underline), (5, 1, 9));
+        assert!(error.hint.unwrap().contains("line 1"));
+    }
+
+    #[test]
+    fn test_invalid_record() {
+        let src = "TY  - JOUR\nTI  - A title\nXX  - outside\n";
+        let error = ParseError::new(
+            ParseErrorKind::InvalidRecord(RecordError {
+                line: 3,
+                text: String::from("XX  - outside"),
+                message: String::from("Tag XX outside of a record"),
+            }),
+            PathBuf::from("file.ris"),
+            src,
+        );
+        assert_eq!((error.line, error.column, error.underline), (3, 1, 13));
+        assert!(error.to_string().starts_with("Tag XX outside of a record\n"));
+    }
+
+    #[test]
+    fn test_unclosed_entry() {
+        let src = "@book{ok,\n  title = {A}\n}\n@book{key,\n  title = {A {title}\n}\n";
+        let error = ParseError::new(
+            ParseErrorKind::UnclosedEntry,
+            PathBuf::from("unclosed.bib"),
+            src,
+        );
+        assert_eq!((error.line, error.column), (4, 1));
+        assert!(error.hint.is_some());
+    }
+}
@@ -0,0 +1,165 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+/// Encoding assumed for files that are not valid UTF-8 and do not declare any encoding
+///
+/// Windows-1252 is a superset of Latin-1 (ISO-8859-1), which is what most old reference managers
+/// used, so this reads both correctly.
+pub const FALLBACK_ENCODING: &Encoding = WINDOWS_1252;
+
+/// Get an encoding from its name (e.g. "utf-8", "latin1" or "windows-1252")
+///
+/// Accepts any of the labels defined in the [WHATWG Encoding Standard](https://encoding.spec.whatwg.org/#names-and-labels).
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label_no_replacement(label.trim().as_bytes())
+        .ok_or_else(|| format!("Unknown encoding \"{}\"", label))
+}
+
+/// Same as `parse_encoding`, but only for encodings that can be used to write the output
+pub fn parse_output_encoding(label: &str) -> Result<&'static Encoding, String> {
+    let encoding = parse_encoding(label)?;
+    // Encoding to UTF-16 is not supported by encoding_rs
+    if encoding.output_encoding() != encoding {
+        return Err(format!(
+            "The output cannot be written in {} (try utf-8 instead)",
+            encoding.name()
+        ));
+    }
+    Ok(encoding)
+}
+
+/// Decode the contents of a file into a String, returning the encoding that was used
+///
+/// The encoding is chosen in the following order:
+///
+/// - A byte order mark at the beginning of the file (which is removed)
+/// - An "% Encoding: ..." line at the beginning of the file, like the ones JabRef writes
+/// - The encoding given as argument, if any
+/// - UTF-8, if the file is valid UTF-8
+/// - `FALLBACK_ENCODING` otherwise
+pub fn decode_file(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> (String, &'static Encoding) {
+    let (encoding, bytes) = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, bom_length)) => (bom_encoding, &bytes[bom_length..]),
+        None => match declared_encoding(bytes).or(encoding) {
+            Some(encoding) => (encoding, bytes),
+            None if std::str::from_utf8(bytes).is_ok() => (UTF_8, bytes),
+            None => (FALLBACK_ENCODING, bytes),
+        },
+    };
+    let (decoded, _) = encoding.decode_without_bom_handling(bytes);
+    (decoded.into_owned(), encoding)
+}
+
+/// Look for an encoding declared in the comments at the beginning of a file
+/// (e.g. "% Encoding: windows-1252")
+fn declared_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    // The declaration has to be in ASCII for us to be able to read it anyway
+    let header = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    header
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with('%'))
+        .filter_map(|line| {
+            let comment = line.trim_start_matches('%').trim();
+            let (name, value) = comment.split_once(':')?;
+            match name.trim().eq_ignore_ascii_case("encoding") {
+                true => parse_encoding(value).ok(),
+                false => None,
+            }
+        })
+        .next()
+}
+
+/// Encode the unified bibliography for writing it to the output
+///
+/// Returns an error listing the characters that cannot be represented in the encoding.
+pub fn encode_output(contents: &str, encoding: &'static Encoding) -> Result<Vec<u8>, String> {
+    let mut unmappable: Vec<char> = contents
+        .chars()
+        .filter(|c| {
+            let mut buffer = [0; 4];
+            let (_, _, had_errors) = encoding.encode(c.encode_utf8(&mut buffer));
+            had_errors
+        })
+        .collect();
+    if !unmappable.is_empty() {
+        unmappable.sort_unstable();
+        unmappable.dedup();
+        return Err(format!(
+            "The following characters cannot be written in {}: {}",
+            encoding.name(),
+            unmappable.into_iter().collect::<String>()
+        ));
+    }
+    let (encoded, _, _) = encoding.encode(contents);
+    Ok(encoded.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::UTF_16LE;
+    use std::fs;
+
+    #[test]
+    fn test_decode_file() {
+        let bytes = fs::read("bib_files/encoding_files/windows1252.bib").unwrap();
+        assert!(String::from_utf8(bytes.clone()).is_err());
+        let (decoded, encoding) = decode_file(&bytes, None);
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(decoded.contains("Untersuchung über den Begriff"));
+
+        let bytes = fs::read("bib_files/encoding_files/declared_latin1.bib").unwrap();
+        let (decoded, encoding) = decode_file(&bytes, None);
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(decoded.contains("Bréslau"));
+
+        // The BOM is removed
+        let bytes = fs::read("bib_files/encoding_files/utf16_bom.bib").unwrap();
+        let (decoded, encoding) = decode_file(&bytes, None);
+        assert_eq!(encoding, UTF_16LE);
+        assert!(decoded.starts_with("@book{Frege1884utf16,"));
+
+        let bytes = fs::read("bib_files/encoding_files/utf8_bom.bib").unwrap();
+        let (decoded, encoding) = decode_file(&bytes, Some(WINDOWS_1252));
+        assert_eq!(encoding, UTF_8);
+        assert!(decoded.starts_with("@book{Frege1884utf8,"));
+
+        // An explicit encoding overrides the detection
+        let bytes = "über".as_bytes();
+        assert_eq!(decode_file(bytes, None).0, "über");
+        assert_eq!(decode_file(bytes, Some(WINDOWS_1252)).0, "Ã¼ber");
+
+        // But not the encoding declared by each file
+        let bytes = fs::read("bib_files/encoding_files/declared_latin1.bib").unwrap();
+        let (decoded, encoding) = decode_file(&bytes, Some(UTF_8));
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(decoded.contains("Bréslau"));
+        let bytes = fs::read("bib_files/encoding_files/declared_utf8.bib").unwrap();
+        let (decoded, encoding) = decode_file(&bytes, Some(WINDOWS_1252));
+        assert_eq!(encoding, UTF_8);
+        assert!(decoded.contains("Bréslau"));
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("latin1").unwrap(), WINDOWS_1252);
+        assert_eq!(parse_encoding("UTF-8").unwrap(), UTF_8);
+        assert!(parse_encoding("klingon").is_err());
+        assert!(parse_output_encoding("utf-16le").is_err());
+    }
+
+    #[test]
+    fn test_encode_output() {
+        assert_eq!(
+            encode_output("über", WINDOWS_1252).unwrap(),
+            vec![0xfc, b'b', b'e', b'r']
+        );
+        assert_eq!(
+            encode_output("Łukasiewicz and Gödel", WINDOWS_1252).unwrap_err(),
+            "The following characters cannot be written in windows-1252: Ł"
+        );
+    }
+}
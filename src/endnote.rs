@@ -0,0 +1,326 @@
+use biblatex::{Chunk, Entry, EntryType};
+use roxmltree::{Document, Node};
+
+use super::{format_page_range, generate_citation_key, month_from_name, RecordError};
+
+/// Get the entry type corresponding to an EndNote reference type
+///
+/// The type is taken from its name (the `name` attribute of `ref-type`), or from its number if
+/// there is no name.
+pub fn entry_type_from_endnote(name: Option<&str>, number: Option<&str>) -> EntryType {
+    let name = name.map(str::to_string).unwrap_or_else(|| {
+        // Numbers of the reference types of EndNote's default settings
+        match number.unwrap_or_default().trim() {
+            "5" => "Book Section",
+            "6" => "Book",
+            "9" => "Computer Program",
+            "10" => "Conference Proceedings",
+            "12" => "Web Page",
+            "17" => "Journal Article",
+            "19" => "Magazine Article",
+            "23" => "Newspaper Article",
+            "25" => "Patent",
+            "27" => "Report",
+            "28" => "Edited Book",
+            "32" => "Thesis",
+            "34" => "Unpublished Work",
+            "36" => "Manuscript",
+            "47" => "Conference Paper",
+            _ => "Generic",
+        }
+        .to_string()
+    });
+    match name.to_lowercase().as_str() {
+        "journal article" | "magazine article" | "newspaper article" | "electronic article" => {
+            EntryType::Article
+        }
+        "book" | "electronic book" => EntryType::Book,
+        "edited book" => EntryType::Collection,
+        "book section" | "electronic book section" => EntryType::InCollection,
+        "conference proceedings" => EntryType::Proceedings,
+        "conference paper" => EntryType::InProceedings,
+        "thesis" => EntryType::PhdThesis,
+        "report" | "government document" => EntryType::TechReport,
+        "web page" => EntryType::Online,
+        "patent" => EntryType::Patent,
+        "unpublished work" | "manuscript" => EntryType::Unpublished,
+        "computer program" => EntryType::Software,
+        "dataset" => EntryType::Dataset,
+        "dictionary" | "encyclopedia" => EntryType::InReference,
+        _ => EntryType::Misc,
+    }
+}
+
+/// Parse the contents of an EndNote XML file, returning an entry (or an error) for each record
+///
+/// The citation key is taken from the label of the record, or generated from the first author and
+/// the year if there is none. PubMed identifiers are kept: the accession number is the PMID (when
+/// it is a number) and `custom2` is the PMCID (when it begins with "PMC"), as in the records EndNote
+/// imports from PubMed.
+pub fn parse_endnote_xml(src: &str) -> Vec<Result<Entry, RecordError>> {
+    let document = match Document::parse(src) {
+        Ok(document) => document,
+        Err(e) => {
+            let line = e.pos().row as usize;
+            return vec![Err(RecordError {
+                line,
+                text: src
+                    .lines()
+                    .nth(line.max(1) - 1)
+                    .unwrap_or_default()
+                    .to_string(),
+                message: format!("Invalid XML: {}", e),
+            })];
+        }
+    };
+    document
+        .descendants()
+        .filter(|node| node.has_tag_name("record"))
+        .map(|record| {
+            record_to_entry(record).ok_or_else(|| RecordError {
+                line: document.text_pos_at(record.range().start).row as usize,
+                text: src[record.range()].to_string(),
+                message: String::from("Record has no author, title or year"),
+            })
+        })
+        .collect()
+}
+
+/// The text of an element, ignoring the styles it is split into
+fn node_text(node: Node) -> Option<String> {
+    let text: Vec<&str> = node
+        .descendants()
+        .filter(|node| node.is_text())
+        .filter_map(|node| node.text())
+        .flat_map(str::split_whitespace)
+        .collect();
+    match text.is_empty() {
+        true => None,
+        false => Some(text.join(" ")),
+    }
+}
+
+/// The element found by following a path of tag names from a node
+fn find_path<'a, 'input>(node: Node<'a, 'input>, path: &[&str]) -> Option<Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, tag_name| {
+        node.children().find(|child| child.has_tag_name(*tag_name))
+    })
+}
+
+/// Convert an EndNote record into an entry
+///
+/// Returns None if the record has no author, title or year at all.
+fn record_to_entry(record: Node) -> Option<Entry> {
+    let first = |path: &[&str]| find_path(record, path).and_then(node_text);
+    // The texts of all the elements with the given tag name inside the element of the path
+    let all = |path: &[&str], tag_name: &str| -> Vec<String> {
+        find_path(record, path)
+            .map(|node| {
+                node.children()
+                    .filter(|child| child.has_tag_name(tag_name))
+                    .filter_map(node_text)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let ref_type = find_path(record, &["ref-type"]);
+    let entry_type = entry_type_from_endnote(
+        ref_type.and_then(|ref_type| ref_type.attribute("name")),
+        ref_type.and_then(|ref_type| ref_type.text()),
+    );
+    let mut entry = Entry::new(String::new(), entry_type.clone());
+    let mut set = |field: &str, value: String| entry.set(field, vec![Chunk::Normal(value)]);
+
+    for (path, field) in [
+        ("authors", "author"),
+        ("secondary-authors", "editor"),
+        ("translated-authors", "translator"),
+    ] {
+        let names = all(&["contributors", path], "author");
+        if !names.is_empty() {
+            set(field, names.join(" and "));
+        }
+    }
+    if let Some(title) = first(&["titles", "title"]) {
+        set("title", title);
+    }
+
+    // The secondary title is the journal of an article, the book of a chapter, and the series of
+    // a book
+    let secondary_title = first(&["titles", "secondary-title"]);
+    let tertiary_title = first(&["titles", "tertiary-title"]);
+    match entry_type {
+        EntryType::Article => {
+            if let Some(journal) = first(&["periodical", "full-title"]).or(secondary_title) {
+                set("journal", journal);
+            }
+        }
+        EntryType::InCollection | EntryType::InProceedings | EntryType::InReference => {
+            if let Some(book_title) = secondary_title {
+                set("booktitle", book_title);
+            }
+            if let Some(series) = tertiary_title {
+                set("series", series);
+            }
+        }
+        _ => {
+            if let Some(series) = secondary_title.or(tertiary_title) {
+                set("series", series);
+            }
+        }
+    }
+
+    let year = first(&["dates", "year"]);
+    // The publication date is written as e.g. "Apr 25" or "December"
+    let pub_date = first(&["dates", "pub-dates", "date"]).unwrap_or_default();
+    let mut pub_date_parts = pub_date.split_whitespace();
+    let month = pub_date_parts.next().and_then(month_from_name);
+    let day = pub_date_parts.next().and_then(|day| day.parse::<u8>().ok());
+    match (year, month, day) {
+        (Some(year), Some(month), Some(day)) => {
+            set("date", format!("{}-{:02}-{:02}", year, month, day))
+        }
+        (Some(year), month, _) => {
+            set("year", year);
+            if let Some(month) = month {
+                set("month", month.to_string());
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(pages) = first(&["pages"]) {
+        set("pages", format_page_range(&pages));
+    }
+    for (path, field) in [
+        ("volume", "volume"),
+        ("number", "number"),
+        ("edition", "edition"),
+        ("pub-location", "address"),
+        ("language", "language"),
+        ("abstract", "abstract"),
+        ("notes", "note"),
+    ] {
+        if let Some(value) = first(&[path]) {
+            set(field, value);
+        }
+    }
+    if let Some(publisher) = first(&["publisher"]) {
+        match entry_type {
+            EntryType::PhdThesis => set("school", publisher),
+            EntryType::TechReport => set("institution", publisher),
+            _ => set("publisher", publisher),
+        }
+    }
+    if let Some(serial_number) = first(&["isbn"]) {
+        // The isbn element holds the ISSN of periodicals, followed by e.g. " (Print)"
+        let serial_number = serial_number
+            .split(" (")
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        match entry_type {
+            EntryType::Article => set("issn", serial_number),
+            _ => set("isbn", serial_number),
+        }
+    }
+    if let Some(doi) = first(&["electronic-resource-num"]) {
+        set("doi", doi);
+    }
+    if let Some(pmid) = first(&["accession-num"])
+        .map(|pmid| pmid.trim_start_matches("PMID:").trim().to_string())
+        .filter(|pmid| pmid.chars().all(|c| c.is_ascii_digit()))
+    {
+        set("pmid", pmid);
+    }
+    if let Some(pmcid) = first(&["custom2"]).filter(|pmcid| pmcid.starts_with("PMC")) {
+        set("pmcid", pmcid);
+    }
+    if let Some(url) = first(&["urls", "related-urls", "url"]) {
+        set("url", url);
+    }
+    let keywords = all(&["keywords"], "keyword");
+    if !keywords.is_empty() {
+        set("keywords", keywords.join(", "));
+    }
+
+    if entry.get("author").is_none()
+        && entry.get("title").is_none()
+        && entry.get("year").is_none()
+        && entry.get("date").is_none()
+    {
+        return None;
+    }
+    entry.key = match first(&["label"]) {
+        Some(label) if !label.contains(char::is_whitespace) => label,
+        _ => generate_citation_key(&entry),
+    };
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biblatex::ChunksExt;
+    use std::fs;
+
+    #[test]
+    fn test_parse_endnote_xml() {
+        let src = fs::read_to_string("bib_files/endnote_files/library.xml").unwrap();
+        let entries = parse_endnote_xml(&src);
+        assert_eq!(entries.len(), 3);
+
+        let article = entries[0].as_ref().unwrap();
+        assert_eq!(article.key, "Watson1953");
+        assert_eq!(article.entry_type, EntryType::Article);
+        assert_eq!(article.author().unwrap().len(), 2);
+        assert_eq!(article.journal().unwrap().format_verbatim(), "Nature");
+        assert_eq!(article.get("date").unwrap().format_verbatim(), "1953-04-25");
+        assert_eq!(article.get("pages").unwrap().format_verbatim(), "737--738");
+        assert_eq!(article.get("issn").unwrap().format_verbatim(), "0028-0836");
+        assert_eq!(article.get("pmid").unwrap().format_verbatim(), "13054692");
+        assert_eq!(article.doi().unwrap(), "10.1038/171737a0");
+        assert_eq!(
+            article.get("keywords").unwrap().format_verbatim(),
+            "DNA, Nucleic Acids"
+        );
+
+        let chapter = entries[1].as_ref().unwrap();
+        assert_eq!(chapter.key, "BPS2018-WIAPL");
+        assert_eq!(chapter.entry_type, EntryType::InCollection);
+        assert_eq!(chapter.editors()[0].0.len(), 2);
+        assert_eq!(
+            chapter.book_title().unwrap().format_verbatim(),
+            "Between Consistency and Inconsistency"
+        );
+        assert_eq!(
+            chapter.get("series").unwrap().format_verbatim(),
+            "Trends in Logic"
+        );
+        assert_eq!(
+            chapter.get("address").unwrap().format_verbatim(),
+            "Dordrecht"
+        );
+
+        let error = entries[2].as_ref().unwrap_err();
+        assert_eq!(error.line, 62);
+        assert!(error.text.contains("PMC6193465"));
+
+        let error = parse_endnote_xml("<xml><records>\n<record></records>")
+            .remove(0)
+            .unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.message.starts_with("Invalid XML"));
+    }
+
+    #[test]
+    fn test_entry_type_from_endnote() {
+        assert_eq!(
+            entry_type_from_endnote(Some("Conference Paper"), Some("47")),
+            EntryType::InProceedings
+        );
+        assert_eq!(entry_type_from_endnote(None, Some("6")), EntryType::Book);
+        assert_eq!(entry_type_from_endnote(None, None), EntryType::Misc);
+    }
+}
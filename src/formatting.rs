@@ -0,0 +1,417 @@
+use biblatex::{Bibliography, Entry};
+use clap::{ArgEnum, Args};
+
+use super::delimiter_depths;
+
+/// Order of the fields of each entry in the .bib output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum FieldOrder {
+    /// Alphabetical order of the field names
+    Alphabetical,
+    /// The fields in `CANONICAL_FIELD_ORDER` first (author, title, ...), then the rest
+    /// alphabetically
+    Canonical,
+}
+
+/// Delimiters of the field values in the .bib output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Delimiters {
+    /// `title = {A title}`
+    Braces,
+    /// `title = "A title"`
+    Quotes,
+}
+
+/// Case of the entry types or field names in the .bib output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum NameCase {
+    /// As they are read (entry types are always read in lowercase)
+    Keep,
+    Lower,
+    Upper,
+}
+
+/// Whether fields are written with their BibTeX or their BibLaTeX names (e.g. `journal` or
+/// `journaltitle`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BibDialect {
+    Bibtex,
+    Biblatex,
+}
+
+/// Order of the fields with `FieldOrder::Canonical` (fields not listed go after these)
+pub const CANONICAL_FIELD_ORDER: [&str; 42] = [
+    "author",
+    "editor",
+    "translator",
+    "title",
+    "subtitle",
+    "booktitle",
+    "journal",
+    "journaltitle",
+    "series",
+    "edition",
+    "volume",
+    "number",
+    "issue",
+    "chapter",
+    "pages",
+    "pagetotal",
+    "year",
+    "month",
+    "day",
+    "date",
+    "publisher",
+    "organization",
+    "institution",
+    "school",
+    "address",
+    "location",
+    "type",
+    "isbn",
+    "issn",
+    "doi",
+    "pmid",
+    "pmcid",
+    "eprint",
+    "eprinttype",
+    "url",
+    "urldate",
+    "crossref",
+    "xdata",
+    "language",
+    "keywords",
+    "note",
+    "abstract",
+];
+
+/// Layout of the .bib output (the default is the layout of the biblatex crate: fields in
+/// alphabetical order, not indented, in braces and followed by a comma)
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct FormatOptions {
+    /// Order of the fields of each entry
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "ORDER",
+        default_value_t = FieldOrder::Alphabetical,
+        help = "Order of the fields of each entry in .bib output (canonical puts author, title, etc. first)",
+        display_order = 25
+    )]
+    pub field_order: FieldOrder,
+
+    /// Number of spaces before each field
+    #[clap(
+        long,
+        value_name = "WIDTH",
+        default_value_t = 0,
+        help = "Number of spaces to indent the fields with in .bib output",
+        display_order = 26
+    )]
+    pub indent: usize,
+
+    /// If true, the field names of each entry are padded so that the = signs are aligned
+    #[clap(
+        long,
+        help = "If present, the = signs of the fields of each entry are aligned in .bib output",
+        display_order = 27
+    )]
+    pub align: bool,
+
+    /// Delimiters of the field values
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "DELIMITERS",
+        default_value_t = Delimiters::Braces,
+        help = "Delimiters of the field values in .bib output",
+        display_order = 28
+    )]
+    pub delimiters: Delimiters,
+
+    /// Case of the entry types (e.g. `@article` or `@ARTICLE`)
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "CASE",
+        default_value_t = NameCase::Keep,
+        help = "Case of the entry types in .bib output",
+        display_order = 29
+    )]
+    pub entry_type_case: NameCase,
+
+    /// Case of the field names (e.g. `author` or `AUTHOR`)
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "CASE",
+        default_value_t = NameCase::Keep,
+        help = "Case of the field names in .bib output",
+        display_order = 30
+    )]
+    pub field_case: NameCase,
+
+    /// If true, the last field of each entry is not followed by a comma
+    #[clap(
+        long,
+        help = "If present, the last field of each entry is not followed by a comma in .bib output",
+        display_order = 31
+    )]
+    pub no_trailing_comma: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            field_order: FieldOrder::Alphabetical,
+            indent: 0,
+            align: false,
+            delimiters: Delimiters::Braces,
+            entry_type_case: NameCase::Keep,
+            field_case: NameCase::Keep,
+            no_trailing_comma: false,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Serialize a bibliography, with a blank line between entries
+    ///
+    /// Field values equal to one of the `abbreviations` (pairs of macro name and value, as
+    /// returned by `BibMetadata::expanded_strings`) are written as the name of the macro.
+    pub fn format_bibliography(
+        &self,
+        bibliography: &Bibliography,
+        dialect: BibDialect,
+        abbreviations: &[(String, String)],
+    ) -> String {
+        bibliography
+            .iter()
+            .map(|entry| format!("{}\n", self.format_entry(entry, dialect, abbreviations)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Serialize a single entry (see `format_bibliography`)
+    pub fn format_entry(
+        &self,
+        entry: &Entry,
+        dialect: BibDialect,
+        abbreviations: &[(String, String)],
+    ) -> String {
+        let (entry_type, mut fields) = serialized_fields(entry, dialect);
+        if self.field_order == FieldOrder::Canonical {
+            // The sort is stable, so the fields that are not listed stay in alphabetical order
+            fields.sort_by_key(|(name, _)| {
+                CANONICAL_FIELD_ORDER
+                    .iter()
+                    .position(|field| field.eq_ignore_ascii_case(name))
+                    .unwrap_or(CANONICAL_FIELD_ORDER.len())
+            });
+        }
+        let fields: Vec<(String, String)> = fields
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match abbreviations
+                    .iter()
+                    .find(|(_, expanded)| *expanded == value)
+                {
+                    Some((macro_name, _)) => macro_name.clone(),
+                    None if self.delimiters == Delimiters::Quotes => braces_to_quotes(&value),
+                    None => value,
+                };
+                (apply_case(&name, self.field_case), value)
+            })
+            .collect();
+
+        let width = match self.align {
+            true => fields
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .max()
+                .unwrap_or(0),
+            false => 0,
+        };
+        let mut formatted = format!(
+            "@{}{{{},\n",
+            apply_case(&entry_type, self.entry_type_case),
+            entry.key
+        );
+        for (idx, (name, value)) in fields.iter().enumerate() {
+            let comma = match self.no_trailing_comma && idx + 1 == fields.len() {
+                true => "",
+                false => ",",
+            };
+            formatted.push_str(&format!(
+                "{}{:width$} = {}{}\n",
+                " ".repeat(self.indent),
+                name,
+                value,
+                comma,
+                width = width
+            ));
+        }
+        formatted.push('}');
+        formatted
+    }
+}
+
+/// The entry type and the fields of an entry as the biblatex crate serializes them
+///
+/// The crate renames some fields for each dialect (e.g. `journaltitle` to `journal` for BibTeX),
+/// and splits the date into year, month and day for BibTeX. Each field is serialized on its own so
+/// that values spanning several lines are kept whole.
+fn serialized_fields(entry: &Entry, dialect: BibDialect) -> (String, Vec<(String, String)>) {
+    let serialize = |entry: &Entry| match dialect {
+        BibDialect::Bibtex => entry.to_bibtex_string(),
+        BibDialect::Biblatex => entry.to_biblatex_string(),
+    };
+    // An entry without fields is serialized as "@type{key,\n}"
+    let empty = Entry::new(entry.key.clone(), entry.entry_type.clone());
+    let header = serialize(&empty);
+    let entry_type = header[1..header.find('{').unwrap_or(1)].to_string();
+    let header_len = header.find('\n').map_or(0, |idx| idx + 1);
+
+    let mut fields = vec![];
+    for (name, value) in entry.fields.iter() {
+        let mut single = empty.clone();
+        single.fields.insert(name.clone(), value.clone());
+        let serialized = serialize(&single);
+        let body = &serialized[header_len..serialized.len() - 1];
+        // Only the date may become more than one field, and those have single line values
+        let lines: Vec<&str> = match name == "date" && dialect == BibDialect::Bibtex {
+            true => body.lines().collect(),
+            false => vec![body.trim_end_matches('\n')],
+        };
+        for line in lines {
+            if let Some((name, value)) = line
+                .strip_suffix(',')
+                .and_then(|line| line.split_once(" = "))
+            {
+                fields.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+    (entry_type, fields)
+}
+
+fn apply_case(name: &str, case: NameCase) -> String {
+    match case {
+        NameCase::Keep => name.to_string(),
+        NameCase::Lower => name.to_lowercase(),
+        NameCase::Upper => name.to_uppercase(),
+    }
+}
+
+/// Turn a value in braces (`{A "quoted" title}`) into a value in quotes (`"A {"}quoted{"} title"`)
+///
+/// Quotes inside the value are put in braces, unless they are already inside braces (counted as
+/// BibTeX does, see `delimiter_depths`).
+fn braces_to_quotes(value: &str) -> String {
+    let inner = match value
+        .strip_prefix('{')
+        .and_then(|value| value.strip_suffix('}'))
+    {
+        Some(inner) => inner,
+        None => return value.to_string(),
+    };
+    let mut quoted = String::from("\"");
+    for (_, c, depth) in delimiter_depths(inner, '{', '}') {
+        match c {
+            '}' if depth == 0 => return value.to_string(),
+            '"' if depth == 0 => {
+                quoted.push_str("{\"}");
+                continue;
+            }
+            _ => {}
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bibliography() -> Bibliography {
+        Bibliography::parse(
+            "@article{prior1960,
+                title = {The Runabout {Inference-Ticket}},
+                author = {Prior, Arthur N.},
+                journal = {Analysis},
+                date = {1960-12},
+                doi = {10.1093/analys/21.2.38},
+            }
+            @book{carnap1942, author = {Rudolf Carnap}, note = {A \"note\"}}",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_format() {
+        // The default options give the same output as the biblatex crate
+        let bibliography = bibliography();
+        let options = FormatOptions::default();
+        assert_eq!(
+            options.format_bibliography(&bibliography, BibDialect::Bibtex, &[]),
+            bibliography.to_bibtex_string()
+        );
+        assert_eq!(
+            options.format_bibliography(&bibliography, BibDialect::Biblatex, &[]),
+            bibliography.to_biblatex_string()
+        );
+    }
+
+    #[test]
+    fn test_format_entry() {
+        let bibliography = bibliography();
+        let options = FormatOptions {
+            field_order: FieldOrder::Canonical,
+            indent: 2,
+            align: true,
+            delimiters: Delimiters::Quotes,
+            entry_type_case: NameCase::Upper,
+            field_case: NameCase::Keep,
+            no_trailing_comma: true,
+        };
+        let abbreviations = vec![(String::from("anl"), String::from("{Analysis}"))];
+        assert_eq!(
+            options.format_entry(
+                bibliography.get("prior1960").unwrap(),
+                BibDialect::Bibtex,
+                &abbreviations
+            ),
+            "@ARTICLE{prior1960,
+  author  = \"Prior, Arthur N.\",
+  title   = \"The Runabout {Inference-Ticket}\",
+  journal = anl,
+  year    = \"1960\",
+  month   = \"12\",
+  doi     = \"10.1093/analys/21.2.38\"
+}"
+        );
+        assert_eq!(
+            options.format_entry(
+                bibliography.get("carnap1942").unwrap(),
+                BibDialect::Biblatex,
+                &[]
+            ),
+            "@BOOK{carnap1942,
+  author = \"Rudolf Carnap\",
+  note   = \"A {\"}note{\"}\"
+}"
+        );
+    }
+
+    #[test]
+    fn test_braces_to_quotes() {
+        assert_eq!(braces_to_quotes("{A \"note\"}"), "\"A {\"}note{\"}\"");
+        // As in BibTeX, a backslash does not escape a brace
+        assert_eq!(braces_to_quotes("{In C:\\}"), "\"In C:\\\"");
+        assert_eq!(braces_to_quotes("{\\{\"a\"\\}}"), "\"\\{\"a\"\\}\"");
+        // Values that are not a single group are left as they are
+        assert_eq!(braces_to_quotes("{a} # {b}"), "{a} # {b}");
+    }
+}
@@ -0,0 +1,741 @@
+use std::collections::BTreeSet;
+
+use biblatex::{Bibliography, Chunk, ChunksExt, Entry, EntryType, Person};
+use serde_yaml::{Mapping, Value};
+
+use super::{generate_citation_key, get_date_parts, RecordError, PARENT_FIELDS};
+
+/// Get the entry type corresponding to a Hayagriva entry type, given the type of its parent
+/// (the work it is part of), if any
+pub fn entry_type_from_hayagriva(hayagriva_type: &str, parent_type: Option<&str>) -> EntryType {
+    let parent_type = parent_type.map(str::to_lowercase);
+    match (
+        hayagriva_type.to_lowercase().as_str(),
+        parent_type.as_deref(),
+    ) {
+        ("article", Some("proceedings" | "conference")) => EntryType::InProceedings,
+        ("article", _) => EntryType::Article,
+        ("chapter", Some("book")) => EntryType::InBook,
+        ("chapter" | "anthos", _) => EntryType::InCollection,
+        ("entry", _) => EntryType::InReference,
+        ("book", _) => EntryType::Book,
+        ("anthology", _) => EntryType::Collection,
+        ("proceedings" | "conference", _) => EntryType::Proceedings,
+        ("reference", _) => EntryType::Reference,
+        ("periodical" | "newspaper", _) => EntryType::Periodical,
+        ("thesis", _) => EntryType::PhdThesis,
+        ("report", _) => EntryType::TechReport,
+        ("web" | "blog" | "post", _) => EntryType::Online,
+        ("patent", _) => EntryType::Patent,
+        ("manuscript", _) => EntryType::Unpublished,
+        ("repository", _) => EntryType::Software,
+        _ => EntryType::Misc,
+    }
+}
+
+/// Get the Hayagriva entry type corresponding to an entry type, and the type of the parent that
+/// holds its journal or book title (if it has one)
+pub fn hayagriva_type_from_entry(entry_type: &EntryType) -> (&'static str, Option<&'static str>) {
+    match entry_type {
+        EntryType::Article => ("article", Some("periodical")),
+        EntryType::InProceedings => ("article", Some("proceedings")),
+        EntryType::InBook | EntryType::BookInBook | EntryType::SuppBook => {
+            ("chapter", Some("book"))
+        }
+        EntryType::InCollection | EntryType::SuppCollection => ("chapter", Some("anthology")),
+        EntryType::InReference => ("entry", Some("reference")),
+        EntryType::Book | EntryType::MvBook => ("book", None),
+        EntryType::Collection | EntryType::MvCollection => ("anthology", None),
+        EntryType::Proceedings | EntryType::MvProceedings => ("proceedings", None),
+        EntryType::Reference | EntryType::MvReference => ("reference", None),
+        EntryType::Periodical | EntryType::SuppPeriodical => ("periodical", None),
+        EntryType::Thesis | EntryType::PhdThesis | EntryType::MastersThesis => ("thesis", None),
+        EntryType::Report | EntryType::TechReport => ("report", None),
+        EntryType::Online => ("web", None),
+        EntryType::Patent => ("patent", None),
+        EntryType::Unpublished => ("manuscript", None),
+        EntryType::Software => ("repository", None),
+        _ => ("misc", None),
+    }
+}
+
+/// Identifiers kept in the serial-number of a Hayagriva entry (Hayagriva name, entry field)
+const SERIAL_NUMBERS: [(&str, &str); 5] = [
+    ("doi", "doi"),
+    ("isbn", "isbn"),
+    ("issn", "issn"),
+    ("pmid", "pmid"),
+    ("pmcid", "pmcid"),
+];
+
+/// Parse the contents of a Hayagriva YAML file, returning an entry (or an error) for each entry
+///
+/// The keys of the top-level mapping are the citation keys. The fields of the parent (e.g. the
+/// journal of an article, or the book of a chapter) are brought into the entry.
+pub fn parse_hayagriva(src: &str) -> Vec<Result<Entry, RecordError>> {
+    let library: Mapping = match serde_yaml::from_str::<Value>(src) {
+        Ok(Value::Mapping(library)) => library,
+        Ok(Value::Null) => return vec![],
+        Ok(_) => {
+            return vec![Err(RecordError {
+                line: 1,
+                text: src.lines().next().unwrap_or_default().to_string(),
+                message: String::from("A Hayagriva file must be a mapping from keys to entries"),
+            })]
+        }
+        Err(e) => {
+            let line = e
+                .location()
+                .map(|location| src[..location.index().min(src.len())].matches('\n').count() + 1)
+                .unwrap_or(1);
+            return vec![Err(RecordError {
+                line,
+                text: src.lines().nth(line - 1).unwrap_or_default().to_string(),
+                message: format!("Invalid YAML: {}", e),
+            })];
+        }
+    };
+
+    library
+        .iter()
+        .map(|(key, fields)| {
+            let key = value_to_string(key).unwrap_or_default();
+            let error = |message: &str| {
+                let line = find_key_line(src, &key);
+                RecordError {
+                    line,
+                    text: src.lines().nth(line - 1).unwrap_or_default().to_string(),
+                    message: message.to_string(),
+                }
+            };
+            match fields {
+                Value::Mapping(fields) => {
+                    let mut entry = fields_to_entry(fields)
+                        .ok_or_else(|| error("Entry has no author, title or date"))?;
+                    entry.key = match key.contains(char::is_whitespace) || key.is_empty() {
+                        true => generate_citation_key(&entry),
+                        false => key,
+                    };
+                    Ok(entry)
+                }
+                _ => Err(error("Entry is not a mapping of fields")),
+            }
+        })
+        .collect()
+}
+
+/// Line (starting from 1) where a top-level key is defined
+fn find_key_line(src: &str, key: &str) -> usize {
+    src.lines()
+        .position(|line| {
+            let line = line.trim_end();
+            [
+                format!("{}:", key),
+                format!("\"{}\":", key),
+                format!("'{}':", key),
+            ]
+            .iter()
+            .any(|prefix| line.starts_with(prefix.as_str()))
+        })
+        .map_or(1, |idx| idx + 1)
+}
+
+/// Convert the fields of a Hayagriva entry into an entry (without a key)
+///
+/// Returns None if the entry has no author, title or date at all.
+fn fields_to_entry(fields: &Mapping) -> Option<Entry> {
+    let parent = fields.get("parent").and_then(first_parent);
+    let grandparent = parent
+        .and_then(|parent| parent.get("parent"))
+        .and_then(first_parent);
+    let get =
+        |mapping: Option<&Mapping>, field: &str| mapping?.get(field).and_then(value_to_string);
+    let own = |field: &str| get(Some(fields), field);
+    // Fields that may be given in the entry or in its parent
+    let inherited = |field: &str| own(field).or_else(|| get(parent, field));
+
+    let hayagriva_type = own("type").unwrap_or_default();
+    let parent_type = get(parent, "type");
+    let entry_type = entry_type_from_hayagriva(&hayagriva_type, parent_type.as_deref());
+    let mut entry = Entry::new(String::new(), entry_type.clone());
+    let mut set = |field: &str, value: String| entry.set(field, vec![Chunk::Normal(value)]);
+
+    if let Some(authors) = fields.get("author").and_then(names_from_hayagriva) {
+        set("author", authors);
+    }
+    let editors = fields
+        .get("editor")
+        .or_else(|| parent?.get("editor"))
+        .and_then(names_from_hayagriva);
+    if let Some(editors) = editors {
+        set("editor", editors);
+    }
+    if let Some(title) = own("title") {
+        set("title", title);
+    }
+
+    // The parent is the journal of an article, the book of a chapter, and the series of a book
+    let is_part = parent.is_some()
+        && !matches!(
+            entry_type,
+            EntryType::Book
+                | EntryType::Collection
+                | EntryType::Proceedings
+                | EntryType::Reference
+                | EntryType::Periodical
+                | EntryType::TechReport
+                | EntryType::PhdThesis
+        );
+    let series = match is_part {
+        true => get(grandparent, "title"),
+        false => get(parent, "title"),
+    };
+    if is_part {
+        if let Some(parent_title) = get(parent, "title") {
+            match entry_type {
+                EntryType::Article => set("journal", parent_title),
+                _ => set("booktitle", parent_title),
+            }
+        }
+    }
+    if let Some(series) = series {
+        set("series", series);
+    }
+
+    match fields.get("date").and_then(date_from_hayagriva) {
+        // The full date only fits in the date field, which is split back when writing BibTeX
+        Some((year, Some(month), Some(day))) => {
+            set("date", format!("{}-{:02}-{:02}", year, month, day))
+        }
+        Some((year, month, _)) => {
+            set("year", year.to_string());
+            if let Some(month) = month {
+                set("month", month.to_string());
+            }
+        }
+        None => {}
+    }
+
+    for (hayagriva_field, field) in [
+        ("volume", "volume"),
+        ("issue", "number"),
+        ("edition", "edition"),
+        ("page-total", "pagetotal"),
+    ] {
+        let value = match is_part {
+            true => inherited(hayagriva_field),
+            false => own(hayagriva_field),
+        };
+        if let Some(value) = value {
+            set(field, value);
+        }
+    }
+    if let Some(pages) = own("page-range") {
+        set("pages", pages.replace('-', "--").replace("----", "--"));
+    }
+
+    let publisher = fields.get("publisher").or_else(|| parent?.get("publisher"));
+    if let Some(name) = publisher.and_then(|publisher| {
+        value_to_string(publisher).or_else(|| publisher.get("name").and_then(value_to_string))
+    }) {
+        set("publisher", name);
+    }
+    let location = publisher
+        .and_then(|publisher| publisher.get("location"))
+        .and_then(value_to_string)
+        .or_else(|| inherited("location"));
+    if let Some(location) = location {
+        set("address", location);
+    }
+    if let Some(organization) = own("organization") {
+        match entry_type {
+            EntryType::PhdThesis => set("school", organization),
+            EntryType::TechReport => set("institution", organization),
+            _ => set("organization", organization),
+        }
+    }
+
+    match fields.get("serial-number") {
+        Some(Value::Mapping(serial_numbers)) => {
+            for (hayagriva_name, field) in SERIAL_NUMBERS {
+                if let Some(value) = get(Some(serial_numbers), hayagriva_name) {
+                    set(field, value);
+                }
+            }
+            if let Some(arxiv) = get(Some(serial_numbers), "arxiv") {
+                set("eprint", arxiv);
+                set("eprinttype", String::from("arxiv"));
+            }
+        }
+        Some(serial_number) => {
+            if let Some(serial_number) = value_to_string(serial_number) {
+                set("number", serial_number);
+            }
+        }
+        None => {}
+    }
+    // The ISSN of an article is often given in its journal
+    let has_issn = fields
+        .get("serial-number")
+        .and_then(|serial_numbers| serial_numbers.get("issn"))
+        .is_some();
+    if !has_issn {
+        if let Some(issn) = get(parent, "issn").or_else(|| {
+            let serial_numbers = parent?.get("serial-number")?.as_mapping();
+            get(serial_numbers, "issn")
+        }) {
+            set("issn", issn);
+        }
+    }
+
+    if let Some(url) = fields.get("url") {
+        if let Some(value) = value_to_string(url) {
+            set("url", value);
+        }
+        if let Some(date) = url.get("date").and_then(value_to_string) {
+            set("urldate", date);
+        }
+    }
+    for (hayagriva_field, field) in [
+        ("language", "language"),
+        ("note", "note"),
+        ("abstract", "abstract"),
+        ("genre", "type"),
+    ] {
+        if let Some(value) = own(hayagriva_field) {
+            set(field, value);
+        }
+    }
+
+    if entry.get("author").is_none()
+        && entry.get("title").is_none()
+        && entry.get("year").is_none()
+        && entry.get("date").is_none()
+    {
+        return None;
+    }
+    Some(entry)
+}
+
+/// A Hayagriva entry may have more than one parent, in which case the first one is taken
+fn first_parent(parent: &Value) -> Option<&Mapping> {
+    match parent {
+        Value::Sequence(parents) => parents.first()?.as_mapping(),
+        parent => parent.as_mapping(),
+    }
+}
+
+/// The value of a Hayagriva field as a string
+///
+/// Formattable strings may be given as a mapping with the value and a short form (e.g.
+/// `{ value: An Essay in Modal Logic, short: Modal Logic }`), in which case the value is taken.
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) if !string.trim().is_empty() => Some(string.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        Value::Mapping(mapping) => mapping.get("value").and_then(value_to_string),
+        _ => None,
+    }
+}
+
+/// Convert a Hayagriva name or list of names into names in BibTeX format ("von Last, Jr., First")
+fn names_from_hayagriva(names: &Value) -> Option<String> {
+    let names: Vec<String> = match names {
+        Value::Sequence(names) => names.iter().filter_map(name_from_hayagriva).collect(),
+        name => name_from_hayagriva(name).into_iter().collect(),
+    };
+    match names.is_empty() {
+        true => None,
+        false => Some(names.join(" and ")),
+    }
+}
+
+fn name_from_hayagriva(name: &Value) -> Option<String> {
+    match name {
+        // Names are already written as "Last, First"
+        Value::String(name) if !name.trim().is_empty() => Some(name.trim().to_string()),
+        Value::Mapping(name) => {
+            let part = |key: &str| name.get(key).and_then(value_to_string);
+            let last_name = [part("prefix"), part("name")]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(" ");
+            if last_name.is_empty() {
+                return None;
+            }
+            let name = [Some(last_name), part("suffix"), part("given-name")]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(", ");
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+/// Get the year, month and day (starting from 1) of a Hayagriva date (e.g. `1960-12` or `2018`)
+fn date_from_hayagriva(date: &Value) -> Option<(i32, Option<u8>, Option<u8>)> {
+    let date = value_to_string(date)?;
+    let mut parts = date.split('-').map(|part| part.trim().parse::<i32>().ok());
+    let year = parts.next()??;
+    let month = parts
+        .next()
+        .flatten()
+        .and_then(|month| u8::try_from(month).ok());
+    let day = parts
+        .next()
+        .flatten()
+        .and_then(|day| u8::try_from(day).ok());
+    Some((year, month.filter(|month| (1..=12).contains(month)), day))
+}
+
+/// Fields that are written to Hayagriva (every other field is dropped)
+pub const HAYAGRIVA_EXPORTED_FIELDS: [&str; 32] = [
+    "author",
+    "editor",
+    "title",
+    "journal",
+    "journaltitle",
+    "booktitle",
+    "series",
+    "date",
+    "year",
+    "month",
+    "day",
+    "volume",
+    "number",
+    "issue",
+    "pages",
+    "pagetotal",
+    "edition",
+    "publisher",
+    "address",
+    "location",
+    "school",
+    "institution",
+    "organization",
+    "doi",
+    "isbn",
+    "issn",
+    "pmid",
+    "pmcid",
+    "url",
+    "urldate",
+    "language",
+    "note",
+];
+
+/// Serialize a bibliography into a Hayagriva YAML library
+///
+/// Entries inherit the fields of the entries they reference through crossref or xdata, since
+/// Hayagriva has no way of expressing those references. The journal of an article and the book of a
+/// chapter go into its parent, along with their volume, issue and editors. Fields not in
+/// `HAYAGRIVA_EXPORTED_FIELDS` (other than abstract and type) are dropped.
+pub fn to_hayagriva_string(bibliography: &Bibliography) -> String {
+    let mut library = Mapping::new();
+    for entry in bibliography.iter() {
+        let entry = bibliography
+            .get_resolved(&entry.key)
+            .unwrap_or_else(|| entry.clone());
+        library.insert(
+            Value::String(entry.key.clone()),
+            Value::Mapping(entry_to_fields(&entry)),
+        );
+    }
+    if library.is_empty() {
+        return String::new();
+    }
+    serde_yaml::to_string(&library).unwrap_or_default()
+}
+
+/// Fields of a bibliography that have no Hayagriva equivalent, and are thus dropped by
+/// `to_hayagriva_string`
+pub fn dropped_hayagriva_fields(bibliography: &Bibliography) -> Vec<String> {
+    let dropped: BTreeSet<String> = bibliography
+        .iter()
+        .flat_map(|entry| entry.fields.keys())
+        .map(|field| field.to_lowercase())
+        .filter(|field| {
+            !HAYAGRIVA_EXPORTED_FIELDS.contains(&field.as_str())
+                && !PARENT_FIELDS.contains(&field.as_str())
+                && !["abstract", "type"].contains(&field.as_str())
+        })
+        .collect();
+    dropped.into_iter().collect()
+}
+
+/// Convert an entry into the fields of a Hayagriva entry
+fn entry_to_fields(entry: &Entry) -> Mapping {
+    // Field names keep the case they were written in (e.g. "ISSN")
+    let get = |field: &str| {
+        entry
+            .fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field))
+            .map(|(_, value)| value.format_verbatim())
+            .filter(|value| !value.is_empty())
+    };
+    let (hayagriva_type, parent_type) = hayagriva_type_from_entry(&entry.entry_type);
+    let parent_title = get("journaltitle")
+        .or_else(|| get("journal"))
+        .or_else(|| get("booktitle"));
+    // Without a journal or book, there is no parent to hold the volume and issue
+    let parent_type = parent_type.filter(|_| parent_title.is_some());
+
+    let mut fields = Mapping::new();
+    let mut parent = Mapping::new();
+    fields.insert(string("type"), string(hayagriva_type));
+    if let Some(title) = get("title") {
+        fields.insert(string("title"), string(&title));
+    }
+    if let Some(authors) = names_to_hayagriva(entry, "author") {
+        fields.insert(string("author"), authors);
+    }
+    if let Some(editors) = names_to_hayagriva(entry, "editor") {
+        // The editors of a chapter are the editors of the book
+        match parent_type {
+            Some(_) => parent.insert(string("editor"), editors),
+            None => fields.insert(string("editor"), editors),
+        };
+    }
+    if let Some((year, month, day)) = get_date_parts(entry) {
+        let date = match (month, day) {
+            (Some(month), Some(day)) => format!("{:04}-{:02}-{:02}", year, month, day),
+            (Some(month), None) => format!("{:04}-{:02}", year, month),
+            _ => format!("{:04}", year),
+        };
+        fields.insert(string("date"), string(&date));
+    } else if let Some(year) = get("year") {
+        fields.insert(string("date"), string(&year));
+    }
+    if let Some(pages) = get("pages") {
+        fields.insert(string("page-range"), string(&pages.replace("--", "-")));
+    }
+    if let Some(page_total) = get("pagetotal") {
+        fields.insert(string("page-total"), number_or_string(&page_total));
+    }
+    if let Some(edition) = get("edition") {
+        fields.insert(string("edition"), number_or_string(&edition));
+    }
+    // The volume and issue of an article are those of the journal
+    for (field, hayagriva_field) in [
+        ("volume", "volume"),
+        ("number", "issue"),
+        ("issue", "issue"),
+    ] {
+        if let Some(value) = get(field) {
+            let target = match parent_type {
+                Some("periodical") => &mut parent,
+                _ => &mut fields,
+            };
+            if !target.contains_key(hayagriva_field) {
+                target.insert(string(hayagriva_field), number_or_string(&value));
+            }
+        }
+    }
+
+    if let Some(publisher) = get("publisher") {
+        fields.insert(string("publisher"), string(&publisher));
+    }
+    if let Some(location) = get("address").or_else(|| get("location")) {
+        fields.insert(string("location"), string(&location));
+    }
+    if let Some(organization) = get("school")
+        .or_else(|| get("institution"))
+        .or_else(|| get("organization"))
+    {
+        fields.insert(string("organization"), string(&organization));
+    }
+
+    let mut serial_numbers = Mapping::new();
+    for (hayagriva_name, field) in SERIAL_NUMBERS {
+        if let Some(value) = get(field) {
+            serial_numbers.insert(string(hayagriva_name), string(&value));
+        }
+    }
+    if !serial_numbers.is_empty() {
+        fields.insert(string("serial-number"), Value::Mapping(serial_numbers));
+    }
+
+    if let Some(url) = get("url") {
+        match get("urldate") {
+            Some(date) => {
+                let mut url_with_date = Mapping::new();
+                url_with_date.insert(string("value"), string(&url));
+                url_with_date.insert(string("date"), string(&date));
+                fields.insert(string("url"), Value::Mapping(url_with_date));
+            }
+            None => {
+                fields.insert(string("url"), string(&url));
+            }
+        }
+    }
+    for (field, hayagriva_field) in [
+        ("language", "language"),
+        ("note", "note"),
+        ("abstract", "abstract"),
+        ("type", "genre"),
+    ] {
+        if let Some(value) = get(field) {
+            fields.insert(string(hayagriva_field), string(&value));
+        }
+    }
+
+    // The series is the parent of the book or, for chapters, the parent of the parent
+    let series = get("series").map(|series| {
+        let mut series_parent = Mapping::new();
+        series_parent.insert(string("type"), string("anthology"));
+        series_parent.insert(string("title"), string(&series));
+        series_parent
+    });
+    match (parent_type, parent_title) {
+        (Some(parent_type), Some(parent_title)) => {
+            let mut full_parent = Mapping::new();
+            full_parent.insert(string("type"), string(parent_type));
+            full_parent.insert(string("title"), string(&parent_title));
+            full_parent.extend(parent);
+            if let Some(series) = series {
+                full_parent.insert(string("parent"), Value::Mapping(series));
+            }
+            fields.insert(string("parent"), Value::Mapping(full_parent));
+        }
+        _ => {
+            if let Some(series) = series {
+                fields.insert(string("parent"), Value::Mapping(series));
+            }
+        }
+    }
+    fields
+}
+
+/// Write the names of a field as a list of "Last, First" strings (with any particle in the last
+/// name, and any suffix after the first name)
+fn names_to_hayagriva(entry: &Entry, field: &str) -> Option<Value> {
+    let persons = entry.get_as::<Vec<Person>>(field)?;
+    let names: Vec<Value> = persons
+        .iter()
+        .map(|person| {
+            let last_name = match person.prefix.is_empty() {
+                true => person.name.clone(),
+                false => format!("{} {}", person.prefix, person.name),
+            };
+            let name = [last_name, person.given_name.clone(), person.suffix.clone()]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<String>>()
+                .join(", ");
+            string(&name)
+        })
+        .collect();
+    match names.is_empty() {
+        true => None,
+        false => Some(Value::Sequence(names)),
+    }
+}
+
+fn string(value: &str) -> Value {
+    Value::String(value.to_string())
+}
+
+/// Numbers such as volumes are written as numbers when they are plain integers
+fn number_or_string(value: &str) -> Value {
+    match value.parse::<u64>() {
+        Ok(number) if !value.starts_with('0') => Value::Number(number.into()),
+        _ => string(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_hayagriva() {
+        let src = fs::read_to_string("bib_files/hayagriva_files/library.yml").unwrap();
+        let entries = parse_hayagriva(&src);
+        assert_eq!(entries.len(), 4);
+
+        let prior = entries[0].as_ref().unwrap();
+        assert_eq!(prior.key, "Prior1960");
+        assert_eq!(prior.entry_type, EntryType::Article);
+        assert_eq!(prior.journal().unwrap().format_verbatim(), "Analysis");
+        assert_eq!(prior.get("volume").unwrap().format_verbatim(), "21");
+        assert_eq!(prior.get("number").unwrap().format_verbatim(), "2");
+        assert_eq!(prior.get("month").unwrap().format_verbatim(), "12");
+        assert_eq!(prior.doi().unwrap(), "10.1093/analys/21.2.38");
+
+        let chapter = entries[1].as_ref().unwrap();
+        assert_eq!(chapter.entry_type, EntryType::InCollection);
+        assert_eq!(chapter.author().unwrap().len(), 3);
+        assert_eq!(chapter.editors()[0].0.len(), 2);
+        assert_eq!(
+            chapter.book_title().unwrap().format_verbatim(),
+            "Between Consistency and Inconsistency"
+        );
+        assert_eq!(
+            chapter.get("series").unwrap().format_verbatim(),
+            "Trends in Logic"
+        );
+        assert_eq!(
+            chapter.get("address").unwrap().format_verbatim(),
+            "Dordrecht"
+        );
+
+        let book = entries[2].as_ref().unwrap();
+        assert_eq!(
+            book.title().unwrap().format_verbatim(),
+            "An Essay in Modal Logic"
+        );
+        assert_eq!(book.author().unwrap()[0].prefix, "von");
+
+        let error = entries[3].as_ref().unwrap_err();
+        assert_eq!(error.line, 47);
+        assert_eq!(error.message, "Entry has no author, title or date");
+
+        let error = parse_hayagriva("a:\n  title: [unclosed\n")
+            .remove(0)
+            .unwrap_err();
+        assert!(error.message.starts_with("Invalid YAML"));
+    }
+
+    #[test]
+    fn test_to_hayagriva_string() {
+        let src = fs::read_to_string("bib_files/hayagriva_files/library.yml").unwrap();
+        let mut bibliography = Bibliography::new();
+        for entry in parse_hayagriva(&src).into_iter().flatten() {
+            bibliography.insert(entry);
+        }
+        let yaml = to_hayagriva_string(&bibliography);
+        let library: Value = serde_yaml::from_str(&yaml).unwrap();
+        let prior = &library["Prior1960"];
+        assert_eq!(prior["type"], string("article"));
+        assert_eq!(prior["date"], string("1960-12"));
+        assert_eq!(prior["page-range"], string("38-39"));
+        assert_eq!(prior["parent"]["title"], string("Analysis"));
+        assert_eq!(prior["parent"]["volume"], Value::Number(21.into()));
+        assert_eq!(
+            library["BPS2018-WIAPL"]["parent"]["editor"][1],
+            string("Malinowski, Jacek")
+        );
+        assert_eq!(
+            library["BPS2018-WIAPL"]["parent"]["parent"]["title"],
+            string("Trends in Logic")
+        );
+        assert_eq!(
+            library["vonWright1951"]["author"][0],
+            string("von Wright, Georg Henrik")
+        );
+
+        // Reading the output back gives the same entries
+        let entries: Vec<Entry> = parse_hayagriva(&yaml).into_iter().flatten().collect();
+        assert_eq!(entries.len(), 3);
+        for (entry, original) in entries.iter().zip(bibliography.iter()) {
+            assert_eq!(entry, original);
+        }
+        assert!(dropped_hayagriva_fields(&bibliography).is_empty());
+    }
+}
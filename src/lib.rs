@@ -1,14 +1,75 @@
 #![doc = include_str!("../README.md")]
 
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::{ffi::OsStr, fs};
 
-use anyhow;
 use anyhow::Context;
+use biblatex::Bibliography;
 use clap::{ArgEnum, Parser};
+use encoding_rs::Encoding;
 
+/// Prints a progress message to stdout, or to stderr if the unified bibliography is being written
+/// to stdout (so that the messages do not end up mixed with it)
+macro_rules! status {
+    ($config:expr, $($arg:tt)*) => {
+        if $config.writes_to_stdout() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Same as `status!`, but for messages formatted with `bunt`
+macro_rules! bunt_status {
+    ($config:expr, $($arg:tt)*) => {
+        if $config.writes_to_stdout() {
+            bunt::eprintln!($($arg)*);
+        } else {
+            bunt::println!($($arg)*);
+        }
+    };
+}
+
+mod authors;
+pub use crate::authors::*;
+mod crossref;
+pub use crate::crossref::*;
+mod csl;
+pub use crate::csl::*;
+mod decisions;
+pub use crate::decisions::*;
+mod diagnostics;
+pub use crate::diagnostics::*;
+mod encoding;
+pub use crate::encoding::*;
+mod endnote;
+pub use crate::endnote::*;
+mod formatting;
+pub use crate::formatting::*;
+mod hayagriva;
+pub use crate::hayagriva::*;
+mod metadata;
+pub use crate::metadata::*;
+mod nbib;
+pub use crate::nbib::*;
+mod normalize;
+pub use crate::normalize::*;
+mod output;
+pub use crate::output::*;
 mod parsing;
-use crate::parsing::*;
+pub use crate::parsing::*;
+mod report;
+pub use crate::report::*;
+mod ris;
+pub use crate::ris::*;
+mod rules;
+pub use crate::rules::*;
+mod scoring;
+pub use crate::scoring::*;
+mod sorting;
+pub use crate::sorting::*;
 mod unify;
 pub use crate::unify::*;
 
@@ -22,16 +83,27 @@ pub enum Algorithm {
     SorensenDice,
 }
 
+/// Order in which the input files are read
+#[derive(Debug, Clone, ArgEnum)]
+pub enum InputOrder {
+    /// Alphabetical order of the file paths
+    Sorted,
+    /// The order in which the inputs were given (files inside a directory are still sorted)
+    Given,
+}
+
 /// Configuration struct for how bib_unifier will run
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 pub struct Config {
-    /// Directory where the .bib files are located
+    /// Files, directories and glob patterns to read the input files from (in any of the formats
+    /// of `InputFormat`)
     #[clap(
         value_name = "PATH",
-        help = "Directory where the .bib files are located"
+        required = true,
+        help = "Files, directories or glob patterns (e.g. \"papers/**/*.bib\") to unify"
     )]
-    pub path_dir: PathBuf,
+    pub inputs: Vec<PathBuf>,
 
     /// Path to the output file (optional)
     #[clap(
@@ -66,6 +138,37 @@ pub struct Config {
     )]
     pub algorithm: Algorithm,
 
+    /// Steps of the normalization applied to titles before comparing them
+    #[clap(
+        long = "normalize-titles",
+        arg_enum,
+        value_name = "STEPS",
+        use_value_delimiter = true,
+        default_values = &["latex", "unicode", "braces", "case", "punctuation", "whitespace"],
+        help = "Comma-separated steps of the normalization applied to titles before comparing them (none, on its own, turns it off)",
+        display_order = 16
+    )]
+    pub title_normalization: Vec<TitleNormalization>,
+
+    /// If true, the authors are not taken into account when comparing titles
+    #[clap(
+        long,
+        help = "If present, entries with the same or similar titles are considered the same even if their authors differ",
+        display_order = 17
+    )]
+    pub ignore_authors: bool,
+
+    /// Threshold to test title similarity when the authors and year of the entries agree (the
+    /// same as `similarity_threshold` if None, or if it is higher)
+    #[clap(
+        long,
+        value_name = "THRESHOLD",
+        validator = validate_threshold,
+        help = "Value between 0 and 1 to compare the titles of entries with the same authors and year (lower than --threshold, to find titles that differ a bit more)",
+        display_order = 18
+    )]
+    pub same_authors_threshold: Option<f64>,
+
     /// If true, will not ask for input regarding which entry to keep
     #[clap(
         short,
@@ -75,19 +178,264 @@ pub struct Config {
     )]
     pub silent: bool,
 
-    /// Write to file in Bibtex or Biblatex format
+    /// Format of the output file (inferred from the extension of the output file if None, or
+    /// bibtex if there is no output file)
+    #[clap(
+        short,
+        long,
+        value_name = "FORMAT",
+        validator = validate_format,
+        help = "Format of the output file: bibtex, biblatex, csl-json, ris or hayagriva [default: inferred from the output file, or bibtex]",
+        display_order = 4
+    )]
+    pub format: Option<String>,
+
+    /// Order of the entries in the output
     #[clap(
-    short,
-    long,
-    help = "Default format for entries is bibtex. Setting this flag changes it to biblatex",
-    display_order = 4
+        long,
+        arg_enum,
+        value_name = "ORDER",
+        default_value_t = SortOrder::Input,
+        help = "Order of the entries in the output (entries that others inherit from through crossref or xdata always go after them)",
+        display_order = 24
+    )]
+    pub sort: SortOrder,
+
+    /// Formats available for writing the output, which library users can add to
+    #[clap(skip)]
+    pub writers: WriterRegistry,
+
+    /// If true, will also look for input files inside subdirectories of the input directories
+    #[clap(
+        short,
+        long,
+        help = "If present, will also look for input files inside subdirectories",
+        display_order = 5
+    )]
+    pub recursive: bool,
+
+    /// How many levels of subdirectories to look into in recursive mode (unlimited if None)
+    #[clap(
+        long,
+        value_name = "DEPTH",
+        requires = "recursive",
+        help = "Maximum subdirectory depth to look into in recursive mode [default: unlimited]",
+        display_order = 6
+    )]
+    pub max_depth: Option<usize>,
+
+    /// If true, the files with generic extensions (.json, .yml, .yaml and .xml) found in the input directories and glob
+    /// patterns are read too, and not only those given by name
+    #[clap(
+        long,
+        help = "If present, will also read the .json, .yml, .yaml and .xml files found in directories and glob patterns (files given by name are always read)",
+        display_order = 7
+    )]
+    pub include_generic: bool,
+
+    /// Order in which the input files are read (and thus, which entries are found first)
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "ORDER",
+        default_value_t = InputOrder::Sorted,
+        help = "Order in which the input files are read",
+        display_order = 7
     )]
-    pub biblatex: bool,
+    pub input_order: InputOrder,
+
+    /// Files whose entries take priority over the rest, from highest to lowest priority
+    #[clap(
+        long,
+        value_name = "FILE",
+        multiple_occurrences = true,
+        help = "Read this file first, so that its entries are kept over repeated ones from other files (can be used more than once, from highest to lowest priority)",
+        display_order = 8
+    )]
+    pub prefer: Vec<PathBuf>,
+
+    /// If true, malformed entries are skipped (and reported) instead of aborting
+    #[clap(
+        short,
+        long,
+        help = "If present, malformed entries are skipped and reported instead of stopping the program",
+        display_order = 9
+    )]
+    pub lenient: bool,
+
+    /// File where the raw text of the entries skipped in lenient mode is written (optional)
+    #[clap(
+        long,
+        value_name = "PATH",
+        requires = "lenient",
+        help = "Write the entries skipped in lenient mode to this file",
+        display_order = 10
+    )]
+    pub quarantine: Option<PathBuf>,
+
+    /// Encoding of the input files (detected for each file if None)
+    #[clap(
+        long,
+        value_name = "ENCODING",
+        parse(try_from_str = parse_encoding),
+        help = "Encoding of the input files (e.g. utf-8, latin1, windows-1252) [default: detected for each file that does not declare its own]",
+        display_order = 11
+    )]
+    pub input_encoding: Option<&'static Encoding>,
+
+    /// Encoding of the output file
+    #[clap(
+        long,
+        value_name = "ENCODING",
+        parse(try_from_str = parse_output_encoding),
+        default_value = "utf-8",
+        help = "Encoding of the output file",
+        display_order = 12
+    )]
+    pub output_encoding: &'static Encoding,
+
+    /// If true, the repeated entries are reported but nothing is written (implies silent)
+    #[clap(
+        long,
+        help = "If present, will only report the repeated entries that would be deleted (keeping the first of each, as in silent mode), without writing any file other than the report",
+        display_order = 13
+    )]
+    pub dry_run: bool,
+
+    /// File where a JSON report of the repeated entries found (and of what was done with them) is
+    /// written (optional)
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write a JSON report of every repeated entry found, what was done with it, and the keys that were changed to this file",
+        display_order = 14
+    )]
+    pub report: Option<PathBuf>,
+
+    /// File where the decisions about repeated entries are recorded, to be applied on later runs
+    /// (optional)
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Apply the decisions about repeated entries recorded in this file instead of asking again, and record the new ones in it",
+        display_order = 15
+    )]
+    pub decisions: Option<PathBuf>,
+
+    /// Decisions about repeated entries made on previous runs (read from `decisions` when running
+    /// the program)
+    #[clap(skip)]
+    pub recorded_decisions: RecordedDecisions,
+
+    /// File with the rules about when entries are the same, evaluated before the built-in ones
+    /// unless it says where these go (optional)
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Read rules about when entries are the same from this JSON file, and apply them (in order) before the built-in rules, unless the file has \"defaults\" where these go",
+        display_order = 23
+    )]
+    pub rules: Option<PathBuf>,
+
+    /// Rules about when entries are the same (the built-in ones by default, and read from `rules`
+    /// when running the program)
+    #[clap(skip)]
+    pub match_rules: MatchRules,
+
+    /// How entries are compared by a weighted score of several fields (if enabled)
+    #[clap(flatten)]
+    pub scoring: ScoringOptions,
+
+    /// Layout of the .bib output (field order, indentation, delimiters, etc.)
+    #[clap(flatten)]
+    pub formatting: FormatOptions,
+}
+
+impl Config {
+    /// Whether the unified bibliography is written to stdout (output path "-")
+    pub fn writes_to_stdout(&self) -> bool {
+        matches!(&self.output, Some(path) if path.as_path() == Path::new(STDIO_PATH))
+    }
+
+    /// The format in which the unified bibliography is written
+    ///
+    /// That is the one named by `format` if given, otherwise the one for the extension of the
+    /// output file (which must match some format), and bibtex if there is no output file.
+    pub fn writer(&self) -> Result<&dyn BibliographyWriter, String> {
+        if let Some(name) = &self.format {
+            return self
+                .writers
+                .get(name)
+                .ok_or_else(|| unknown_format(name, &self.writers));
+        }
+        match &self.output {
+            Some(path) if !self.writes_to_stdout() => path
+                .extension()
+                .and_then(OsStr::to_str)
+                .and_then(|extension| self.writers.for_extension(extension))
+                .ok_or_else(|| {
+                    let extensions: Vec<String> = self
+                        .writers
+                        .extensions()
+                        .iter()
+                        .map(|extension| format!(".{}", extension))
+                        .collect();
+                    format!(
+                        "Output must be a path to a file of one of the supported formats ({}), or the format must be given with --format",
+                        extensions.join(", ")
+                    )
+                }),
+            _ => self
+                .writers
+                .get("bibtex")
+                .ok_or_else(|| String::from("There is no format to write the output in")),
+        }
+    }
+
+    /// Whether any of the inputs is stdin (input path "-")
+    pub fn reads_from_stdin(&self) -> bool {
+        self.inputs.iter().any(|path| path.as_path() == Path::new(STDIO_PATH))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            inputs: vec![],
+            output: None,
+            similarity_threshold: 1.0,
+            algorithm: Algorithm::Levenshtein,
+            title_normalization: DEFAULT_TITLE_NORMALIZATION.to_vec(),
+            ignore_authors: false,
+            same_authors_threshold: None,
+            silent: false,
+            format: None,
+            sort: SortOrder::Input,
+            writers: WriterRegistry::default(),
+            recursive: false,
+            max_depth: None,
+            include_generic: false,
+            input_order: InputOrder::Sorted,
+            prefer: vec![],
+            lenient: false,
+            quarantine: None,
+            input_encoding: None,
+            output_encoding: encoding_rs::UTF_8,
+            dry_run: false,
+            report: None,
+            decisions: None,
+            recorded_decisions: RecordedDecisions::default(),
+            rules: None,
+            match_rules: MatchRules::default(),
+            scoring: ScoringOptions::default(),
+            formatting: FormatOptions::default(),
+        }
+    }
 }
 
 fn validate_threshold(v: &str) -> Result<(), String> {
     if let Ok(num) = v.parse::<f64>() {
-        if num >= 0.0 && num <= 1.0 {
+        if (0.0..=1.0).contains(&num) {
             return Ok(());
         }
     }
@@ -96,74 +444,242 @@ fn validate_threshold(v: &str) -> Result<(), String> {
     ))
 }
 fn validate_output(v: &str) -> Result<(), String> {
-    if let Ok(path) = v.parse::<PathBuf>() {
-        if let (Some(_filename), Some(extension)) = (
-            path.file_name().and_then(OsStr::to_str),
-            path.extension().and_then(OsStr::to_str),
-        ) {
-            if extension == "bib" {
-                return Ok(());
-            }
-        }
+    if v == STDIO_PATH || PathBuf::from(v).file_name().is_some() {
+        return Ok(());
     }
-    Err(String::from("Output must be a path to a .bib file"))
+    Err(String::from("Output must be a path to a file, or \"-\" for stdout"))
 }
 
-/// Get the .bib files from a path, parse them and unify them into a single .bib file
+fn validate_format(v: &str) -> Result<(), String> {
+    let writers = WriterRegistry::default();
+    match writers.get(v) {
+        Some(_) => Ok(()),
+        None => Err(unknown_format(v, &writers)),
+    }
+}
+
+fn unknown_format(name: &str, writers: &WriterRegistry) -> String {
+    format!(
+        "Unknown output format \"{}\" (the available formats are {})",
+        name,
+        writers.names().join(", ")
+    )
+}
+
+/// Get the .bib files from the inputs, parse them and unify them into a single .bib file
 /// deleting repetitions
 pub fn run(mut config: Config) -> anyhow::Result<()> {
+    // If the bibliography comes from stdin, there is no way to ask the user which entry to keep
+    if config.reads_from_stdin() && !config.silent {
+        eprintln!("Reading from stdin, repeated entries will be handled as in silent mode.");
+        config.silent = true;
+    }
+    // A dry run only reports what would happen, so there is nothing to ask
+    if config.dry_run {
+        config.silent = true;
+    }
+    validate_title_normalization(&config.title_normalization).map_err(anyhow::Error::msg)?;
+    if let Some(decisions_path) = &config.decisions {
+        if decisions_path.exists() {
+            let decisions = fs::read_to_string(decisions_path)
+                .map_err(|e| e.to_string())
+                .and_then(|src| RecordedDecisions::parse(&src))
+                .map_err(anyhow::Error::msg)
+                .with_context(|| "A problem was encountered when reading the decisions file")?;
+            config.recorded_decisions = decisions;
+        }
+    }
+    if let Some(rules_path) = &config.rules {
+        let rules = fs::read_to_string(rules_path)
+            .map_err(|e| e.to_string())
+            .and_then(|src| MatchRules::parse(&src))
+            .map_err(anyhow::Error::msg)
+            .with_context(|| "A problem was encountered when reading the rules file")?;
+        config.match_rules = rules;
+    }
+    let formatting = config.formatting.clone();
+    config.writers.set_format_options(&formatting);
+    let writer = config.writer().map_err(anyhow::Error::msg)?;
+
     // Get the bibliographies
-    let filepaths = get_filepaths(config.path_dir.as_path())
-        .with_context(|| "A problem was encountered with the input path")?;
+    // Directories given as input are only read at the top level unless in recursive mode
+    let max_depth = match config.recursive {
+        true => config.max_depth.unwrap_or(usize::MAX),
+        false => 0,
+    };
+    // The files that the program reads or writes besides the inputs are not inputs themselves
+    let own_files: Vec<PathBuf> = [
+        &config.output,
+        &config.report,
+        &config.decisions,
+        &config.rules,
+        &config.quarantine,
+    ]
+    .into_iter()
+    .flatten()
+    .cloned()
+    .collect();
+    let filepaths = get_input_filepaths(
+        &config.inputs,
+        max_depth,
+        config.include_generic,
+        &own_files,
+    )
+    .with_context(|| "A problem was encountered with the input paths")?;
+    let filepaths = order_filepaths(filepaths, &config.input_order, &config.prefer)
+        .with_context(|| "A problem was encountered with the preferred files")?;
     let bibliographies =
-        get_files(&filepaths).with_context(|| "A problem was encountered with the input files")?;
+        get_files(&filepaths, config.input_encoding)
+            .with_context(|| "A problem was encountered with the input files")?;
     anyhow::ensure!(
-        bibliographies.len() > 0,
-        "No .bib files in the specified input directory"
+        !bibliographies.is_empty(),
+        "No .bib files found in the specified inputs"
     );
-    let bibliographies = get_bibliographies(filepaths, bibliographies)?;
+
+    // Get the @string, @preamble and @comment blocks, which are not part of the entries
+    let (metadata, conflicts) = collect_metadata(&filepaths, &bibliographies);
+    for conflict in conflicts.iter() {
+        bunt::eprintln!(
+            "{$yellow}@string {} is defined as {} in {:?} but as {} in {:?}.{/$} Keeping the first definition.",
+            conflict.kept.name,
+            conflict.kept.value,
+            conflict.kept.filepath,
+            conflict.discarded.value,
+            conflict.discarded.filepath
+        );
+    }
+    let bibliographies = match config.lenient {
+        true => {
+            let (bibliographies, rejected) = get_bibliographies_lenient(&filepaths, bibliographies);
+            for entry in rejected.iter() {
+                bunt::eprintln!(
+                    "{$yellow}Skipped malformed entry in {:?} (line {}):{/$} {}",
+                    entry.filepath,
+                    entry.line,
+                    entry.reason
+                );
+            }
+            if let (Some(quarantine_path), false) = (&config.quarantine, config.dry_run) {
+                write_quarantine(quarantine_path, &rejected).with_context(|| {
+                    "A problem was encountered when writing the skipped entries to the file"
+                })?;
+            }
+            bibliographies
+        }
+        false => get_bibliographies(filepaths.clone(), bibliographies)?,
+    };
 
     // Unify the bibliography
-    let unified_bibliography = unify_bibliography(bibliographies, &config);
+    let (unified_bibliography, report) = unify_bibliography_with_report(bibliographies, &config);
+    if let Some(report_path) = &config.report {
+        fs::write(report_path, report.to_json(&filepaths, &config))
+            .with_context(|| "A problem was encountered when writing the report to the file")?;
+    }
+    // Record the new decisions made by the user
+    if let Some(decisions_path) = &config.decisions {
+        let mut decisions = config.recorded_decisions.clone();
+        let new_decisions: Vec<&MatchReport> = report
+            .matches
+            .iter()
+            .filter(|entry_match| entry_match.decided_by == Decider::User)
+            .collect();
+        for entry_match in new_decisions.iter() {
+            decisions.record(entry_match);
+        }
+        if !new_decisions.is_empty() {
+            fs::write(decisions_path, decisions.to_json()).with_context(|| {
+                "A problem was encountered when writing the decisions to the file"
+            })?;
+        }
+    }
+
+    if config.dry_run {
+        print_duplicates(&report, &unified_bibliography, writer, &config);
+        status!(config, "Dry run: no file was written.");
+        return Ok(());
+    }
 
     // Write the result to a file
-    // By default, the output path is the input path plus the following file name
-    config.path_dir.push("[bib_unifier]bibliography.bib");
-    let mut path = config.path_dir.as_path();
+    // By default, the output path is the input directory plus the following file name
+    let default_path = default_output_dir(&config.inputs)
+        .join(format!("[bib_unifier]bibliography.{}", writer.extension()));
+    let mut path = default_path.as_path();
     // If the user entered a different output path, change that:
     if let Some(output_path) = &config.output {
         path = output_path.as_path()
     }
 
-    let bibliography_string = match config.biblatex {
-        true => unified_bibliography.to_biblatex_string(),
-        false => unified_bibliography.to_bibtex_string(),
-    };
-    fs::write(path, bibliography_string).with_context(|| {
+    for warning in writer.warnings(&unified_bibliography) {
+        status!(config, "{}", warning);
+    }
+    let bibliography_string = writer.write(&unified_bibliography, &metadata);
+    let bibliography_bytes = encode_output(&bibliography_string, config.output_encoding)
+        .map_err(anyhow::Error::msg)
+        .with_context(|| "A problem was encountered when encoding the unified bibliography")?;
+    if config.writes_to_stdout() {
+        io::stdout().write_all(&bibliography_bytes).with_context(|| {
+            "A problem was encountered when writing the unified bibliography to stdout"
+        })?;
+        eprintln!("Unified bibliography was written to stdout.");
+        return Ok(());
+    }
+    fs::write(path, bibliography_bytes).with_context(|| {
         "A problem was encountered when writing the unified bibliography to the file"
     })?;
     println!("Unified bibliography was written to {:?}.", path);
     Ok(())
 }
 
+/// Print each group of repeated entries: the entry kept, and the keys of the ones deleted with the
+/// reason why they were considered the same
+fn print_duplicates(
+    report: &UnificationReport,
+    bibliography: &Bibliography,
+    writer: &dyn BibliographyWriter,
+    config: &Config,
+) {
+    for (kept, group) in report.group_duplicates() {
+        let kept_entry = match bibliography.get(kept) {
+            Some(entry) => writer.format_entry(entry),
+            None => kept.to_string(),
+        };
+        bunt_status!(config, "{$green}Would keep:{/$} {}", kept_entry);
+        for entry_match in group {
+            let mut similarity = match entry_match.similarity {
+                Some(similarity) => format!("title similarity {:.2}", similarity),
+                None => String::from("no titles to compare"),
+            };
+            if let Some(score) = entry_match.score {
+                similarity.push_str(&format!(", score {:.2}", score));
+            }
+            bunt_status!(
+                config,
+                "{$red}Would delete:{/$} {} ({}, {})",
+                entry_match.deleted().unwrap_or_default(),
+                entry_match.criterion,
+                similarity
+            );
+        }
+        status!(config, "");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use biblatex::Bibliography;
+    use biblatex::{Bibliography, ChunksExt};
     use std::fs;
     use std::path::PathBuf;
 
     #[test]
     fn test_run() {
         let config = Config {
-            path_dir: PathBuf::from(r"bib_files/test_files/"),
+            inputs: vec![PathBuf::from(r"bib_files/test_files/")],
             similarity_threshold: 0.7,
-            algorithm: Algorithm::Levenshtein,
             silent: true,
-            output: None,
-            biblatex: false,
+            ..Default::default()
         };
-        if let Err(_) = run(config) {
+        if run(config).is_err() {
             panic!("Error running")
         }
 
@@ -174,4 +690,228 @@ mod tests {
         let bibliography = Bibliography::parse(&file).unwrap();
         assert_eq!(bibliography.len(), 7);
     }
+
+    /// Run silently on some inputs with the options set by `options`, writing the output to a
+    /// temporary file with the given name, and return its contents (None if it was not written)
+    fn run_on(
+        inputs: &[&str],
+        output_name: &str,
+        options: impl FnOnce(&mut Config),
+    ) -> anyhow::Result<Option<String>> {
+        let output = std::env::temp_dir().join(format!("bib_unifier_{}", output_name));
+        let _ = fs::remove_file(&output);
+        let mut config = Config {
+            inputs: inputs.iter().map(PathBuf::from).collect(),
+            silent: true,
+            output: Some(output.clone()),
+            ..Default::default()
+        };
+        options(&mut config);
+        let encoding = config.output_encoding;
+        run(config)?;
+        let contents = fs::read(&output).ok().map(|bytes| {
+            let (contents, _) = encoding.decode_without_bom_handling(&bytes);
+            contents.into_owned()
+        });
+        let _ = fs::remove_file(&output);
+        Ok(contents)
+    }
+
+    #[test]
+    fn test_run_dry_run() {
+        let report = std::env::temp_dir().join("bib_unifier_test_run_dry_run.json");
+        let contents = run_on(&["bib_files/test_files/"], "test_run_dry_run.bib", |config| {
+            config.similarity_threshold = 0.7;
+            config.dry_run = true;
+            config.report = Some(report.clone());
+        })
+        .unwrap();
+        assert!(contents.is_none());
+
+        // The report is still written
+        let report_json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(report_json["repetitions"], 7);
+        assert_eq!(
+            report_json["matches"][0]["entry"]["file"],
+            "bib_files/test_files/rep_in_file.bib"
+        );
+        fs::remove_file(&report).unwrap();
+    }
+
+    #[test]
+    fn test_run_twice_in_same_directory() {
+        // The output and the report of the first run are written to the input directory
+        let dir = std::env::temp_dir().join("bib_unifier_test_run_twice");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        fs::copy("bib_files/test_files/test.bib", dir.join("test.bib")).unwrap();
+        // Files with generic extensions are only read from directories if asked to
+        fs::write(dir.join("package.json"), "{\"name\": \"references\"}").unwrap();
+        let config = || Config {
+            inputs: vec![dir.clone()],
+            silent: true,
+            output: Some(dir.join("unified.bib")),
+            report: Some(dir.join("report.json")),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            run(config()).unwrap();
+            let file = fs::read_to_string(dir.join("unified.bib")).unwrap();
+            assert_eq!(Bibliography::parse(&file).unwrap().len(), 6);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_lenient() {
+        // The .json file is only read because it is given by name
+        let inputs = ["bib_files/lenient_files/", "bib_files/lenient_files/incomplete.json"];
+        assert!(run_on(&inputs, "test_run_lenient.bib", |_| {}).is_err());
+
+        let quarantine = std::env::temp_dir().join("bib_unifier_test_run_lenient_quarantine.bib");
+        let contents = run_on(&inputs, "test_run_lenient.bib", |config| {
+            config.lenient = true;
+            config.quarantine = Some(quarantine.clone());
+        })
+        .unwrap()
+        .unwrap();
+        let bibliography = Bibliography::parse(&contents).unwrap();
+        assert_eq!(bibliography.len(), 3);
+        assert!(bibliography.get("Dummett1973").is_some());
+        let quarantined = fs::read_to_string(&quarantine).unwrap();
+        assert!(quarantined.contains("@inproceedings{conigliocorbalan,"));
+        assert!(quarantined.contains("@article{Unclosed,"));
+        assert!(quarantined.contains("\"id\": \"empty\""));
+        fs::remove_file(&quarantine).unwrap();
+    }
+
+    #[test]
+    fn test_run_encodings() {
+        let contents = run_on(&["bib_files/encoding_files/"], "test_run_encodings.bib", |config| {
+            config.output_encoding = parse_output_encoding("latin1").unwrap();
+        })
+        .unwrap()
+        .unwrap();
+        let bibliography = Bibliography::parse(&contents).unwrap();
+        // All five files have the same title
+        assert_eq!(bibliography.len(), 1);
+        assert!(contents.contains("über den Begriff"));
+    }
+
+    #[test]
+    fn test_run_metadata() {
+        let contents = run_on(&["bib_files/metadata_files/"], "test_run_metadata.bib", |_| {})
+            .unwrap()
+            .unwrap();
+        assert_eq!(contents.matches("@string").count(), 2);
+
+        let mut bibliography = Bibliography::parse(&contents).unwrap();
+        remove_special_entries(&mut bibliography);
+        assert_eq!(bibliography.len(), 3);
+        // Prior1960 used b.bib's definition of anl, which was not kept
+        let prior = bibliography.get("Prior1960").unwrap();
+        assert_eq!(prior.journal().unwrap().format_verbatim(), "Analyses");
+    }
+
+    #[test]
+    fn test_run_csl_json() {
+        let inputs = ["bib_files/csl_files/references.json", "bib_files/test_files/test.bib"];
+        let contents = run_on(&inputs, "test_run_csl_json.bib", |_| {}).unwrap().unwrap();
+        let bibliography = Bibliography::parse(&contents).unwrap();
+        // Prior1960 and the BPS chapter are in both files
+        assert_eq!(bibliography.len(), 7);
+        assert!(bibliography.get("vonWright1951").is_some());
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut config = Config::default();
+        assert_eq!(config.writer().unwrap().name(), "bibtex");
+        config.output = Some(PathBuf::from("out/references.json"));
+        assert_eq!(config.writer().unwrap().name(), "csl-json");
+        // An explicit format wins over the extension
+        config.format = Some(String::from("biblatex"));
+        assert_eq!(config.writer().unwrap().name(), "biblatex");
+        config.format = Some(String::from("klingon"));
+        assert!(config.writer().is_err());
+        assert!(validate_format("ris").is_ok());
+        assert!(validate_format("klingon").is_err());
+        // Any extension is fine if the format is given
+        assert!(validate_output("output.txt").is_ok());
+        config.output = Some(PathBuf::from("output.txt"));
+        config.format = Some(String::from("ris"));
+        assert_eq!(config.writer().unwrap().name(), "ris");
+        config.format = None;
+        assert!(config.writer().is_err());
+        config.output = Some(PathBuf::from(STDIO_PATH));
+        assert_eq!(config.writer().unwrap().name(), "bibtex");
+    }
+
+    #[test]
+    fn test_run_custom_writer() {
+        struct KeysWriter;
+        impl BibliographyWriter for KeysWriter {
+            fn name(&self) -> &str {
+                "keys"
+            }
+            fn extension(&self) -> &str {
+                "txt"
+            }
+            fn write(&self, bibliography: &Bibliography, _metadata: &BibMetadata) -> String {
+                bibliography.iter().map(|entry| format!("{}\n", entry.key)).collect()
+            }
+        }
+
+        let keys = run_on(&["bib_files/crossref_files/"], "test_run_custom_writer.txt", |config| {
+            config.writers.register(Box::new(KeysWriter));
+        })
+        .unwrap()
+        .unwrap();
+        assert!(!keys.contains('@'));
+        assert!(keys.lines().count() > 1);
+    }
+
+    #[test]
+    fn test_stdio() {
+        assert!(validate_output("-").is_ok());
+        assert!(validate_output("output.bib").is_ok());
+        assert!(validate_output("output.json").is_ok());
+        assert!(validate_output("output.ris").is_ok());
+        assert!(validate_output("out/..").is_err());
+
+        let config = Config {
+            inputs: vec![PathBuf::from("bib_files/test_files/"), PathBuf::from("-")],
+            output: Some(PathBuf::from("-")),
+            ..Default::default()
+        };
+        assert!(config.reads_from_stdin());
+        assert!(config.writes_to_stdout());
+        assert!(!Config::default().reads_from_stdin());
+        assert!(!Config::default().writes_to_stdout());
+    }
+
+    #[test]
+    fn test_run_prefer() {
+        let inputs = [
+            "bib_files/test_files/test.bib",
+            "bib_files/test_files/only_same_title.bib",
+        ];
+
+        // By default, the first file (in alphabetical order) wins
+        let contents = run_on(&inputs, "test_run_prefer.bib", |_| {}).unwrap().unwrap();
+        let bibliography = Bibliography::parse(&contents).unwrap();
+        assert!(bibliography.get("Prior1960").is_none());
+        assert!(bibliography.get("Prior1961").is_some());
+
+        // Preferring test.bib keeps its entry instead
+        let contents = run_on(&inputs, "test_run_prefer.bib", |config| {
+            config.prefer = vec![PathBuf::from("test.bib")];
+        })
+        .unwrap()
+        .unwrap();
+        let bibliography = Bibliography::parse(&contents).unwrap();
+        assert!(bibliography.get("Prior1960").is_some());
+        assert!(bibliography.get("Prior1961").is_none());
+    }
 }
@@ -1,8 +1,6 @@
 use anyhow::{Result};
 use clap::Parser;
 
-use bib_unifier;
-
 fn main() -> Result<()>{
     let config = bib_unifier::Config::parse();
 
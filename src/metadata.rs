@@ -0,0 +1,256 @@
+use std::path::{Path, PathBuf};
+
+use biblatex::{Bibliography, ChunksExt, EntryType, RawBibliography};
+
+use super::{split_raw_blocks, InputFormat};
+
+/// A @string definition (macro), and the file where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringDefinition {
+    /// Name of the macro
+    pub name: String,
+    /// Value of the macro, as written in the file (e.g. `{Journal of Philosophical Logic}`)
+    pub value: String,
+    /// File where the macro was defined
+    pub filepath: PathBuf,
+}
+
+/// Two files define the same @string name with different values
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringConflict {
+    /// The definition that was found first, and is kept
+    pub kept: StringDefinition,
+    /// The definition that was found later, and is discarded
+    pub discarded: StringDefinition,
+}
+
+/// Everything in a set of .bib files that is not an entry: @string, @preamble and @comment blocks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BibMetadata {
+    /// @string definitions, without repetitions
+    pub strings: Vec<StringDefinition>,
+    /// Contents of the @preamble blocks, without repetitions
+    pub preambles: Vec<String>,
+    /// Contents of the @comment blocks, without repetitions
+    pub comments: Vec<String>,
+}
+
+impl BibMetadata {
+    /// Create new, empty metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether there is no metadata at all
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty() && self.preambles.is_empty() && self.comments.is_empty()
+    }
+
+    /// Add the @string, @preamble and @comment blocks of a file
+    ///
+    /// Blocks identical to previously added ones are skipped. Macro names are compared ignoring
+    /// case (as BibTeX does), and if a macro was already defined with a different value, the
+    /// first definition is kept and the conflict is returned.
+    pub fn add_file(&mut self, filepath: &Path, src: &str) -> Vec<StringConflict> {
+        let mut conflicts = vec![];
+        for block in split_raw_blocks(src).into_iter().filter(|block| block.closed) {
+            match block.block_type.to_lowercase().as_str() {
+                "string" => {
+                    let raw = RawBibliography::parse(block.text);
+                    for (name, value) in raw.abbreviations {
+                        let definition = StringDefinition {
+                            name: name.to_string(),
+                            value: value.trim().to_string(),
+                            filepath: filepath.to_path_buf(),
+                        };
+                        match self
+                            .strings
+                            .iter()
+                            .find(|prev| prev.name.eq_ignore_ascii_case(name))
+                        {
+                            Some(prev) if same_value(&prev.value, &definition.value) => {}
+                            Some(prev) => conflicts.push(StringConflict {
+                                kept: prev.clone(),
+                                discarded: definition,
+                            }),
+                            None => self.strings.push(definition),
+                        }
+                    }
+                }
+                "preamble" => push_unique(&mut self.preambles, block_contents(block.text)),
+                "comment" => push_unique(&mut self.comments, block_contents(block.text)),
+                _ => {}
+            }
+        }
+        conflicts
+    }
+
+    /// Serialize the metadata into the blocks that go at the top of the output file
+    /// (first the comments, then the preambles and then the macros)
+    pub fn to_bib_string(&self) -> String {
+        let mut header = String::new();
+        for comment in self.comments.iter() {
+            header.push_str(&format!("@comment{{{}}}\n\n", comment));
+        }
+        for preamble in self.preambles.iter() {
+            header.push_str(&format!("@preamble{{{}}}\n\n", preamble));
+        }
+        for string in self.strings.iter() {
+            header.push_str(&format!("@string{{{} = {}}}\n", string.name, string.value));
+        }
+        if !self.strings.is_empty() {
+            header.push('\n');
+        }
+        header
+    }
+
+    /// The name and value of each macro, expanded and serialized as the output would show it
+    /// (e.g. `("jpl", "{Journal of Philosophical Logic}")`)
+    pub fn expanded_strings(&self) -> Vec<(String, String)> {
+        // Macros may be defined in terms of other macros, so all definitions are needed
+        let definitions: String = self
+            .strings
+            .iter()
+            .map(|string| format!("@string{{{} = {}}}\n", string.name, string.value))
+            .collect();
+        self.strings
+            .iter()
+            .filter_map(|string| {
+                let src = format!("{}@misc{{key, field = {}}}", definitions, string.name);
+                let bibliography = Bibliography::parse(&src).ok()?;
+                let value = bibliography.get("key")?.get("field")?.to_biblatex_string();
+                Some((string.name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+/// Collect the metadata of a set of files, returning the @string conflicts found
+///
+/// Only BibTeX files (and stdin) have metadata: files in other formats (see
+/// `InputFormat::from_path`) are skipped, even if their text looks like an @-block.
+pub fn collect_metadata(
+    filepaths: &[PathBuf],
+    file_contents: &[String],
+) -> (BibMetadata, Vec<StringConflict>) {
+    let mut metadata = BibMetadata::new();
+    let mut conflicts = vec![];
+    for (filepath, file_content) in filepaths.iter().zip(file_contents) {
+        let format = InputFormat::from_path(filepath).unwrap_or(InputFormat::Bibtex);
+        if format == InputFormat::Bibtex {
+            conflicts.extend(metadata.add_file(filepath, file_content));
+        }
+    }
+    (metadata, conflicts)
+}
+
+/// Remove from a Bibliography the entries that biblatex creates for @preamble and @comment
+/// blocks (which are not actual entries, and would otherwise end up in the output)
+pub fn remove_special_entries(bibliography: &mut Bibliography) {
+    let special_keys: Vec<String> = bibliography
+        .iter()
+        .filter(|entry| is_special_entry_type(&entry.entry_type))
+        .map(|entry| entry.key.clone())
+        .collect();
+    for key in special_keys {
+        bibliography.remove(&key);
+    }
+}
+
+/// Whether an entry type is actually one of the non-entry blocks
+fn is_special_entry_type(entry_type: &EntryType) -> bool {
+    match entry_type {
+        EntryType::Unknown(name) => ["comment", "preamble", "string"]
+            .iter()
+            .any(|special| name.eq_ignore_ascii_case(special)),
+        _ => false,
+    }
+}
+
+/// The text of a block between its outer delimiters
+fn block_contents(text: &str) -> String {
+    let start = text.find(&['{', '('][..]).map_or(0, |idx| idx + 1);
+    let end = text.len().saturating_sub(1).max(start);
+    text[start..end].trim().to_string()
+}
+
+/// Two macro values are the same if they only differ in whitespace
+fn same_value(value1: &str, value2: &str) -> bool {
+    value1.split_whitespace().eq(value2.split_whitespace())
+}
+
+fn push_unique(blocks: &mut Vec<String>, block: String) {
+    if !blocks.contains(&block) {
+        blocks.push(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn setup() -> (BibMetadata, Vec<StringConflict>) {
+        let filepaths = vec![
+            PathBuf::from("bib_files/metadata_files/a.bib"),
+            PathBuf::from("bib_files/metadata_files/b.bib"),
+        ];
+        let files: Vec<String> = filepaths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        collect_metadata(&filepaths, &files)
+    }
+
+    #[test]
+    fn test_collect_metadata() {
+        let (metadata, conflicts) = setup();
+        assert_eq!(metadata.preambles, vec!["\"\\newcommand{\\noopsort}[1]{}\""]);
+        assert_eq!(metadata.comments, vec!["jabref-meta: databaseType:bibtex;"]);
+        let mut names: Vec<&str> = metadata.strings.iter().map(|s| s.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["anl", "jpl"]);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kept.name, "anl");
+        assert_eq!(conflicts[0].kept.value, "{Analysis}");
+        assert_eq!(conflicts[0].discarded.value, "{Analyses}");
+        assert_eq!(
+            conflicts[0].discarded.filepath,
+            PathBuf::from("bib_files/metadata_files/b.bib")
+        );
+
+        // Files in other formats have no metadata
+        let (metadata, _) = collect_metadata(
+            &[PathBuf::from("notes.ris")],
+            &[String::from("TY  - GEN\nN1  - @comment{not a comment}\nER  - \n")],
+        );
+        assert_eq!(metadata, BibMetadata::new());
+    }
+
+    #[test]
+    fn test_expanded_strings() {
+        let (metadata, _) = setup();
+        let expanded = metadata.expanded_strings();
+        assert!(expanded.contains(&(
+            String::from("jpl"),
+            String::from("{Journal of Philosophical Logic}")
+        )));
+    }
+
+    #[test]
+    fn test_to_bib_string() {
+        let (metadata, _) = setup();
+        let header = metadata.to_bib_string();
+        assert!(header.starts_with("@comment{jabref-meta: databaseType:bibtex;}\n\n@preamble{"));
+        // The header is itself valid, and defines the macros
+        let mut bibliography =
+            Bibliography::parse(&format!("{}@article{{x, journal = jpl}}", header)).unwrap();
+        remove_special_entries(&mut bibliography);
+        assert_eq!(bibliography.len(), 1);
+        assert_eq!(
+            bibliography.get("x").unwrap().journal().unwrap().format_verbatim(),
+            "Journal of Philosophical Logic"
+        );
+    }
+}
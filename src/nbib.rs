@@ -0,0 +1,279 @@
+use biblatex::{Chunk, Entry, EntryType};
+
+use super::{format_page_range, generate_citation_key, month_from_name, RecordError};
+
+/// Parse the contents of a PubMed/MEDLINE (.nbib) file, returning an entry (or an error) for each
+/// record
+///
+/// Records are separated by blank lines, and each line begins with a tag of up to four letters
+/// followed by "- " (e.g. "TI  - A title"). Lines that begin with spaces are the continuation of the
+/// previous one. The citation key is generated from the first author and the year, and the PMID and
+/// PMCID of the record are kept in the `pmid` and `pmcid` fields.
+pub fn parse_nbib(src: &str) -> Vec<Result<Entry, RecordError>> {
+    let mut records = vec![];
+    // Tags and values of the current record, and the line where it began
+    let mut record: Option<(usize, Vec<(String, String)>)> = None;
+    let mut record_lines: Vec<&str> = vec![];
+
+    for (idx, line) in src.lines().enumerate() {
+        let line_number = idx + 1;
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            if let Some((start, tags)) = record.take() {
+                records.push(finish_record(start, &tags, &record_lines));
+            }
+            record_lines.clear();
+            continue;
+        }
+        match split_tag(trimmed) {
+            Some((tag, value)) => {
+                record_lines.push(trimmed);
+                let (_, tags) = record.get_or_insert_with(|| (line_number, vec![]));
+                tags.push((tag.to_string(), value.to_string()));
+            }
+            None => match record.as_mut() {
+                // A continuation of the previous value
+                Some((_, tags)) if line.starts_with(' ') => {
+                    record_lines.push(trimmed);
+                    let value = &mut tags.last_mut().unwrap().1;
+                    value.push(' ');
+                    value.push_str(trimmed.trim());
+                }
+                _ => records.push(Err(RecordError {
+                    line: line_number,
+                    text: trimmed.to_string(),
+                    message: String::from("Line is not a MEDLINE tag"),
+                })),
+            },
+        }
+    }
+
+    if let Some((start, tags)) = record {
+        records.push(finish_record(start, &tags, &record_lines));
+    }
+    records
+}
+
+fn finish_record(
+    start: usize,
+    tags: &[(String, String)],
+    record_lines: &[&str],
+) -> Result<Entry, RecordError> {
+    record_to_entry(tags).ok_or_else(|| RecordError {
+        line: start,
+        text: record_lines.join("\n"),
+        message: String::from("Record has no author, title or year"),
+    })
+}
+
+/// Split a line into its tag and value (e.g. "PMID- 13054692" into "PMID" and "13054692")
+fn split_tag(line: &str) -> Option<(&str, &str)> {
+    let tag = line.get(..4)?.trim_end();
+    if tag.is_empty()
+        || !tag
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return None;
+    }
+    let value = line.get(4..)?.strip_prefix('-')?;
+    Some((tag, value.trim()))
+}
+
+/// Convert the tags of a MEDLINE record into an entry
+///
+/// Returns None if the record has no author, title or year at all.
+fn record_to_entry(tags: &[(String, String)]) -> Option<Entry> {
+    let get = |name: &str| -> Vec<&str> {
+        tags.iter()
+            .filter(|(tag, value)| tag == name && !value.is_empty())
+            .map(|(_, value)| value.as_str())
+            .collect()
+    };
+    let first = |name: &str| get(name).first().map(|value| value.to_string());
+
+    // Books and their chapters (from the NCBI Bookshelf) have a book title
+    let entry_type = match (first("TI"), first("BTI")) {
+        (Some(_), Some(_)) => EntryType::InCollection,
+        (None, Some(_)) => EntryType::Book,
+        _ => EntryType::Article,
+    };
+    let mut entry = Entry::new(String::new(), entry_type.clone());
+    let mut set = |field: &str, value: String| entry.set(field, vec![Chunk::Normal(value)]);
+
+    // The full names (FAU) are "Last, First", the short ones (AU) "Last Initials"
+    for (full, short, field) in [("FAU", "AU", "author"), ("FED", "ED", "editor")] {
+        let names: Vec<String> = match get(full).is_empty() {
+            false => get(full).into_iter().map(str::to_string).collect(),
+            true => get(short).into_iter().map(short_name_to_bibtex).collect(),
+        };
+        if !names.is_empty() {
+            set(field, names.join(" and "));
+        }
+    }
+
+    // Titles end with a period, which is not part of the title
+    let title = |title: String| match title.strip_suffix('.') {
+        Some(title) => title.to_string(),
+        None => title,
+    };
+    match entry_type {
+        EntryType::Article => {
+            if let Some(article_title) = first("TI") {
+                set("title", title(article_title));
+            }
+            if let Some(journal) = first("JT").or_else(|| first("TA")) {
+                set("journal", journal);
+            }
+        }
+        EntryType::InCollection => {
+            set("title", title(first("TI").unwrap_or_default()));
+            set("booktitle", title(first("BTI").unwrap_or_default()));
+        }
+        _ => set("title", title(first("BTI").unwrap_or_default())),
+    }
+    if let Some(series) = first("CTI") {
+        set("series", title(series));
+    }
+
+    // The publication date is written as e.g. "1953 Apr 25", "1960 Dec" or "2018 Spring"
+    let date = first("DP").unwrap_or_default();
+    let mut date_parts = date.split_whitespace();
+    let year = date_parts
+        .next()
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()));
+    let month = date_parts.next().and_then(month_from_name);
+    let day = date_parts.next().and_then(|day| day.parse::<u8>().ok());
+    match (year, month, day) {
+        (Some(year), Some(month), Some(day)) => {
+            set("date", format!("{}-{:02}-{:02}", year, month, day))
+        }
+        (Some(year), month, _) => {
+            set("year", year.to_string());
+            if let Some(month) = month {
+                set("month", month.to_string());
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(pages) = first("PG") {
+        set("pages", format_page_range(&pages));
+    }
+    for (tag, field) in [
+        ("VI", "volume"),
+        ("IP", "number"),
+        ("EN", "edition"),
+        ("PB", "publisher"),
+        ("CI", "address"),
+        ("LA", "language"),
+        ("AB", "abstract"),
+        ("PMID", "pmid"),
+        ("PMC", "pmcid"),
+    ] {
+        if let Some(value) = first(tag) {
+            set(field, value);
+        }
+    }
+    // ISSNs and ISBNs are followed by the medium, e.g. "0028-0836 (Print)"
+    for (tag, field) in [("IS", "issn"), ("ISBN", "isbn")] {
+        if let Some(serial_number) = first(tag) {
+            let serial_number = serial_number.split(" (").next().unwrap_or_default();
+            set(field, serial_number.to_string());
+        }
+    }
+    // Article identifiers are followed by their kind, e.g. "10.1038/171737a0 [doi]"
+    let doi = get("LID")
+        .into_iter()
+        .chain(get("AID"))
+        .find_map(|id| id.strip_suffix("[doi]"));
+    if let Some(doi) = doi {
+        set("doi", doi.trim().to_string());
+    }
+    let keywords = get("OT");
+    if !keywords.is_empty() {
+        set("keywords", keywords.join(", "));
+    }
+
+    if entry.get("author").is_none()
+        && entry.get("title").is_none()
+        && entry.get("year").is_none()
+        && entry.get("date").is_none()
+    {
+        return None;
+    }
+    entry.key = generate_citation_key(&entry);
+    Some(entry)
+}
+
+/// Convert a short MEDLINE name (e.g. "van der Berg M" or "Watson JD") into BibTeX format
+/// ("van der Berg, M." or "Watson, J. D.")
+fn short_name_to_bibtex(name: &str) -> String {
+    match name.rsplit_once(' ') {
+        Some((last_name, initials))
+            if initials.chars().all(|c| c.is_ascii_uppercase()) && !last_name.is_empty() =>
+        {
+            let initials: Vec<String> = initials.chars().map(|c| format!("{}.", c)).collect();
+            format!("{}, {}", last_name, initials.join(" "))
+        }
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biblatex::ChunksExt;
+    use std::fs;
+
+    #[test]
+    fn test_parse_nbib() {
+        let src = fs::read_to_string("bib_files/nbib_files/pubmed.nbib").unwrap();
+        let entries = parse_nbib(&src);
+        assert_eq!(entries.len(), 3);
+
+        let article = entries[0].as_ref().unwrap();
+        assert_eq!(article.key, "Watson1953");
+        assert_eq!(article.entry_type, EntryType::Article);
+        assert_eq!(
+            article.title().unwrap().format_verbatim(),
+            "Molecular structure of nucleic acids; a structure for deoxyribose nucleic acid"
+        );
+        assert_eq!(article.author().unwrap()[1].name, "Crick");
+        assert_eq!(article.get("date").unwrap().format_verbatim(), "1953-04-25");
+        assert_eq!(article.get("pages").unwrap().format_verbatim(), "737--738");
+        assert_eq!(article.get("issn").unwrap().format_verbatim(), "0028-0836");
+        assert_eq!(article.get("pmid").unwrap().format_verbatim(), "13054692");
+        assert_eq!(article.doi().unwrap(), "10.1038/171737a0");
+
+        let article = entries[1].as_ref().unwrap();
+        assert_eq!(article.author().unwrap()[1].prefix, "van der");
+        assert_eq!(article.author().unwrap()[1].given_name, "M.");
+        assert_eq!(
+            article.get("pmcid").unwrap().format_verbatim(),
+            "PMC6193465"
+        );
+        assert_eq!(article.doi().unwrap(), "10.1371/journal.pone.0205421");
+        assert_eq!(
+            article.get("abstract").unwrap().format_verbatim(),
+            "The abstract of the article spans more than one line."
+        );
+
+        let error = entries[2].as_ref().unwrap_err();
+        assert_eq!(error.line, 51);
+        assert_eq!(error.message, "Record has no author, title or year");
+
+        let error = parse_nbib("Not a tag").remove(0).unwrap_err();
+        assert_eq!(error.message, "Line is not a MEDLINE tag");
+    }
+
+    #[test]
+    fn test_short_name_to_bibtex() {
+        assert_eq!(short_name_to_bibtex("Watson JD"), "Watson, J. D.");
+        assert_eq!(short_name_to_bibtex("van der Berg M"), "van der Berg, M.");
+        assert_eq!(
+            short_name_to_bibtex("World Health Organization"),
+            "World Health Organization"
+        );
+    }
+}
@@ -0,0 +1,547 @@
+use clap::ArgEnum;
+use unicode_normalization::UnicodeNormalization;
+
+use super::format_page_range;
+
+/// A step of the normalization of titles before comparing them
+///
+/// The steps are always applied in the order in which they are listed here, whatever the order in
+/// which they are given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum TitleNormalization {
+    /// Turn LaTeX accents and symbols into Unicode characters (`{\"o}` into `ö`, `\ss` into `ß`)
+    /// and drop other commands (`\emph{word}` into `word`)
+    Latex,
+    /// Unicode NFKC normalization (e.g. the ligature `ﬁ` into `fi`)
+    Unicode,
+    /// Remove braces (`{{Title}}` into `Title`)
+    Braces,
+    /// Turn every letter into lowercase
+    Case,
+    /// Turn punctuation (including hyphens and quotes) into spaces
+    Punctuation,
+    /// Collapse runs of whitespace into a single space, and trim the ends
+    Whitespace,
+    /// Remove a leading article ("a", "an" or "the")
+    Articles,
+    /// Remove common English words ("of", "and", "the", etc.)
+    StopWords,
+    /// No normalization at all (the titles are compared as they are)
+    None,
+}
+
+/// The steps of title normalization applied by default (all but `Articles` and `StopWords`)
+pub const DEFAULT_TITLE_NORMALIZATION: [TitleNormalization; 6] = [
+    TitleNormalization::Latex,
+    TitleNormalization::Unicode,
+    TitleNormalization::Braces,
+    TitleNormalization::Case,
+    TitleNormalization::Punctuation,
+    TitleNormalization::Whitespace,
+];
+
+/// Leading articles removed by `TitleNormalization::Articles`
+const ARTICLES: [&str; 3] = ["a", "an", "the"];
+
+/// Words removed by `TitleNormalization::StopWords`
+const STOP_WORDS: [&str; 26] = [
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "into", "is", "it",
+    "its", "of", "on", "or", "that", "the", "this", "to", "was", "were", "with", "without",
+];
+
+/// Check that a list of normalization steps makes sense: `None` cannot be combined with any other
+/// step, since it means that the titles are compared as they are
+pub fn validate_title_normalization(steps: &[TitleNormalization]) -> Result<(), String> {
+    match steps.contains(&TitleNormalization::None) && steps.len() > 1 {
+        true => Err(String::from(
+            "The title normalization step none cannot be combined with other steps",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Normalize a title with the given steps, so that titles that differ only in those respects are
+/// compared as equal
+///
+/// For example, with `DEFAULT_TITLE_NORMALIZATION`, both `{{What is a Paraconsistent Logic?}}` and
+/// `What is a paraconsistent logic` become `what is a paraconsistent logic`.
+pub fn normalize_title(title: &str, steps: &[TitleNormalization]) -> String {
+    let has = |step: TitleNormalization| steps.contains(&step);
+    let mut title = title.to_string();
+    if has(TitleNormalization::Latex) {
+        title = latex_to_unicode(&title);
+    }
+    if has(TitleNormalization::Unicode) {
+        title = title.nfkc().collect();
+    }
+    if has(TitleNormalization::Braces) {
+        title = title.chars().filter(|c| *c != '{' && *c != '}').collect();
+    }
+    if has(TitleNormalization::Case) {
+        title = title.to_lowercase();
+    }
+    if has(TitleNormalization::Punctuation) {
+        title = title
+            .chars()
+            .map(|c| match c.is_alphanumeric() || c.is_whitespace() {
+                true => c,
+                false => ' ',
+            })
+            .collect();
+    }
+    if has(TitleNormalization::Whitespace) {
+        title = title.split_whitespace().collect::<Vec<&str>>().join(" ");
+    }
+    if has(TitleNormalization::Articles) {
+        if let Some((first_word, rest)) = title.trim_start().split_once(char::is_whitespace) {
+            if ARTICLES
+                .iter()
+                .any(|article| article.eq_ignore_ascii_case(first_word))
+            {
+                title = rest.trim_start().to_string();
+            }
+        }
+    }
+    if has(TitleNormalization::StopWords) {
+        title = title
+            .split_whitespace()
+            .filter(|word| {
+                !STOP_WORDS
+                    .iter()
+                    .any(|stop| stop.eq_ignore_ascii_case(word))
+            })
+            .collect::<Vec<&str>>()
+            .join(" ");
+    }
+    title
+}
+
+/// The combining character for a LaTeX accent command (e.g. the diaeresis for `\"`)
+fn latex_accent(command: &str) -> Option<char> {
+    let accent = match command {
+        "`" => '\u{300}',
+        "'" => '\u{301}',
+        "^" => '\u{302}',
+        "~" => '\u{303}',
+        "=" => '\u{304}',
+        "u" => '\u{306}',
+        "." => '\u{307}',
+        "\"" => '\u{308}',
+        "r" => '\u{30A}',
+        "H" => '\u{30B}',
+        "v" => '\u{30C}',
+        "d" => '\u{323}',
+        "c" => '\u{327}',
+        "k" => '\u{328}',
+        "b" => '\u{331}',
+        _ => return None,
+    };
+    Some(accent)
+}
+
+/// The text for a LaTeX command without arguments (e.g. `ß` for `\ss`)
+fn latex_symbol(command: &str) -> Option<&'static str> {
+    let symbol = match command {
+        "ss" => "ß",
+        "ae" => "æ",
+        "AE" => "Æ",
+        "oe" => "œ",
+        "OE" => "Œ",
+        "o" => "ø",
+        "O" => "Ø",
+        "aa" => "å",
+        "AA" => "Å",
+        "l" => "ł",
+        "L" => "Ł",
+        "i" => "ı",
+        "j" => "ȷ",
+        "&" => "&",
+        "%" => "%",
+        "$" => "$",
+        "#" => "#",
+        "_" => "_",
+        "{" => "{",
+        "}" => "}",
+        " " | "," | ";" | ":" => " ",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+/// Turn the LaTeX accents and symbols of a text into Unicode characters, dropping other commands
+/// (but not their arguments), math delimiters and non-breaking spaces (`~`)
+pub fn latex_to_unicode(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        match chars[idx] {
+            '\\' if idx + 1 < chars.len() => {
+                // The name of a command is either a run of letters or a single other character
+                let start = idx + 1;
+                let mut end = start + 1;
+                if chars[start].is_ascii_alphabetic() {
+                    while end < chars.len() && chars[end].is_ascii_alphabetic() {
+                        end += 1;
+                    }
+                }
+                let command: String = chars[start..end].iter().collect();
+                idx = end;
+                if chars[start].is_ascii_alphabetic() {
+                    while idx < chars.len() && chars[idx] == ' ' {
+                        idx += 1;
+                    }
+                }
+                if let Some(accent) = latex_accent(&command) {
+                    // The accented letter is either the next character or a group in braces
+                    let argument = match chars.get(idx) {
+                        Some('{') => {
+                            let mut depth = 0;
+                            let group_start = idx + 1;
+                            while idx < chars.len() {
+                                match chars[idx] {
+                                    '{' => depth += 1,
+                                    '}' => depth -= 1,
+                                    _ => {}
+                                }
+                                idx += 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            // The closing brace is not part of the group (if there is one)
+                            let group_end = match depth {
+                                0 => idx - 1,
+                                _ => idx,
+                            };
+                            chars[group_start..group_end].iter().collect::<String>()
+                        }
+                        Some(c) => {
+                            idx += 1;
+                            c.to_string()
+                        }
+                        None => String::new(),
+                    };
+                    let argument = latex_to_unicode(&argument);
+                    let mut argument = argument.chars();
+                    if let Some(letter) = argument.next() {
+                        // Dotless i and j are only used to put accents on them
+                        let letter = match letter {
+                            'ı' => 'i',
+                            'ȷ' => 'j',
+                            letter => letter,
+                        };
+                        let accented: String = [letter, accent].iter().collect();
+                        result.extend(accented.nfc());
+                        result.extend(argument);
+                    }
+                } else if let Some(symbol) = latex_symbol(&command) {
+                    result.push_str(symbol);
+                }
+            }
+            '~' => {
+                result.push(' ');
+                idx += 1;
+            }
+            '$' => idx += 1,
+            c => {
+                result.push(c);
+                idx += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Remove the first of the prefixes that the text begins with (if any)
+fn strip_prefixes<'a>(text: &'a str, prefixes: &[&str]) -> &'a str {
+    prefixes
+        .iter()
+        .find_map(|prefix| text.strip_prefix(prefix))
+        .unwrap_or(text)
+}
+
+/// Normalize a DOI: without the resolver or "doi:" prefix, and in lowercase (as DOIs are
+/// case-insensitive), e.g. "https://doi.org/10.1000/XYZ" into "10.1000/xyz"
+///
+/// Returns None if what remains is not a DOI (which always begins with "10.").
+pub fn normalize_doi(doi: &str) -> Option<String> {
+    let doi = doi.trim().to_lowercase();
+    let doi = strip_prefixes(&doi, &["https://", "http://"]);
+    let doi = strip_prefixes(doi, &["dx.doi.org/", "www.doi.org/", "doi.org/", "doi:"]);
+    let doi = doi.trim();
+    match doi.starts_with("10.") {
+        true => Some(doi.to_string()),
+        false => None,
+    }
+}
+
+/// Normalize the ISBNs in a field (separated by commas or semicolons) to ISBN-13, without hyphens
+/// or spaces, e.g. "0-306-40615-2" into "9780306406157"
+///
+/// Values that are not ISBN-10s or ISBN-13s are left out.
+pub fn normalize_isbns(isbns: &str) -> Vec<String> {
+    isbns
+        .split([',', ';'])
+        .filter_map(|isbn| {
+            let isbn: String = isbn
+                .to_uppercase()
+                .trim()
+                .trim_start_matches("ISBN")
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect();
+            let digits = |isbn: &str| isbn.chars().all(|c| c.is_ascii_digit());
+            match isbn.len() {
+                13 if digits(&isbn) => Some(isbn),
+                10 if digits(&isbn[..9]) && (digits(&isbn[9..]) || &isbn[9..] == "X") => {
+                    // The ISBN-13 is the ISBN-10 after "978", with a new check digit
+                    let isbn = format!("978{}", &isbn[..9]);
+                    let sum: u32 = isbn
+                        .chars()
+                        .filter_map(|c| c.to_digit(10))
+                        .enumerate()
+                        .map(|(idx, digit)| if idx % 2 == 0 { digit } else { digit * 3 })
+                        .sum();
+                    Some(format!("{}{}", isbn, (10 - sum % 10) % 10))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Normalize the ISSNs in a field (e.g. "0028-0836 (Print)" or "00223611, 15730433") to eight
+/// characters without hyphens, in uppercase
+pub fn normalize_issns(issns: &str) -> Vec<String> {
+    issns
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter_map(|issn| {
+            let issn: String = issn
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_uppercase();
+            let valid = issn.len() == 8
+                && issn[..7].chars().all(|c| c.is_ascii_digit())
+                && issn[7..].chars().all(|c| c.is_ascii_digit() || c == 'X');
+            match valid {
+                true => Some(issn),
+                false => None,
+            }
+        })
+        .collect()
+}
+
+/// Normalize a PubMed identifier (a PMID or PMCID): without the "PMID:" or "PMCID:" prefix, and
+/// in uppercase, e.g. "pmcid: pmc6193465" into "PMC6193465"
+///
+/// Returns None if nothing remains.
+pub fn normalize_pubmed_id(id: &str) -> Option<String> {
+    let id = id.trim().to_uppercase();
+    let id = strip_prefixes(&id, &["PMID:", "PMCID:"]).trim();
+    match id.is_empty() {
+        true => None,
+        false => Some(id.to_string()),
+    }
+}
+
+/// Normalize a page range so that the ways of writing it can be compared, e.g. "451--461",
+/// "451-461" and "451-61" into "451-461"
+pub fn normalize_pages(pages: &str) -> String {
+    format_page_range(pages)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+        .to_lowercase()
+}
+
+/// Normalize an arXiv identifier: without the "arXiv:" prefix or the URL it is part of, without
+/// its version and in lowercase, e.g. "https://arxiv.org/abs/2101.00001v2" into "2101.00001", or
+/// "arXiv:math.GT/0309136v1" into "math.gt/0309136"
+///
+/// Both the current scheme ("YYMM.NNNNN") and the one used until 2007 ("archive/YYMMNNN") are
+/// recognized. Returns None if the text is not an arXiv identifier.
+pub fn normalize_arxiv_id(id: &str) -> Option<String> {
+    let id = id.trim().to_lowercase();
+    let id = strip_prefixes(&id, &["https://", "http://"]);
+    let id = strip_prefixes(id, &["www.", "export."]);
+    let id = strip_prefixes(id, &["arxiv.org/abs/", "arxiv.org/pdf/", "arxiv:"]);
+    let id = id.trim_end_matches('/').trim_end_matches(".pdf");
+    // The version is a "v" followed by a number at the end
+    let id = match id.rfind('v') {
+        Some(idx) if idx + 1 < id.len() && id[idx + 1..].chars().all(|c| c.is_ascii_digit()) => {
+            &id[..idx]
+        }
+        _ => id,
+    };
+    let digits = |text: &str, lengths: &[usize]| {
+        lengths.contains(&text.len()) && text.chars().all(|c| c.is_ascii_digit())
+    };
+    let valid = match id.split_once('/') {
+        // The archive may have a subject class, e.g. "math.GT"
+        Some((archive, number)) => {
+            !archive.is_empty()
+                && archive
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c == '-' || c == '.')
+                && digits(number, &[7])
+        }
+        None => match id.split_once('.') {
+            Some((date, number)) => digits(date, &[4]) && digits(number, &[4, 5]),
+            None => false,
+        },
+    };
+    match valid {
+        true => Some(id.to_string()),
+        false => None,
+    }
+}
+
+/// Normalize a URL: without the scheme, "www.", fragment or trailing slash, and in lowercase
+/// (except for its path and query), e.g. "https://www.Example.org/Paper/#top" into
+/// "example.org/Paper"
+pub fn normalize_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    let url = match url.find("://") {
+        Some(idx) => &url[idx + 3..],
+        None => url,
+    };
+    let url = url.split('#').next().unwrap_or_default();
+    let (host, path) = match url.find(['/', '?']) {
+        Some(idx) => url.split_at(idx),
+        None => (url, ""),
+    };
+    let host = host.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let url = format!("{}{}", host, path.trim_end_matches('/'));
+    match host.contains('.') {
+        true => Some(url),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_title() {
+        let normalize = |title| normalize_title(title, &DEFAULT_TITLE_NORMALIZATION);
+        assert_eq!(
+            normalize("{{What is a paraconsistent logic?}}"),
+            normalize("What is a Paraconsistent Logic?")
+        );
+        assert_eq!(
+            normalize("G{\\\"o}del's \\emph{Incompleteness}  Theorems."),
+            "gödel s incompleteness theorems"
+        );
+        assert_eq!(normalize("The ﬁrst---and~last"), "the first and last");
+        assert_eq!(
+            normalize_title("{A} Title", &[TitleNormalization::None]),
+            "{A} Title"
+        );
+
+        let steps = [
+            TitleNormalization::Case,
+            TitleNormalization::Whitespace,
+            TitleNormalization::Articles,
+        ];
+        assert_eq!(
+            normalize_title("An Introduction to Semantics", &steps),
+            "introduction to semantics"
+        );
+        assert_eq!(normalize_title("Anselm's proof", &steps), "anselm's proof");
+        let steps = [TitleNormalization::StopWords];
+        assert_eq!(
+            normalize_title("The Logic of Questions and Answers", &steps),
+            "Logic Questions Answers"
+        );
+    }
+
+    #[test]
+    fn test_validate_title_normalization() {
+        assert!(validate_title_normalization(&DEFAULT_TITLE_NORMALIZATION).is_ok());
+        assert!(validate_title_normalization(&[TitleNormalization::None]).is_ok());
+        assert!(validate_title_normalization(&[
+            TitleNormalization::None,
+            TitleNormalization::None
+        ])
+        .is_err());
+        assert!(validate_title_normalization(&[
+            TitleNormalization::Case,
+            TitleNormalization::None
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_latex_to_unicode() {
+        assert_eq!(
+            latex_to_unicode("{\\\"o}\\\"{u}\\'e\\`a\\^o\\~n"),
+            "{ö}üéàôñ"
+        );
+        assert_eq!(
+            latex_to_unicode("\\c{c}\\v c \\ss{} \\aa \\'{\\i}"),
+            "çč ß{} åí"
+        );
+        assert_eq!(latex_to_unicode("$x^2$ \\& \\textit{y}"), "x^2 & {y}");
+    }
+
+    #[test]
+    fn test_normalize_identifiers() {
+        for doi in [
+            "10.1000/XYZ",
+            "https://doi.org/10.1000/xyz",
+            "http://dx.doi.org/10.1000/xyz",
+            "doi:10.1000/xyz",
+        ] {
+            assert_eq!(normalize_doi(doi).unwrap(), "10.1000/xyz");
+        }
+        assert_eq!(normalize_doi("not a doi"), None);
+
+        assert_eq!(normalize_isbns("0-306-40615-2"), vec!["9780306406157"]);
+        assert_eq!(
+            normalize_isbns("978-0-306-40615-7; ISBN 0-8044-2957-X"),
+            vec!["9780306406157", "9780804429573"]
+        );
+        assert!(normalize_isbns("12345").is_empty());
+        assert_eq!(
+            normalize_issns("0028-0836 (Print)"),
+            vec![String::from("00280836")]
+        );
+        assert_eq!(normalize_issns("00223611, 1573-043x").len(), 2);
+        assert_eq!(normalize_pages("451--461"), normalize_pages("451-61"));
+        assert_eq!(normalize_pubmed_id("PMID: 13054692").unwrap(), "13054692");
+        assert_eq!(normalize_pubmed_id("pmc6193465").unwrap(), "PMC6193465");
+        assert_eq!(normalize_pubmed_id("PMCID:"), None);
+
+        for id in [
+            "2101.00001",
+            "arXiv:2101.00001v2",
+            "https://arxiv.org/abs/2101.00001v1",
+            "http://arxiv.org/pdf/2101.00001v3.pdf",
+        ] {
+            assert_eq!(normalize_arxiv_id(id).unwrap(), "2101.00001");
+        }
+        assert_eq!(
+            normalize_arxiv_id("arXiv:math.GT/0309136v1").unwrap(),
+            "math.gt/0309136"
+        );
+        assert_eq!(
+            normalize_arxiv_id("hep-th/9901001").unwrap(),
+            "hep-th/9901001"
+        );
+        assert_eq!(normalize_arxiv_id("https://example.org/paper.pdf"), None);
+
+        assert_eq!(
+            normalize_url("https://www.Example.org/Paper/#top").unwrap(),
+            "example.org/Paper"
+        );
+        assert_eq!(
+            normalize_url("http://example.org/Paper"),
+            normalize_url("example.org/Paper/")
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::fmt;
+
+use biblatex::{Bibliography, Entry};
+
+use super::{
+    dropped_hayagriva_fields, dropped_ris_fields, to_csl_json_string, to_hayagriva_string,
+    to_ris_string, BibDialect, BibMetadata, FormatOptions,
+};
+
+/// A format in which the unified bibliography can be written
+///
+/// Implement this trait and add it to the `writers` of the `Config` (with
+/// `WriterRegistry::register`) to write the bibliography in a format of your own.
+pub trait BibliographyWriter {
+    /// Name used to choose the format (with `--format`)
+    fn name(&self) -> &str;
+
+    /// Extension of the files written in this format (without the dot), used to infer the format
+    /// from the output path and to name the default output file
+    fn extension(&self) -> &str;
+
+    /// Whether files with the given extension (without the dot) are written in this format
+    fn matches_extension(&self, extension: &str) -> bool {
+        self.extension().eq_ignore_ascii_case(extension)
+    }
+
+    /// Serialize the unified bibliography, along with the @string, @preamble and @comment blocks
+    /// of the input files (which formats other than BibTeX may ignore)
+    fn write(&self, bibliography: &Bibliography, metadata: &BibMetadata) -> String;
+
+    /// Show a single entry, as when asking the user which of two repeated entries to keep
+    fn format_entry(&self, entry: &Entry) -> String {
+        let mut bibliography = Bibliography::new();
+        bibliography.insert(entry.clone());
+        self.write(&bibliography, &BibMetadata::new())
+    }
+
+    /// Warnings about the information that will be lost when writing the bibliography
+    fn warnings(&self, _bibliography: &Bibliography) -> Vec<String> {
+        vec![]
+    }
+
+    /// Use the given layout options (field order, indentation, etc.), for formats that have any
+    fn set_format_options(&mut self, _options: &FormatOptions) {}
+}
+
+/// BibTeX format (`@article{key, journal = {...}, ...}`), laid out according to its options
+#[derive(Debug, Clone, Default)]
+pub struct BibtexWriter {
+    pub options: FormatOptions,
+}
+
+impl BibliographyWriter for BibtexWriter {
+    fn name(&self) -> &str {
+        "bibtex"
+    }
+
+    fn extension(&self) -> &str {
+        "bib"
+    }
+
+    fn write(&self, bibliography: &Bibliography, metadata: &BibMetadata) -> String {
+        write_bib(bibliography, metadata, BibDialect::Bibtex, &self.options)
+    }
+
+    fn format_entry(&self, entry: &Entry) -> String {
+        self.options.format_entry(entry, BibDialect::Bibtex, &[])
+    }
+
+    fn set_format_options(&mut self, options: &FormatOptions) {
+        self.options = options.clone();
+    }
+}
+
+/// BibLaTeX format, which uses some different field names (e.g. `journaltitle` instead of
+/// `journal`) and keeps dates in a single `date` field
+#[derive(Debug, Clone, Default)]
+pub struct BiblatexWriter {
+    pub options: FormatOptions,
+}
+
+impl BibliographyWriter for BiblatexWriter {
+    fn name(&self) -> &str {
+        "biblatex"
+    }
+
+    fn extension(&self) -> &str {
+        "bib"
+    }
+
+    fn write(&self, bibliography: &Bibliography, metadata: &BibMetadata) -> String {
+        write_bib(bibliography, metadata, BibDialect::Biblatex, &self.options)
+    }
+
+    fn format_entry(&self, entry: &Entry) -> String {
+        self.options.format_entry(entry, BibDialect::Biblatex, &[])
+    }
+
+    fn set_format_options(&mut self, options: &FormatOptions) {
+        self.options = options.clone();
+    }
+}
+
+/// CSL-JSON format, as used by Pandoc and Zotero (see `to_csl_json_string`)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CslJsonWriter;
+
+impl BibliographyWriter for CslJsonWriter {
+    fn name(&self) -> &str {
+        "csl-json"
+    }
+
+    fn extension(&self) -> &str {
+        "json"
+    }
+
+    // CSL has no macros, so the metadata is left out (the values are already expanded)
+    fn write(&self, bibliography: &Bibliography, _metadata: &BibMetadata) -> String {
+        to_csl_json_string(bibliography)
+    }
+}
+
+/// RIS format, as read by EndNote and most other reference managers (see `to_ris_string`)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RisWriter;
+
+impl BibliographyWriter for RisWriter {
+    fn name(&self) -> &str {
+        "ris"
+    }
+
+    fn extension(&self) -> &str {
+        "ris"
+    }
+
+    fn write(&self, bibliography: &Bibliography, _metadata: &BibMetadata) -> String {
+        to_ris_string(bibliography)
+    }
+
+    fn warnings(&self, bibliography: &Bibliography) -> Vec<String> {
+        let dropped = dropped_ris_fields(bibliography);
+        match dropped.is_empty() {
+            true => vec![],
+            false => vec![format!(
+                "The following fields have no RIS equivalent and were left out: {}",
+                dropped.join(", ")
+            )],
+        }
+    }
+}
+
+/// Hayagriva YAML format, as used by Typst (see `to_hayagriva_string`)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HayagrivaWriter;
+
+impl BibliographyWriter for HayagrivaWriter {
+    fn name(&self) -> &str {
+        "hayagriva"
+    }
+
+    fn extension(&self) -> &str {
+        "yml"
+    }
+
+    fn matches_extension(&self, extension: &str) -> bool {
+        extension.eq_ignore_ascii_case("yml") || extension.eq_ignore_ascii_case("yaml")
+    }
+
+    fn write(&self, bibliography: &Bibliography, _metadata: &BibMetadata) -> String {
+        to_hayagriva_string(bibliography)
+    }
+
+    fn warnings(&self, bibliography: &Bibliography) -> Vec<String> {
+        let dropped = dropped_hayagriva_fields(bibliography);
+        match dropped.is_empty() {
+            true => vec![],
+            false => vec![format!(
+                "The following fields have no Hayagriva equivalent and were left out: {}",
+                dropped.join(", ")
+            )],
+        }
+    }
+}
+
+/// The metadata goes at the top, and the macros it defines replace their expanded values
+fn write_bib(
+    bibliography: &Bibliography,
+    metadata: &BibMetadata,
+    dialect: BibDialect,
+    options: &FormatOptions,
+) -> String {
+    format!(
+        "{}{}",
+        metadata.to_bib_string(),
+        options.format_bibliography(bibliography, dialect, &metadata.expanded_strings())
+    )
+}
+
+/// The formats the unified bibliography can be written in
+///
+/// The default registry has the built-in formats: bibtex, biblatex, csl-json, ris and
+/// hayagriva.
+pub struct WriterRegistry {
+    writers: Vec<Box<dyn BibliographyWriter>>,
+}
+
+impl WriterRegistry {
+    /// Create a registry with the built-in formats
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a format, replacing any format with the same name
+    ///
+    /// Formats added later only take precedence when inferring the format from an extension if
+    /// they replace a previous one (e.g. .bib files are written as bibtex unless "bibtex" itself is
+    /// replaced).
+    pub fn register(&mut self, writer: Box<dyn BibliographyWriter>) {
+        match self.writers.iter().position(|w| w.name() == writer.name()) {
+            Some(idx) => self.writers[idx] = writer,
+            None => self.writers.push(writer),
+        }
+    }
+
+    /// Pass the layout options to every format (see `BibliographyWriter::set_format_options`)
+    pub fn set_format_options(&mut self, options: &FormatOptions) {
+        for writer in self.writers.iter_mut() {
+            writer.set_format_options(options);
+        }
+    }
+
+    /// Get a format by its name
+    pub fn get(&self, name: &str) -> Option<&dyn BibliographyWriter> {
+        self.writers
+            .iter()
+            .find(|writer| writer.name().eq_ignore_ascii_case(name))
+            .map(|writer| writer.as_ref())
+    }
+
+    /// Get the first format whose files have the given extension
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn BibliographyWriter> {
+        self.writers
+            .iter()
+            .find(|writer| writer.matches_extension(extension))
+            .map(|writer| writer.as_ref())
+    }
+
+    /// Names of all the formats, in the order they were added
+    pub fn names(&self) -> Vec<&str> {
+        self.writers.iter().map(|writer| writer.name()).collect()
+    }
+
+    /// Extensions of all the formats, without repetitions
+    pub fn extensions(&self) -> Vec<&str> {
+        let mut extensions: Vec<&str> = vec![];
+        for writer in self.writers.iter() {
+            if !extensions.contains(&writer.extension()) {
+                extensions.push(writer.extension());
+            }
+        }
+        extensions
+    }
+}
+
+impl Default for WriterRegistry {
+    fn default() -> Self {
+        WriterRegistry {
+            writers: vec![
+                Box::new(BibtexWriter::default()),
+                Box::new(BiblatexWriter::default()),
+                Box::new(CslJsonWriter),
+                Box::new(RisWriter),
+                Box::new(HayagrivaWriter),
+            ],
+        }
+    }
+}
+
+impl fmt::Debug for WriterRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{collect_metadata, remove_special_entries};
+    use std::fs;
+    use std::path::PathBuf;
+
+    struct KeysWriter;
+
+    impl BibliographyWriter for KeysWriter {
+        fn name(&self) -> &str {
+            "keys"
+        }
+
+        fn extension(&self) -> &str {
+            "txt"
+        }
+
+        fn write(&self, bibliography: &Bibliography, _metadata: &BibMetadata) -> String {
+            bibliography
+                .iter()
+                .map(|entry| format!("{}\n", entry.key))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_writer_registry() {
+        let mut registry = WriterRegistry::new();
+        assert_eq!(registry.names(), vec!["bibtex", "biblatex", "csl-json", "ris", "hayagriva"]);
+        assert_eq!(registry.extensions(), vec!["bib", "json", "ris", "yml"]);
+        assert_eq!(registry.for_extension("bib").unwrap().name(), "bibtex");
+        assert_eq!(registry.for_extension("JSON").unwrap().name(), "csl-json");
+        assert_eq!(registry.for_extension("yaml").unwrap().name(), "hayagriva");
+        assert!(registry.get("keys").is_none());
+
+        registry.register(Box::new(KeysWriter));
+        let writer = registry.get("keys").unwrap();
+        let bibliography = Bibliography::parse("@misc{a, title = {A}} @misc{b, title = {B}}").unwrap();
+        assert_eq!(writer.write(&bibliography, &BibMetadata::new()), "a\nb\n");
+        assert_eq!(writer.format_entry(bibliography.get("b").unwrap()), "b\n");
+        assert_eq!(registry.for_extension("txt").unwrap().name(), "keys");
+    }
+
+    #[test]
+    fn test_builtin_writers() {
+        let bibliography =
+            Bibliography::parse("@article{a, journal = {Analysis}, file = {a.pdf}}").unwrap();
+        let entry = bibliography.get("a").unwrap();
+        assert!(BibtexWriter::default().format_entry(entry).contains("journal = {Analysis}"));
+        assert!(BiblatexWriter::default().format_entry(entry).contains("journaltitle = {Analysis}"));
+        assert!(CslJsonWriter.format_entry(entry).contains("\"container-title\": \"Analysis\""));
+        assert_eq!(
+            RisWriter.warnings(&bibliography),
+            vec!["The following fields have no RIS equivalent and were left out: file"]
+        );
+        assert!(BibtexWriter::default().warnings(&bibliography).is_empty());
+    }
+
+    #[test]
+    fn test_write_bib_metadata() {
+        let filepaths = vec![
+            PathBuf::from("bib_files/metadata_files/a.bib"),
+            PathBuf::from("bib_files/metadata_files/b.bib"),
+        ];
+        let files: Vec<String> = filepaths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        let (metadata, _) = collect_metadata(&filepaths, &files);
+        let mut bibliography = Bibliography::new();
+        for file in files.iter() {
+            let mut parsed = Bibliography::parse(file).unwrap();
+            remove_special_entries(&mut parsed);
+            for entry in parsed.into_iter() {
+                bibliography.insert(entry);
+            }
+        }
+
+        let bib = BibtexWriter::default().write(&bibliography, &metadata);
+        assert_eq!(bib.matches("@preamble").count(), 1);
+        assert_eq!(bib.matches("@comment").count(), 1);
+        assert_eq!(bib.matches("@string").count(), 2);
+        // Values defined by a macro are written as the macro
+        assert_eq!(bib.matches("journal = jpl,").count(), 2);
+        // Prior1960 used b.bib's definition of anl, which was not kept
+        assert!(bib.contains("journal = {Analyses},"));
+    }
+}
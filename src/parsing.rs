@@ -1,26 +1,303 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::io::Read;
+use std::panic;
 use std::path::{Path, PathBuf};
+use std::sync::Once;
 use std::{ffi::OsStr, fs, io};
 
-use biblatex::{Bibliography, BibliographyError};
+use biblatex::{Bibliography, BibliographyError, ChunksExt, DateValue, Entry};
+use encoding_rs::Encoding;
+
+use super::{
+    add_entry_to_bibliography, decode_file, parse_csl_json, parse_endnote_xml, parse_hayagriva,
+    parse_nbib, parse_ris, remove_special_entries, InputOrder, ParseError, ParseErrorKind,
+    RecordError,
+};
+
+/// Path used (as input or as output) to refer to stdin or stdout
+pub const STDIO_PATH: &str = "-";
+
+// Resolve the inputs given by the user into the list of files to read
+// Each input may be "-" (stdin), a file (always included, whatever its name), a directory (read with
+// get_filepaths_recursive up to max_depth) or a glob pattern such as "papers/**/*.bib" (whose
+// matches are filtered with include_path, as are the files in directories). Files reached through
+// more than one input are only returned once, in the position where they first appeared.
+// The excluded files (those the program itself reads or writes, e.g. the report) are skipped when
+// found in a directory or through a glob pattern, so that a second run in the same directory does
+// not read them as inputs.
+pub fn get_input_filepaths(
+    inputs: &[PathBuf],
+    max_depth: usize,
+    generic: bool,
+    excluded: &[PathBuf],
+) -> io::Result<Vec<PathBuf>> {
+    let mut input_filepaths = vec![];
+    let mut seen = HashSet::new();
+    // Files that do not exist yet cannot be found either
+    let excluded: HashSet<PathBuf> = excluded
+        .iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+
+    for input in inputs {
+        let (filepaths, found) = if input.as_path() == Path::new(STDIO_PATH) {
+            (vec![input.clone()], false)
+        } else if input.is_dir() {
+            (
+                get_filepaths_recursive(input.as_path(), max_depth, generic)?,
+                true,
+            )
+        } else if input.is_file() {
+            (vec![input.clone()], false)
+        } else if is_glob_pattern(input.as_path()) {
+            (get_glob_filepaths(input.as_path(), generic)?, true)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Input {:?} does not exist", input),
+            ));
+        };
+
+        for path in filepaths {
+            // stdin has no canonical path, but it can only be read once anyway
+            let canonical_path = match path.as_path() == Path::new(STDIO_PATH) {
+                true => path.clone(),
+                false => fs::canonicalize(&path)?,
+            };
+            if found && excluded.contains(&canonical_path) {
+                continue;
+            }
+            if seen.insert(canonical_path) {
+                input_filepaths.push(path);
+            }
+        }
+    }
+    Ok(input_filepaths)
+}
+
+// Put the input files in the order in which they will be unified
+// With InputOrder::Sorted they are sorted by path, with InputOrder::Given they are kept as they
+// were collected. Then, the preferred files are moved to the front (the first one being the
+// preferred the most), so that in silent mode their entries are kept over repeated ones.
+// A preferred path matches a file if it is the same file, or if the file path ends with it
+// (so "master.bib" matches "refs/master.bib"). It is an error for it to match no file at all.
+pub fn order_filepaths(
+    mut filepaths: Vec<PathBuf>,
+    input_order: &InputOrder,
+    preferred: &[PathBuf],
+) -> io::Result<Vec<PathBuf>> {
+    if let InputOrder::Sorted = input_order {
+        filepaths.sort();
+    }
+
+    let mut priorities = vec![preferred.len(); filepaths.len()];
+    for (priority, preferred_path) in preferred.iter().enumerate().rev() {
+        let canonical_preferred = fs::canonicalize(preferred_path).ok();
+        let mut found = false;
+        for (idx, path) in filepaths.iter().enumerate() {
+            if path.ends_with(preferred_path)
+                || (canonical_preferred.is_some()
+                    && fs::canonicalize(path).ok() == canonical_preferred)
+            {
+                priorities[idx] = priority;
+                found = true;
+            }
+        }
+        if !found {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Preferred file {:?} is not among the input files", preferred_path),
+            ));
+        }
+    }
+
+    // The sort is stable, so files with the same priority keep their relative order
+    let mut indexed: Vec<(usize, PathBuf)> = priorities.into_iter().zip(filepaths).collect();
+    indexed.sort_by_key(|(priority, _)| *priority);
+    Ok(indexed.into_iter().map(|(_, path)| path).collect())
+}
+
+// Return the files matching a glob pattern, sorted by path
+fn get_glob_filepaths(pattern: &Path, generic: bool) -> io::Result<Vec<PathBuf>> {
+    let pattern = pattern.to_string_lossy();
+    let paths = glob::glob(&pattern).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid glob pattern {:?}: {}", pattern, e),
+        )
+    })?;
+
+    let mut bib_filepaths = vec![];
+    for path in paths {
+        let path = path.map_err(io::Error::from)?;
+        if path.is_file() && include_path(path.as_path(), generic) {
+            bib_filepaths.push(path);
+        }
+    }
+    bib_filepaths.sort();
+    Ok(bib_filepaths)
+}
+
+// Check whether a path contains any of the glob special characters
+fn is_glob_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(&['*', '?', '['][..])
+}
+
+// Get the directory where the output is placed by default
+// If every input lives in the same directory (a directory given as input, the parent of a file,
+// or the part of a glob pattern before its first special character), that directory is used.
+// Otherwise, there is no single input directory and the current directory is used instead.
+pub fn default_output_dir(inputs: &[PathBuf]) -> PathBuf {
+    let mut base_dirs = inputs.iter().map(|input| {
+        if input.is_dir() {
+            input.clone()
+        } else if !input.is_file() && is_glob_pattern(input.as_path()) {
+            input
+                .components()
+                .take_while(|c| !is_glob_pattern(Path::new(c.as_os_str())))
+                .collect()
+        } else {
+            input.parent().map(Path::to_path_buf).unwrap_or_default()
+        }
+    });
+
+    if let Some(first) = base_dirs.next() {
+        if base_dirs.all(|dir| dir == first) && first != Path::new("") {
+            return first;
+        }
+    }
+    PathBuf::from(".")
+}
 
 // Read a directory path and return a vec of the .bib filepaths (i.e. PathBuf's) inside it
 pub fn get_filepaths(path_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    get_filepaths_recursive(path_dir, 0, false)
+}
+
+// Same as get_filepaths, but also looks inside subdirectories, down to max_depth levels below
+// path_dir (0 means only path_dir itself), and also includes the files with generic extensions if
+// generic is true (see include_path). Files are returned sorted by path within each directory, so
+// the result does not depend on the order in which the OS lists them.
+pub fn get_filepaths_recursive(
+    path_dir: &Path,
+    max_depth: usize,
+    generic: bool,
+) -> io::Result<Vec<PathBuf>> {
     let mut bib_filepaths = vec![];
+    let mut visited_dirs = HashSet::new();
+    collect_filepaths(
+        path_dir,
+        max_depth,
+        generic,
+        &mut visited_dirs,
+        &mut bib_filepaths,
+    )?;
+    Ok(bib_filepaths)
+}
+
+// Walks a directory tree, pushing the paths to include into bib_filepaths
+// Directories are identified by their canonical path, so that a symlink pointing back to one of
+// its ancestors (or any directory reached twice) is only visited once
+fn collect_filepaths(
+    path_dir: &Path,
+    depth_left: usize,
+    generic: bool,
+    visited_dirs: &mut HashSet<PathBuf>,
+    bib_filepaths: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !visited_dirs.insert(fs::canonicalize(path_dir)?) {
+        return Ok(());
+    }
+
+    let mut paths = fs::read_dir(path_dir)?
+        .map(|dir_entry| dir_entry.map(|dir_entry| dir_entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    paths.sort();
 
-    for path in fs::read_dir(path_dir)? {
-        let path = path?.path();
-        if include_path(path.as_path()) {
+    for path in paths {
+        if path.is_dir() {
+            if depth_left > 0 {
+                collect_filepaths(&path, depth_left - 1, generic, visited_dirs, bib_filepaths)?;
+            }
+        } else if include_path(path.as_path(), generic) {
             bib_filepaths.push(path);
         }
     }
-    Ok(bib_filepaths)
+    Ok(())
+}
+
+/// Format of an input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// BibTeX or BibLaTeX (.bib)
+    Bibtex,
+    /// RIS, as exported by most reference managers and databases (.ris)
+    Ris,
+    /// CSL-JSON, as used by Pandoc and Zotero (.json)
+    CslJson,
+    /// Hayagriva YAML, as used by Typst (.yml or .yaml)
+    Hayagriva,
+    /// EndNote XML (.xml)
+    EndnoteXml,
+    /// PubMed/MEDLINE (.nbib)
+    Nbib,
+}
+
+impl InputFormat {
+    /// Get the format of a file from its extension (ignoring case)
+    ///
+    /// Returns None for any other extension. Files given by name with those (and stdin) are
+    /// read as BibTeX.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "bib" => Some(InputFormat::Bibtex),
+            "ris" => Some(InputFormat::Ris),
+            "json" => Some(InputFormat::CslJson),
+            "yml" | "yaml" => Some(InputFormat::Hayagriva),
+            "xml" => Some(InputFormat::EndnoteXml),
+            "nbib" => Some(InputFormat::Nbib),
+            _ => None,
+        }
+    }
+
+    /// Whether other programs also keep their own files with the extensions of this format
+    /// (e.g. package.json, the .yml configuration of CI services, or Maven's pom.xml)
+    pub fn is_generic(&self) -> bool {
+        matches!(
+            self,
+            InputFormat::CslJson | InputFormat::Hayagriva | InputFormat::EndnoteXml
+        )
+    }
+
+    /// Parse the contents of a file in a format other than BibTeX, returning an entry (or an
+    /// error) for each record
+    ///
+    /// Returns None for BibTeX, which is parsed as a whole by biblatex.
+    pub fn parse_records(&self, src: &str) -> Option<Vec<Result<Entry, RecordError>>> {
+        match self {
+            InputFormat::Bibtex => None,
+            InputFormat::Ris => Some(parse_ris(src)),
+            InputFormat::CslJson => Some(parse_csl_json(src)),
+            InputFormat::Hayagriva => Some(parse_hayagriva(src)),
+            InputFormat::EndnoteXml => Some(parse_endnote_xml(src)),
+            InputFormat::Nbib => Some(parse_nbib(src)),
+        }
+    }
 }
 
 // Check whether a file needs to be included in the unification or not
-// (has to be a .bib file and not begin with [bib_unifier])
-pub fn include_path(path: &Path) -> bool {
-    // Check that the extension is .bib
-    if let Some("bib") = path.extension().and_then(OsStr::to_str) {
+// (has to be in one of the input formats and not begin with [bib_unifier])
+// Files with the generic extensions of some formats (see InputFormat::is_generic) are only included
+// if generic is true.
+pub fn include_path(path: &Path, generic: bool) -> bool {
+    // Check that the extension is one of the input formats
+    let included = match InputFormat::from_path(path) {
+        Some(format) => generic || !format.is_generic(),
+        None => false,
+    };
+    if included {
         // Check that the file is not a previous output of the program itself
         // i.e. that it does not begin with [bib_unifier]
         if let Some(filename) = path.file_name().and_then(OsStr::to_str) {
@@ -32,36 +309,397 @@ pub fn include_path(path: &Path) -> bool {
     false
 }
 
-// Given a vec of PathBufs, return a vec of the file contents
-pub fn get_files(filepaths: &Vec<PathBuf>) -> io::Result<Vec<String>> {
+// Generate a citation key for an entry that has none, from the family name of its first author
+// and its year (e.g. "Prior1960"), or from the first word of its title if there are no authors
+pub fn generate_citation_key(entry: &Entry) -> String {
+    let alphanumeric = |text: &str| -> String {
+        text.chars().filter(char::is_ascii_alphanumeric).collect()
+    };
+    let name = entry
+        .author()
+        .and_then(|authors| authors.first().map(|author| alphanumeric(&author.name)))
+        .filter(|name| !name.is_empty())
+        .or_else(|| {
+            let title = entry.title()?.format_verbatim();
+            title
+                .split_whitespace()
+                .map(alphanumeric)
+                .find(|word| !word.is_empty())
+        });
+    // Full dates (e.g. "2018-10-03") only contribute their year
+    let year = entry
+        .get("year")
+        .or_else(|| entry.get("date"))
+        .map(|year| {
+            let year = year.format_verbatim();
+            alphanumeric(year.split('-').next().unwrap_or_default())
+        })
+        .unwrap_or_default();
+    match name {
+        Some(name) => format!("{}{}", name, year),
+        None if !year.is_empty() => format!("entry{}", year),
+        None => String::from("entry"),
+    }
+}
+
+// Given a vec of PathBufs, return a vec of the file contents ("-" is read from stdin)
+// The contents are transcoded to UTF-8 (see decode_file for how the encoding of each file is
+// chosen, encoding being the one given by the user, if any)
+pub fn get_files(
+    filepaths: &[PathBuf],
+    encoding: Option<&'static Encoding>,
+) -> io::Result<Vec<String>> {
     let mut files = vec![];
     for path in filepaths.iter() {
-        files.push(fs::read_to_string(path)?);
+        let bytes = if path.as_path() == Path::new(STDIO_PATH) {
+            let mut bytes = vec![];
+            io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        } else {
+            fs::read(path)?
+        };
+        files.push(decode_file(&bytes, encoding).0);
     }
     Ok(files)
 }
 
-// Given a vec of Strings (contents of the .bib files), return a vec of Bibliography
+// Get the year, month and day (both starting from 1) of an entry, from its date field or from its
+// year, month and day fields. Only the start of a date range is taken.
+pub fn get_date_parts(entry: &Entry) -> Option<(i32, Option<u8>, Option<u8>)> {
+    let date = entry.date()?;
+    let (year, month, day) = match date.value {
+        DateValue::At(datetime)
+        | DateValue::After(datetime)
+        | DateValue::Before(datetime)
+        | DateValue::Between(datetime, _) => (datetime.year, datetime.month, datetime.day),
+    };
+    // biblatex counts from zero, and only understands month names, but months are often numbers
+    let month = month.map(|month| month + 1).or_else(|| {
+        entry
+            .get("month")
+            .and_then(|month| month.format_verbatim().trim().parse::<u8>().ok())
+            .filter(|month| (1..=12).contains(month))
+    });
+    Some((year, month, day.map(|day| day + 1)))
+}
+
+// Get the number (starting from 1) of a month from its English name or abbreviation
+pub fn month_from_name(name: &str) -> Option<u8> {
+    let name = name.trim().to_lowercase();
+    if name.len() < 3 {
+        return None;
+    }
+    [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ]
+    .iter()
+    .position(|month| name.starts_with(month))
+    .map(|idx| idx as u8 + 1)
+}
+
+// Write a page range with the BibTeX dash ("--"), completing end pages that only give the digits
+// that change, as PubMed does (e.g. "737-8" becomes "737--738")
+pub fn format_page_range(pages: &str) -> String {
+    let pages = pages.trim();
+    let (start, end) = match pages.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim_start_matches('-').trim()),
+        None => return pages.to_string(),
+    };
+    if end.is_empty() {
+        return start.to_string();
+    }
+    let all_digits = |pages: &str| pages.chars().all(|c| c.is_ascii_digit());
+    let end = match all_digits(start) && all_digits(end) && end.len() < start.len() {
+        true => format!("{}{}", &start[..start.len() - end.len()], end),
+        false => end.to_string(),
+    };
+    format!("{}--{}", start, end)
+}
+
+// Given a vec of Strings (contents of the input files), return a vec of Bibliography
+// Files in other formats (e.g. .ris or .json) are converted record by record (see InputFormat)
+// The @preamble and @comment blocks are not returned as entries (see collect_metadata for those)
+// If a file cannot be parsed, the error says where in the file the problem is
 pub fn get_bibliographies(
     filepaths: Vec<PathBuf>,
     file_contents: Vec<String>,
-) -> Result<Vec<Bibliography>, BibliographyError> {
+) -> Result<Vec<Bibliography>, Box<ParseError>> {
     let mut bibliographies = vec![];
-    for (idx, file_content) in file_contents.into_iter().enumerate() {
+    for (file_content, filepath) in file_contents.into_iter().zip(filepaths) {
+        let format = InputFormat::from_path(&filepath).unwrap_or(InputFormat::Bibtex);
+        if let Some(records) = format.parse_records(&file_content) {
+            let mut bibliography = Bibliography::new();
+            for record in records {
+                match record {
+                    Ok(entry) => {
+                        add_entry_to_bibliography(entry, &mut bibliography);
+                    }
+                    Err(e) => {
+                        return Err(Box::new(ParseError::new(
+                            ParseErrorKind::InvalidRecord(e),
+                            filepath,
+                            &file_content,
+                        )));
+                    }
+                }
+            }
+            bibliographies.push(bibliography);
+            continue;
+        }
+        // The parser does not handle unclosed entries well, so check for them first
+        if split_raw_blocks(&file_content).iter().any(|block| !block.closed) {
+            return Err(Box::new(ParseError::new(
+                ParseErrorKind::UnclosedEntry,
+                filepath,
+                &file_content,
+            )));
+        }
         match Bibliography::parse(&file_content) {
-            Ok(bibliography) => bibliographies.push(bibliography),
+            Ok(mut bibliography) => {
+                remove_special_entries(&mut bibliography);
+                bibliographies.push(bibliography)
+            }
             Err(e) => {
-                eprintln!(
-                    "A problem was encountered while processing file {:?}.",
-                    filepaths[idx]
-                );
-                return Err(e);
+                return Err(Box::new(ParseError::new(
+                    ParseErrorKind::Bibliography(e),
+                    filepath,
+                    &file_content,
+                )));
             }
         }
     }
     Ok(bibliographies)
 }
 
+/// An entry that could not be parsed in lenient mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedEntry {
+    /// File the entry was read from
+    pub filepath: PathBuf,
+    /// Line (starting from 1) where the entry begins
+    pub line: usize,
+    /// Raw text of the entry, as found in the file
+    pub text: String,
+    /// Why the entry was rejected
+    pub reason: String,
+}
+
+/// Raw text of an @-block of a .bib file (an entry, or an @string, @preamble or @comment)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawBlock<'s> {
+    /// Block type as written in the file (e.g. "article" or "string")
+    pub block_type: &'s str,
+    /// Text of the whole block, from the @ to the closing delimiter
+    pub text: &'s str,
+    /// Line (starting from 1) where the block begins
+    pub line: usize,
+    /// False if the file ended before the block was closed
+    pub closed: bool,
+}
+
+// Split the contents of a .bib file into its @-blocks, without parsing them
+// Blocks end at the brace (or parenthesis) that matches the one after the block type. Text
+// outside of blocks is ignored, as are @'s in lines commented out with %.
+pub fn split_raw_blocks(src: &str) -> Vec<RawBlock<'_>> {
+    let mut blocks = vec![];
+    let mut chars = src.char_indices().peekable();
+    let mut comment = false;
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '%' => comment = true,
+            '\n' => comment = false,
+            '@' if !comment => {
+                // Read the block type
+                let mut type_end = start + 1;
+                while let Some(&(idx, c)) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '-' {
+                        type_end = idx + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let block_type = &src[start + 1..type_end];
+                while let Some((_, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let (open_idx, open, close) = match chars.peek() {
+                    Some(&(idx, '{')) => (idx, '{', '}'),
+                    Some(&(idx, '(')) => (idx, '(', ')'),
+                    // Not a block, e.g. an email address in a comment between entries
+                    _ => continue,
+                };
+
+                // Look for the matching closing delimiter
+                let end = delimiter_depths(&src[open_idx..], open, close)
+                    .find(|&(_, c, depth)| c == close && depth == 1)
+                    .map(|(idx, c, _)| open_idx + idx + c.len_utf8());
+                let closed = end.is_some();
+                let end = end.unwrap_or(src.len());
+                while chars.next_if(|&(idx, _)| idx < end).is_some() {}
+
+                blocks.push(RawBlock {
+                    block_type,
+                    text: &src[start..end],
+                    line: src[..start].matches('\n').count() + 1,
+                    closed,
+                });
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+// Iterate over the characters of a piece of a .bib file (with their byte index), along with the
+// depth of the braces (or of the given delimiters) they are in
+// As in BibTeX, a backslash does not escape a brace, so every delimiter counts. Delimiters are at
+// the depth of the group they open or close (e.g. the braces of "{a}" are at depth 1, like the
+// "a"), and unmatched closing delimiters at depth 0.
+pub fn delimiter_depths(
+    text: &str,
+    open: char,
+    close: char,
+) -> impl Iterator<Item = (usize, char, usize)> + '_ {
+    let mut depth = 0;
+    text.char_indices().map(move |(idx, c)| {
+        if c == open {
+            depth += 1;
+            (idx, c, depth)
+        } else if c == close && depth > 0 {
+            depth -= 1;
+            (idx, c, depth + 1)
+        } else {
+            (idx, c, depth)
+        }
+    })
+}
+
+// Lenient version of get_bibliographies
+// Instead of parsing each file as a whole, splits it into its entries and parses them one by one.
+// The entries that cannot be parsed are returned separately instead of making the whole run fail.
+// The @string definitions of a file are available to all of its entries.
+pub fn get_bibliographies_lenient(
+    filepaths: &[PathBuf],
+    file_contents: Vec<String>,
+) -> (Vec<Bibliography>, Vec<RejectedEntry>) {
+    let mut bibliographies = vec![];
+    let mut rejected = vec![];
+
+    for (idx, file_content) in file_contents.iter().enumerate() {
+        let format = InputFormat::from_path(&filepaths[idx]).unwrap_or(InputFormat::Bibtex);
+        if let Some(records) = format.parse_records(file_content) {
+            let mut bibliography = Bibliography::new();
+            for record in records {
+                match record {
+                    Ok(entry) => {
+                        add_entry_to_bibliography(entry, &mut bibliography);
+                    }
+                    Err(e) => rejected.push(RejectedEntry {
+                        filepath: filepaths[idx].clone(),
+                        line: e.line,
+                        text: e.text,
+                        reason: e.message,
+                    }),
+                }
+            }
+            bibliographies.push(bibliography);
+            continue;
+        }
+        let blocks = split_raw_blocks(file_content);
+        let mut bibliography = Bibliography::new();
+        let strings: String = blocks
+            .iter()
+            .filter(|block| block.closed && block.block_type.eq_ignore_ascii_case("string"))
+            .map(|block| format!("{}\n", block.text))
+            .collect();
+
+        for block in blocks.iter() {
+            if ["string", "preamble", "comment"]
+                .iter()
+                .any(|t| block.block_type.eq_ignore_ascii_case(t))
+            {
+                continue;
+            }
+            let mut reject = |reason: String| {
+                rejected.push(RejectedEntry {
+                    filepath: filepaths[idx].clone(),
+                    line: block.line,
+                    text: block.text.to_string(),
+                    reason,
+                })
+            };
+            if !block.closed {
+                reject(String::from("Unbalanced braces, the entry is never closed"));
+                continue;
+            }
+
+            let source = format!("{}{}", strings, block.text);
+            match parse_quietly(&source) {
+                Ok(Ok(parsed)) => {
+                    for entry in parsed.into_iter() {
+                        if bibliography.get(&entry.key).is_some() {
+                            reject(BibliographyError::DuplicateKey(entry.key).to_string());
+                        } else {
+                            bibliography.insert(entry);
+                        }
+                    }
+                }
+                Ok(Err(e)) => reject(e.to_string()),
+                Err(_) => reject(String::from("The entry could not be parsed")),
+            }
+        }
+        bibliographies.push(bibliography);
+    }
+    (bibliographies, rejected)
+}
+
+thread_local! {
+    // Whether the panics of this thread are being caught by parse_quietly
+    static PARSING_QUIETLY: Cell<bool> = const { Cell::new(false) };
+}
+
+// Parse a bibliography, catching the panics of the parser
+// The parser panics on some malformed input instead of returning an error. A panic hook that
+// ignores the panics caught here is installed once, rather than swapped on each call (which would
+// race with other threads), so that the rejected entry is reported without the panic message.
+fn parse_quietly(source: &str) -> std::thread::Result<Result<Bibliography, BibliographyError>> {
+    static INSTALL_HOOK: Once = Once::new();
+    INSTALL_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !PARSING_QUIETLY.with(Cell::get) {
+                default_hook(info);
+            }
+        }));
+    });
+    PARSING_QUIETLY.with(|quiet| quiet.set(true));
+    let result = panic::catch_unwind(|| Bibliography::parse(source));
+    PARSING_QUIETLY.with(|quiet| quiet.set(false));
+    result
+}
+
+// Write the text of the rejected entries to a file, each preceded by a comment saying where it
+// came from, so that they can be fixed and unified later
+pub fn write_quarantine(path: &Path, rejected: &[RejectedEntry]) -> io::Result<()> {
+    let mut quarantine = String::new();
+    for entry in rejected {
+        quarantine.push_str(&format!(
+            "% {}, line {}: {}\n{}\n\n",
+            entry.filepath.display(),
+            entry.line,
+            entry.reason,
+            entry.text
+        ));
+    }
+    fs::write(path, quarantine)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,17 +712,214 @@ mod tests {
         assert_eq!(filepaths.len(), 7);
     }
 
+    #[test]
+    fn test_get_filepaths_recursive() {
+        let path_dir = PathBuf::from("bib_files/nested_files/");
+        // Without recursion only the top-level file is found
+        assert_eq!(get_filepaths(path_dir.as_path()).unwrap().len(), 1);
+        assert_eq!(
+            get_filepaths_recursive(path_dir.as_path(), 0, false).unwrap().len(),
+            1
+        );
+
+        // One level down finds refs/sub.bib (but not the previous output in refs/)
+        let filepaths = get_filepaths_recursive(path_dir.as_path(), 1, false).unwrap();
+        assert_eq!(
+            filepaths,
+            vec![
+                PathBuf::from("bib_files/nested_files/refs/sub.bib"),
+                PathBuf::from("bib_files/nested_files/top.bib"),
+            ]
+        );
+
+        // Without a depth limit it finds all three, in sorted order
+        let filepaths = get_filepaths_recursive(path_dir.as_path(), usize::MAX, false).unwrap();
+        assert_eq!(
+            filepaths,
+            vec![
+                PathBuf::from("bib_files/nested_files/refs/deeper/deep.bib"),
+                PathBuf::from("bib_files/nested_files/refs/sub.bib"),
+                PathBuf::from("bib_files/nested_files/top.bib"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_input_filepaths() {
+        // A directory, a file inside it (repeated) and a file elsewhere
+        let inputs = vec![
+            PathBuf::from("bib_files/nested_files/"),
+            PathBuf::from("bib_files/nested_files/top.bib"),
+            PathBuf::from("bib_files/test_files/test.bib"),
+        ];
+        let filepaths = get_input_filepaths(&inputs, 0, false, &[]).unwrap();
+        assert_eq!(
+            filepaths,
+            vec![
+                PathBuf::from("bib_files/nested_files/top.bib"),
+                PathBuf::from("bib_files/test_files/test.bib"),
+            ]
+        );
+
+        // A glob pattern ignores previous outputs and non-bib files
+        let inputs = vec![PathBuf::from("bib_files/nested_files/**/*")];
+        let filepaths = get_input_filepaths(&inputs, 0, false, &[]).unwrap();
+        assert_eq!(
+            filepaths,
+            vec![
+                PathBuf::from("bib_files/nested_files/refs/deeper/deep.bib"),
+                PathBuf::from("bib_files/nested_files/refs/sub.bib"),
+                PathBuf::from("bib_files/nested_files/top.bib"),
+            ]
+        );
+
+        // Explicit files are included even if they would not be picked from a directory
+        let inputs = vec![PathBuf::from("bib_files/no_bib_files/some_file.txt")];
+        assert_eq!(get_input_filepaths(&inputs, 0, false, &[]).unwrap().len(), 1);
+
+        // stdin is only read once
+        let inputs = vec![PathBuf::from("-"), PathBuf::from("-")];
+        assert_eq!(
+            get_input_filepaths(&inputs, 0, false, &[]).unwrap(),
+            vec![PathBuf::from("-")]
+        );
+
+        // Excluded files are skipped in directories, but not when given by name
+        let inputs = vec![
+            PathBuf::from("bib_files/nested_files/"),
+            PathBuf::from("bib_files/test_files/test.bib"),
+        ];
+        let excluded = [
+            PathBuf::from("bib_files/nested_files/top.bib"),
+            PathBuf::from("bib_files/test_files/test.bib"),
+        ];
+        assert_eq!(
+            get_input_filepaths(&inputs, 0, false, &excluded).unwrap(),
+            vec![PathBuf::from("bib_files/test_files/test.bib")]
+        );
+
+        let inputs = vec![PathBuf::from("bib_files/does_not_exist.bib")];
+        assert!(get_input_filepaths(&inputs, 0, false, &[]).is_err());
+    }
+
+    #[test]
+    fn test_order_filepaths() {
+        let filepaths = vec![
+            PathBuf::from("bib_files/test_files/test.bib"),
+            PathBuf::from("bib_files/nested_files/top.bib"),
+            PathBuf::from("bib_files/test_files/rep_in_file.bib"),
+        ];
+
+        let ordered = order_filepaths(filepaths.clone(), &InputOrder::Sorted, &[]).unwrap();
+        assert_eq!(
+            ordered,
+            vec![
+                PathBuf::from("bib_files/nested_files/top.bib"),
+                PathBuf::from("bib_files/test_files/rep_in_file.bib"),
+                PathBuf::from("bib_files/test_files/test.bib"),
+            ]
+        );
+
+        let ordered = order_filepaths(filepaths.clone(), &InputOrder::Given, &[]).unwrap();
+        assert_eq!(ordered, filepaths);
+
+        // The first preferred file goes first, then the second, then the rest
+        let preferred = vec![
+            PathBuf::from("test.bib"),
+            PathBuf::from("bib_files/test_files/rep_in_file.bib"),
+        ];
+        let ordered = order_filepaths(filepaths.clone(), &InputOrder::Sorted, &preferred).unwrap();
+        assert_eq!(
+            ordered,
+            vec![
+                PathBuf::from("bib_files/test_files/test.bib"),
+                PathBuf::from("bib_files/test_files/rep_in_file.bib"),
+                PathBuf::from("bib_files/nested_files/top.bib"),
+            ]
+        );
+
+        // Preferring a file that is not an input is an error
+        let preferred = vec![PathBuf::from("master.bib")];
+        assert!(order_filepaths(filepaths, &InputOrder::Sorted, &preferred).is_err());
+    }
+
+    #[test]
+    fn test_default_output_dir() {
+        assert_eq!(
+            default_output_dir(&[PathBuf::from("bib_files/test_files/")]),
+            PathBuf::from("bib_files/test_files")
+        );
+        assert_eq!(
+            default_output_dir(&[
+                PathBuf::from("bib_files/test_files/test.bib"),
+                PathBuf::from("bib_files/test_files/rep_in_file.bib"),
+            ]),
+            PathBuf::from("bib_files/test_files")
+        );
+        assert_eq!(
+            default_output_dir(&[PathBuf::from("bib_files/nested_files/**/*.bib")]),
+            PathBuf::from("bib_files/nested_files")
+        );
+        // No single input directory
+        assert_eq!(
+            default_output_dir(&[
+                PathBuf::from("bib_files/test_files/"),
+                PathBuf::from("bib_files/nested_files/top.bib"),
+            ]),
+            PathBuf::from(".")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_filepaths_recursive_symlink_loop() {
+        let path_dir = std::env::temp_dir().join("bib_unifier_symlink_loop");
+        let _ = fs::remove_dir_all(&path_dir);
+        fs::create_dir_all(path_dir.join("sub")).unwrap();
+        fs::copy("bib_files/nested_files/top.bib", path_dir.join("sub/top.bib")).unwrap();
+        // sub/loop points back to the root directory
+        std::os::unix::fs::symlink(&path_dir, path_dir.join("sub/loop")).unwrap();
+
+        let filepaths = get_filepaths_recursive(path_dir.as_path(), usize::MAX, false).unwrap();
+        assert_eq!(filepaths, vec![path_dir.join("sub/top.bib")]);
+        fs::remove_dir_all(&path_dir).unwrap();
+    }
+
     #[test]
     fn test_include_path() {
         assert!(include_path(
-            PathBuf::from("bib_files/test_files/test1.bib").as_path()
+            PathBuf::from("bib_files/test_files/test1.bib").as_path(),
+            false
         ));
         assert!(!include_path(
-            PathBuf::from("bib_files/test_files/test1.txt").as_path()
+            PathBuf::from("bib_files/test_files/test1.txt").as_path(),
+            false
+        ));
+        assert!(include_path(
+            PathBuf::from("bib_files/ris_files/references.ris").as_path(),
+            false
+        ));
+        assert!(include_path(
+            PathBuf::from("bib_files/nbib_files/pubmed.nbib").as_path(),
+            false
         ));
         assert!(!include_path(
-            PathBuf::from("bib_files/test_files/[bib_unifier]test1.bib").as_path()
+            PathBuf::from("bib_files/test_files/[bib_unifier]test1.bib").as_path(),
+            false
         ));
+        // Generic extensions are only included if asked to
+        let csl_json = PathBuf::from("bib_files/csl_files/references.json");
+        assert!(!include_path(csl_json.as_path(), false));
+        assert!(include_path(csl_json.as_path(), true));
+        let hayagriva = PathBuf::from("bib_files/hayagriva_files/library.yml");
+        assert!(!include_path(hayagriva.as_path(), false));
+        assert!(include_path(hayagriva.as_path(), true));
+        // Extensions are compared ignoring case
+        assert!(include_path(PathBuf::from("REFERENCES.BIB").as_path(), false));
+        assert!(include_path(PathBuf::from("library.YAML").as_path(), true));
+        let endnote = PathBuf::from("bib_files/endnote_files/library.xml");
+        assert!(!include_path(endnote.as_path(), false));
+        assert!(include_path(endnote.as_path(), true));
     }
 
     #[test]
@@ -100,6 +935,137 @@ mod tests {
         assert_eq!(&prior_title, "The Runabout Inference-Ticket")
     }
 
+    #[test]
+    fn test_split_raw_blocks() {
+        let file = fs::read_to_string("bib_files/lenient_files/mixed.bib").unwrap();
+        let blocks = split_raw_blocks(&file);
+        let types: Vec<&str> = blocks.iter().map(|block| block.block_type).collect();
+        assert_eq!(
+            types,
+            vec!["string", "article", "inproceedings", "article", "article"]
+        );
+        assert_eq!(blocks[1].line, 4);
+        assert!(blocks[1].text.starts_with("@article{Prior1960,"));
+        assert!(blocks[1].text.ends_with('}'));
+        assert!(blocks[3].closed);
+        assert!(!blocks[4].closed);
+
+        // Braces are counted even after a backslash
+        let blocks = split_raw_blocks("@misc{a, note = {In C:\\}}\n@misc{b, title = {B}}");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].text, "@misc{a, note = {In C:\\}}");
+        assert!(blocks[0].closed);
+        assert!(blocks[1].closed);
+    }
+
+    #[test]
+    fn test_get_bibliographies_lenient() {
+        let filepaths = vec![PathBuf::from("bib_files/lenient_files/mixed.bib")];
+        let files = get_files(&filepaths, None).unwrap();
+        let (bibliographies, rejected) = get_bibliographies_lenient(&filepaths, files);
+
+        assert_eq!(bibliographies.len(), 1);
+        assert_eq!(bibliographies[0].len(), 2);
+        // @string abbreviations are resolved
+        let prior = bibliographies[0].get("Prior1960").unwrap();
+        assert_eq!(prior.journal().unwrap().format_verbatim(), "Analysis");
+
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(rejected[0].line, 12);
+        assert_eq!(
+            rejected[0].reason,
+            "Key \"conigliocorbalan\" contains malformed field \"author\""
+        );
+        assert_eq!(rejected[1].line, 25);
+        assert!(rejected[1].text.starts_with("@article{Unclosed,"));
+    }
+
+    #[test]
+    fn test_get_bibliographies_ris() {
+        let filepaths = vec![PathBuf::from("bib_files/ris_files/references.ris")];
+        let files = get_files(&filepaths, None).unwrap();
+        let error = get_bibliographies(filepaths.clone(), files.clone()).unwrap_err();
+        assert_eq!(error.line, 31);
+
+        let (bibliographies, rejected) = get_bibliographies_lenient(&filepaths, files);
+        assert_eq!(bibliographies[0].len(), 2);
+        assert!(bibliographies[0].get("Prior1960").is_some());
+        assert_eq!(rejected.len(), 1);
+        assert!(rejected[0].text.starts_with("TY  - BOOK"));
+    }
+
+    #[test]
+    fn test_generate_citation_key() {
+        let bibliography = Bibliography::parse(
+            "@article{a, author = {von Wright, G. H.}, year = {1951}}
+            @book{b, title = {The Laws of Thought}}
+            @misc{c, note = {Nothing}}",
+        )
+        .unwrap();
+        let keys: Vec<String> = bibliography.iter().map(generate_citation_key).collect();
+        assert_eq!(keys, vec!["Wright1951", "The", "entry"]);
+    }
+
+    #[test]
+    fn test_get_date_parts() {
+        let bibliography = Bibliography::parse(
+            "@misc{a, date = {2018-10-03}}
+            @misc{b, year = {1960}, month = {12}}
+            @misc{c, year = {1960}, month = dec}
+            @misc{d, title = {No date}}",
+        )
+        .unwrap();
+        let dates: Vec<_> = bibliography.iter().map(get_date_parts).collect();
+        assert_eq!(
+            dates,
+            vec![
+                Some((2018, Some(10), Some(3))),
+                Some((1960, Some(12), None)),
+                Some((1960, Some(12), None)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_page_range() {
+        assert_eq!(format_page_range("737-8"), "737--738");
+        assert_eq!(format_page_range("89--108"), "89--108");
+        assert_eq!(format_page_range("221-42"), "221--242");
+        assert_eq!(format_page_range("e1234-e1240"), "e1234--e1240");
+        assert_eq!(format_page_range("38"), "38");
+        assert_eq!(month_from_name("December"), Some(12));
+        assert_eq!(month_from_name("Apr"), Some(4));
+        assert_eq!(month_from_name("Spring"), None);
+    }
+
+    #[test]
+    fn test_get_files_encodings() {
+        let filepaths = get_filepaths(Path::new("bib_files/encoding_files/")).unwrap();
+        let files = get_files(&filepaths, None).unwrap();
+        let bibliographies = get_bibliographies(filepaths, files).unwrap();
+        assert_eq!(bibliographies.len(), 5);
+        for bibliography in bibliographies {
+            let title = bibliography.iter().next().unwrap().title().unwrap();
+            assert!(title.format_verbatim().contains("über den Begriff"));
+        }
+    }
+
+    #[test]
+    fn test_get_bibliographies_incorrect() {
+        let filepaths = vec![PathBuf::from("bib_files/incorrect.bib")];
+        let files = get_files(&filepaths, None).unwrap();
+        let error = get_bibliographies(filepaths, files).unwrap_err();
+        assert_eq!(
+            error.kind,
+            ParseErrorKind::Bibliography(BibliographyError::MalformedField(
+                "conigliocorbalan".into(),
+                "author".into()
+            ))
+        );
+        assert_eq!((error.line, error.column), (3, 15));
+    }
+
     #[test]
     fn test_parsing_incorrect() {
         let file = fs::read_to_string("bib_files/incorrect.bib").unwrap();
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::ArgEnum;
+use serde_json::{json, Value};
+
+use super::{ComparisonResult, Config, MatchCriterion};
+
+/// What happened while unifying a set of bibliographies: the repeated entries found, what was
+/// done with each pair, and the entries that had to be added with a new key
+///
+/// Bibliographies are referred to by their position in the vec given to
+/// `unify_bibliography_with_report` (which, when running the program, is that of the input files).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnificationReport {
+    /// Every pair of entries found to be the same, in the order in which they were found
+    pub matches: Vec<MatchReport>,
+    /// Entries added with a new key because theirs was already taken
+    pub renames: Vec<KeyRename>,
+    /// Position of the bibliography being added
+    pub(crate) current: usize,
+    /// Position of the bibliography that each entry of the unified bibliography comes from
+    pub(crate) sources: HashMap<String, usize>,
+}
+
+/// Who decided what to do with two repeated entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decider {
+    /// The program (in silent mode, or because the entries were identical)
+    Automatic,
+    /// The user, when asked
+    User,
+    /// The user, on a previous run (see `RecordedDecisions`)
+    Recorded,
+}
+
+/// Two entries found to be the same, and what was done with them
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchReport {
+    /// Key of the entry that was already in the unified bibliography
+    pub prev_key: String,
+    /// Position of the bibliography that entry comes from
+    pub prev_source: usize,
+    /// Fingerprint of that entry, as returned by `entry_fingerprint` (empty unless the config
+    /// records or applies decisions, see `EntryData`)
+    pub prev_fingerprint: String,
+    /// Key of the entry being added (as it was in its bibliography)
+    pub entry_key: String,
+    /// Position of the bibliography being added
+    pub entry_source: usize,
+    pub entry_fingerprint: String,
+    pub criterion: MatchCriterion,
+    /// Similarity of their titles with the algorithm of the config (None if either has no title).
+    /// Unless they were found to be the same by their titles, it is only computed when there is a
+    /// report to write or a dry run to print
+    pub similarity: Option<f64>,
+    /// Score of the entries (only if they were compared by score, see `ScoringOptions`)
+    pub score: Option<f64>,
+    pub result: ComparisonResult,
+    pub decided_by: Decider,
+    /// Key, in the unified bibliography, of the entry that was kept (None if both were)
+    pub kept: Option<String>,
+}
+
+/// An entry added to the unified bibliography with a new key, as returned by
+/// `get_new_citation_key`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRename {
+    pub old_key: String,
+    pub new_key: String,
+    /// Position of the bibliography the entry comes from
+    pub source: usize,
+}
+
+impl MatchReport {
+    /// Key of the entry that was deleted (None if both were kept)
+    pub fn deleted(&self) -> Option<&str> {
+        match self.result {
+            ComparisonResult::KeepPrev => Some(&self.entry_key),
+            ComparisonResult::KeepEntry => Some(&self.prev_key),
+            ComparisonResult::KeepBoth => None,
+        }
+    }
+}
+
+impl UnificationReport {
+    /// Position of the bibliography that an entry of the unified bibliography comes from
+    pub fn source_of(&self, key: &str) -> usize {
+        self.sources.get(key).copied().unwrap_or(self.current)
+    }
+
+    /// Groups the matches in which an entry was deleted by the entry that was kept, in the order
+    /// in which those were found
+    pub fn group_duplicates(&self) -> Vec<(&str, Vec<&MatchReport>)> {
+        let mut groups: Vec<(&str, Vec<&MatchReport>)> = vec![];
+        for entry_match in self.matches.iter() {
+            let kept = match (&entry_match.kept, entry_match.deleted()) {
+                (Some(kept), Some(_)) => kept.as_str(),
+                _ => continue,
+            };
+            match groups
+                .iter_mut()
+                .find(|(group_kept, _)| *group_kept == kept)
+            {
+                Some((_, group)) => group.push(entry_match),
+                None => groups.push((kept, vec![entry_match])),
+            }
+        }
+        groups
+    }
+
+    /// Serialize the report as JSON
+    ///
+    /// Bibliographies are referred to by the path in `filepaths` at their position, and the
+    /// algorithm and threshold used to compare titles are taken from the config.
+    pub fn to_json(&self, filepaths: &[PathBuf], config: &Config) -> String {
+        let file = |source: usize| match filepaths.get(source) {
+            Some(path) => json!(path.to_string_lossy()),
+            None => Value::Null,
+        };
+        let algorithm = config
+            .algorithm
+            .to_possible_value()
+            .map(|value| value.get_name().to_string());
+        let matches: Vec<Value> = self
+            .matches
+            .iter()
+            .map(|entry_match| {
+                let mut value = json!({
+                    "prev": {
+                        "key": entry_match.prev_key,
+                        "file": file(entry_match.prev_source),
+                    },
+                    "entry": {
+                        "key": entry_match.entry_key,
+                        "file": file(entry_match.entry_source),
+                    },
+                    "criterion": criterion_name(entry_match.criterion),
+                    "similarity": entry_match.similarity,
+                    "algorithm": algorithm,
+                    "score": entry_match.score,
+                    "decision": format!("{:?}", entry_match.result),
+                    "interactive": entry_match.decided_by == Decider::User,
+                    "recorded": entry_match.decided_by == Decider::Recorded,
+                    "kept": entry_match.kept,
+                    "deleted": entry_match.deleted(),
+                });
+                // The rule that found them, by its position (from 1) in the list
+                if let MatchCriterion::Rule(idx) = entry_match.criterion {
+                    value["rule"] = json!(idx + 1);
+                }
+                value
+            })
+            .collect();
+        let renames: Vec<Value> = self
+            .renames
+            .iter()
+            .map(|rename| {
+                json!({
+                    "old_key": rename.old_key,
+                    "new_key": rename.new_key,
+                    "file": file(rename.source),
+                })
+            })
+            .collect();
+        let repetitions = self
+            .matches
+            .iter()
+            .filter(|m| m.deleted().is_some())
+            .count();
+        let report = json!({
+            "algorithm": algorithm,
+            "threshold": config.similarity_threshold,
+            "repetitions": repetitions,
+            "matches": matches,
+            "renames": renames,
+        });
+        let mut json = serde_json::to_string_pretty(&report).unwrap_or_else(|_| String::from("{}"));
+        json.push('\n');
+        json
+    }
+}
+
+/// Name of a criterion in the JSON report
+fn criterion_name(criterion: MatchCriterion) -> &'static str {
+    match criterion {
+        MatchCriterion::Identical => "identical",
+        MatchCriterion::Key => "key",
+        MatchCriterion::Doi => "doi",
+        MatchCriterion::Pmid => "pmid",
+        MatchCriterion::Pmcid => "pmcid",
+        MatchCriterion::Isbn => "isbn",
+        MatchCriterion::Arxiv => "arxiv",
+        MatchCriterion::IssnVolumePages => "issn-volume-pages",
+        MatchCriterion::Url => "url",
+        MatchCriterion::Title => "title",
+        MatchCriterion::SimilarTitle => "similar-title",
+        MatchCriterion::SimilarTitleSameAuthors => "similar-title-same-authors",
+        MatchCriterion::Score => "score",
+        MatchCriterion::MaybeScore => "maybe-score",
+        MatchCriterion::Rule(_) => "rule",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unify_bibliography_with_report;
+    use biblatex::Bibliography;
+
+    #[test]
+    fn test_report() {
+        let config = Config {
+            silent: true,
+            similarity_threshold: 0.7,
+            ..Default::default()
+        };
+        let bibliography1 = Bibliography::parse(
+            "@book{carnap1942, title = {Introduction to Semantics}, year = {1942}}
+            @article{prior1960, title = {The Runabout Inference-Ticket}, year = {1960}}",
+        )
+        .unwrap();
+        let bibliography2 = Bibliography::parse(
+            "@book{carnap1942b, title = {An Introduction to Semantics}, year = {1942}}
+            @article{prior1960, title = {Something else}, doi = {10.1093/analys/21.2.38}}",
+        )
+        .unwrap();
+        let (_, report) =
+            unify_bibliography_with_report(vec![bibliography1, bibliography2], &config);
+
+        assert_eq!(report.matches.len(), 2);
+        let carnap = &report.matches[0];
+        assert_eq!(carnap.prev_key, "carnap1942");
+        assert_eq!(carnap.prev_source, 0);
+        assert_eq!(carnap.entry_key, "carnap1942b");
+        assert_eq!(carnap.entry_source, 1);
+        assert_eq!(carnap.criterion, MatchCriterion::SimilarTitle);
+        assert_eq!(carnap.result, ComparisonResult::KeepPrev);
+        assert_eq!(carnap.decided_by, Decider::Automatic);
+        assert_eq!(carnap.deleted(), Some("carnap1942b"));
+        assert_eq!(report.matches[1].criterion, MatchCriterion::Key);
+        assert!(report.renames.is_empty());
+
+        let filepaths = vec![PathBuf::from("a.bib"), PathBuf::from("b.bib")];
+        let json: Value = serde_json::from_str(&report.to_json(&filepaths, &config)).unwrap();
+        assert_eq!(json["algorithm"], "levenshtein");
+        assert_eq!(json["repetitions"], 2);
+        assert_eq!(json["matches"][0]["prev"]["file"], "a.bib");
+        assert_eq!(json["matches"][0]["entry"]["file"], "b.bib");
+        assert_eq!(json["matches"][0]["criterion"], "similar-title");
+        assert_eq!(json["matches"][0]["decision"], "KeepPrev");
+        assert_eq!(json["matches"][1]["kept"], "prior1960");
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::BTreeSet;
+
+use biblatex::{Bibliography, Chunk, ChunksExt, Entry, EntryType, Person};
+
+use super::{generate_citation_key, get_date_parts, RecordError, PARENT_FIELDS};
+
+/// Get the entry type corresponding to a RIS reference type (the value of the TY tag)
+pub fn entry_type_from_ris(ris_type: &str) -> EntryType {
+    match ris_type {
+        "JOUR" | "JFULL" | "EJOUR" | "MGZN" | "NEWS" | "INPR" => EntryType::Article,
+        "BOOK" | "EBOOK" | "WHOLE" => EntryType::Book,
+        "EDBOOK" | "ANTHOL" => EntryType::Collection,
+        "CHAP" | "ECHAP" => EntryType::InCollection,
+        "CONF" => EntryType::Proceedings,
+        "CPAPER" => EntryType::InProceedings,
+        "THES" => EntryType::PhdThesis,
+        "RPRT" => EntryType::TechReport,
+        "UNPB" | "MANSCPT" => EntryType::Unpublished,
+        "ELEC" | "WEB" | "BLOG" => EntryType::Online,
+        "PAT" => EntryType::Patent,
+        "DATA" | "DBASE" => EntryType::Dataset,
+        "COMP" => EntryType::Software,
+        "ENCYC" | "DICT" => EntryType::InReference,
+        "PAMP" => EntryType::Booklet,
+        _ => EntryType::Misc,
+    }
+}
+
+/// Get the RIS reference type (the value of the TY tag) corresponding to an entry type
+pub fn ris_type_from_entry(entry_type: &EntryType) -> &'static str {
+    match entry_type {
+        EntryType::Article => "JOUR",
+        EntryType::Book | EntryType::MvBook => "BOOK",
+        EntryType::Collection | EntryType::MvCollection => "EDBOOK",
+        EntryType::InBook
+        | EntryType::BookInBook
+        | EntryType::SuppBook
+        | EntryType::InCollection
+        | EntryType::SuppCollection => "CHAP",
+        EntryType::Proceedings | EntryType::MvProceedings => "CONF",
+        EntryType::InProceedings => "CPAPER",
+        EntryType::Thesis | EntryType::PhdThesis | EntryType::MastersThesis => "THES",
+        EntryType::Report | EntryType::TechReport => "RPRT",
+        EntryType::Unpublished => "UNPB",
+        EntryType::Online => "ELEC",
+        EntryType::Patent => "PAT",
+        EntryType::Dataset => "DATA",
+        EntryType::Software => "COMP",
+        EntryType::InReference | EntryType::Reference | EntryType::MvReference => "ENCYC",
+        EntryType::Booklet => "PAMP",
+        _ => "GEN",
+    }
+}
+
+/// Whether an entry type is a part of a bigger work, whose title goes in `booktitle` (see also
+/// `is_part_of_book`)
+pub(crate) fn is_part_of_book_type(entry_type: &EntryType) -> bool {
+    matches!(
+        entry_type,
+        EntryType::InCollection
+            | EntryType::InProceedings
+            | EntryType::InBook
+            | EntryType::InReference
+    )
+}
+
+/// Parse the contents of a RIS file, returning an entry (or an error) for each record
+///
+/// Records begin with a TY tag and end with an ER tag. Lines that do not begin with a tag are
+/// taken to be the continuation of the previous one. The citation key is taken from the ID tag,
+/// or generated from the first author and the year if there is none.
+pub fn parse_ris(src: &str) -> Vec<Result<Entry, RecordError>> {
+    let mut records = vec![];
+    // Tags and values of the current record, and the line where it began
+    let mut record: Option<(usize, Vec<(String, String)>)> = None;
+    let mut record_lines: Vec<&str> = vec![];
+
+    for (idx, line) in src.lines().enumerate() {
+        let line_number = idx + 1;
+        let trimmed = line.trim_end();
+        match split_tag(trimmed) {
+            Some((tag, value)) => {
+                if record.is_none() && tag != "TY" {
+                    records.push(Err(RecordError {
+                        line: line_number,
+                        text: trimmed.to_string(),
+                        message: format!(
+                            "Tag {} found outside of a record (records must begin with TY)",
+                            tag
+                        ),
+                    }));
+                    continue;
+                }
+                if tag == "TY" && record.is_some() {
+                    let (start, _) = record.take().unwrap();
+                    records.push(Err(RecordError {
+                        line: start,
+                        text: record_lines.join("\n"),
+                        message: String::from("Record is not closed with ER before the next one"),
+                    }));
+                    record_lines.clear();
+                }
+                record_lines.push(trimmed);
+                if tag == "TY" {
+                    record = Some((line_number, vec![]));
+                }
+                let (start, tags) = record.as_mut().unwrap();
+                if tag == "ER" {
+                    records.push(record_to_entry(tags).ok_or_else(|| RecordError {
+                        line: *start,
+                        text: record_lines.join("\n"),
+                        message: String::from("Record has no author, title or year"),
+                    }));
+                    record = None;
+                    record_lines.clear();
+                } else {
+                    tags.push((tag.to_string(), value.to_string()));
+                }
+            }
+            None if trimmed.trim().is_empty() => {}
+            None => match record.as_mut() {
+                // A continuation of the previous value
+                Some((_, tags)) if !tags.is_empty() => {
+                    record_lines.push(trimmed);
+                    let value = &mut tags.last_mut().unwrap().1;
+                    value.push(' ');
+                    value.push_str(trimmed.trim());
+                }
+                _ => records.push(Err(RecordError {
+                    line: line_number,
+                    text: trimmed.to_string(),
+                    message: String::from("Line is not a RIS tag"),
+                })),
+            },
+        }
+    }
+
+    if let Some((start, _)) = record {
+        records.push(Err(RecordError {
+            line: start,
+            text: record_lines.join("\n"),
+            message: String::from("Record is never closed with ER"),
+        }));
+    }
+    records
+}
+
+/// Split a line into its tag and value (e.g. "TI  - A title" into "TI" and "A title")
+fn split_tag(line: &str) -> Option<(&str, &str)> {
+    let tag = line.get(..2)?;
+    let rest = line.get(2..)?;
+    if !tag
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return None;
+    }
+    let value = rest.trim_start_matches(' ').strip_prefix('-')?;
+    Some((tag, value.trim()))
+}
+
+/// Convert the tags of a RIS record into an entry
+///
+/// Returns None if the record has no author, title or year at all.
+fn record_to_entry(tags: &[(String, String)]) -> Option<Entry> {
+    let get = |names: &[&str]| -> Vec<&str> {
+        tags.iter()
+            .filter(|(tag, value)| names.contains(&tag.as_str()) && !value.is_empty())
+            .map(|(_, value)| value.as_str())
+            .collect()
+    };
+    let first = |names: &[&str]| get(names).first().map(|value| value.to_string());
+
+    let ris_type = first(&["TY"]).unwrap_or_default();
+    let entry_type = entry_type_from_ris(&ris_type);
+    let mut entry = Entry::new(String::new(), entry_type.clone());
+    let mut set = |field: &str, value: String| entry.set(field, vec![Chunk::Normal(value)]);
+
+    let authors = get(&["AU", "A1"]);
+    if !authors.is_empty() {
+        set("author", authors.join(" and "));
+    }
+    let editors = get(&["ED", "A2"]);
+    if !editors.is_empty() {
+        // For most types, A2 holds the editors of the containing work
+        set("editor", editors.join(" and "));
+    }
+    let translators = get(&["A4"]);
+    if !translators.is_empty() {
+        set("translator", translators.join(" and "));
+    }
+    if let Some(title) = first(&["TI", "T1", "CT"]) {
+        set("title", title);
+    }
+
+    // The secondary title is the journal for articles and the book for chapters
+    let secondary_title = first(&["T2", "BT"]);
+    match entry_type {
+        EntryType::Article => {
+            if let Some(journal) = first(&["JO", "JF", "T2", "JA", "J2"]) {
+                set("journal", journal);
+            }
+        }
+        _ if is_part_of_book_type(&entry_type) => {
+            if let Some(book_title) = secondary_title {
+                set("booktitle", book_title);
+            }
+        }
+        _ => {}
+    }
+    if let Some(series) = first(&["T3"]) {
+        set("series", series);
+    }
+
+    // Dates are of the form "YYYY/MM/DD/other", where any part may be missing
+    let date = first(&["PY", "Y1", "DA"]).unwrap_or_default();
+    let mut date_parts = date.split('/');
+    if let Some(year) = date_parts.next().filter(|year| !year.trim().is_empty()) {
+        set("year", year.trim().to_string());
+    }
+    if let Some(month) = date_parts
+        .next()
+        .and_then(|month| month.trim().parse::<u8>().ok())
+    {
+        set("month", month.to_string());
+    }
+
+    if let Some(volume) = first(&["VL"]) {
+        set("volume", volume);
+    }
+    if let Some(number) = first(&["IS", "M1"]) {
+        set("number", number);
+    }
+    match (first(&["SP"]), first(&["EP"])) {
+        (Some(start), Some(end)) => set("pages", format!("{}--{}", start, end)),
+        (Some(pages), None) => set("pages", pages.replace('-', "--").replace("----", "--")),
+        _ => {}
+    }
+    if let Some(publisher) = first(&["PB"]) {
+        match entry_type {
+            EntryType::PhdThesis => set("school", publisher),
+            EntryType::TechReport => set("institution", publisher),
+            _ => set("publisher", publisher),
+        }
+    }
+    if let Some(address) = first(&["CY", "PP"]) {
+        set("address", address);
+    }
+    if let Some(doi) = first(&["DO"]) {
+        set("doi", doi);
+    }
+    if let Some(serial_number) = first(&["SN"]) {
+        // SN holds the ISBN for books and the ISSN for periodicals
+        match entry_type {
+            EntryType::Book | EntryType::Collection | EntryType::Proceedings => {
+                set("isbn", serial_number)
+            }
+            _ if is_part_of_book_type(&entry_type) => set("isbn", serial_number),
+            _ => set("issn", serial_number),
+        }
+    }
+    if let Some(url) = first(&["UR", "L2"]) {
+        set("url", url);
+    }
+    if let Some(edition) = first(&["ET"]) {
+        set("edition", edition);
+    }
+    if let Some(language) = first(&["LA"]) {
+        set("language", language);
+    }
+    if let Some(abstract_) = first(&["AB", "N2"]) {
+        set("abstract", abstract_);
+    }
+    let keywords = get(&["KW"]);
+    if !keywords.is_empty() {
+        set("keywords", keywords.join(", "));
+    }
+    let notes = get(&["N1"]);
+    if !notes.is_empty() {
+        set("note", notes.join(". "));
+    }
+
+    if entry.get("author").is_none() && entry.get("title").is_none() && entry.get("year").is_none()
+    {
+        return None;
+    }
+    entry.key = match first(&["ID"]) {
+        Some(id) if !id.contains(char::is_whitespace) => id,
+        _ => generate_citation_key(&entry),
+    };
+    Some(entry)
+}
+
+/// Fields that are written to RIS (every other field is dropped)
+///
+/// Names go to AU, ED and A4, titles to TI, T2 (journal or book) and T3 (series), dates to PY and DA,
+/// pages to SP and EP, and the rest to the tag of the same meaning (e.g. `doi` to DO).
+pub const RIS_EXPORTED_FIELDS: [&str; 31] = [
+    "author",
+    "editor",
+    "translator",
+    "title",
+    "journal",
+    "journaltitle",
+    "booktitle",
+    "series",
+    "date",
+    "year",
+    "month",
+    "day",
+    "volume",
+    "number",
+    "issue",
+    "pages",
+    "publisher",
+    "school",
+    "institution",
+    "address",
+    "location",
+    "doi",
+    "isbn",
+    "issn",
+    "url",
+    "edition",
+    "language",
+    "abstract",
+    "keywords",
+    "note",
+    "urldate",
+];
+
+/// Serialize a bibliography into RIS records
+///
+/// Entries inherit the fields of the entries they reference through crossref or xdata, since RIS
+/// has no way of expressing those references. Fields not in `RIS_EXPORTED_FIELDS` are dropped
+/// (see `dropped_ris_fields`).
+pub fn to_ris_string(bibliography: &Bibliography) -> String {
+    let mut ris = String::new();
+    for entry in bibliography.iter() {
+        let entry = bibliography
+            .get_resolved(&entry.key)
+            .unwrap_or_else(|| entry.clone());
+        for (tag, value) in entry_to_record(&entry) {
+            ris.push_str(&format!("{}  - {}\n", tag, value));
+        }
+        ris.push_str("ER  - \n\n");
+    }
+    ris
+}
+
+/// Fields of a bibliography that have no RIS tag, and are thus dropped by `to_ris_string`
+pub fn dropped_ris_fields(bibliography: &Bibliography) -> Vec<String> {
+    let dropped: BTreeSet<String> = bibliography
+        .iter()
+        .flat_map(|entry| entry.fields.keys())
+        .map(|field| field.to_lowercase())
+        .filter(|field| {
+            !RIS_EXPORTED_FIELDS.contains(&field.as_str()) && !PARENT_FIELDS.contains(&field.as_str())
+        })
+        .collect();
+    dropped.into_iter().collect()
+}
+
+/// Convert an entry into the tags of a RIS record (without the final ER)
+fn entry_to_record(entry: &Entry) -> Vec<(&'static str, String)> {
+    // Field names keep the case they were written in (e.g. "ISSN")
+    let get = |field: &str| {
+        entry
+            .fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field))
+            .map(|(_, value)| value.format_verbatim())
+            .filter(|value| !value.is_empty())
+    };
+    let mut tags = vec![("TY", ris_type_from_entry(&entry.entry_type).to_string())];
+    tags.push(("ID", entry.key.clone()));
+
+    for (field, tag) in [("author", "AU"), ("editor", "ED"), ("translator", "A4")] {
+        for person in entry.get_as::<Vec<Person>>(field).unwrap_or_default() {
+            tags.push((tag, name_to_ris(&person)));
+        }
+    }
+    if let Some(title) = get("title") {
+        tags.push(("TI", title));
+    }
+    if let Some(secondary_title) = get("journaltitle")
+        .or_else(|| get("journal"))
+        .or_else(|| get("booktitle"))
+    {
+        tags.push(("T2", secondary_title));
+    }
+    if let Some(series) = get("series") {
+        tags.push(("T3", series));
+    }
+
+    // Dates are written as "YYYY/MM/DD/", leaving out the unknown parts
+    if let Some((year, month, day)) = get_date_parts(entry) {
+        tags.push(("PY", year.to_string()));
+        if let Some(month) = month {
+            let day = day.map(|day| format!("{:02}", day)).unwrap_or_default();
+            tags.push(("DA", format!("{}/{:02}/{}/", year, month, day)));
+        }
+    } else if let Some(year) = get("year") {
+        tags.push(("PY", year));
+    }
+
+    if let Some(volume) = get("volume") {
+        tags.push(("VL", volume));
+    }
+    if let Some(number) = get("number").or_else(|| get("issue")) {
+        tags.push(("IS", number));
+    }
+    if let Some(pages) = get("pages") {
+        match pages.split_once('-') {
+            Some((start, end)) => {
+                tags.push(("SP", start.trim().to_string()));
+                tags.push(("EP", end.trim_start_matches('-').trim().to_string()));
+            }
+            None => tags.push(("SP", pages)),
+        }
+    }
+    if let Some(publisher) = get("publisher")
+        .or_else(|| get("school"))
+        .or_else(|| get("institution"))
+    {
+        tags.push(("PB", publisher));
+    }
+    if let Some(address) = get("address").or_else(|| get("location")) {
+        tags.push(("CY", address));
+    }
+    for (field, tag) in [
+        ("doi", "DO"),
+        ("isbn", "SN"),
+        ("issn", "SN"),
+        ("url", "UR"),
+        ("urldate", "Y2"),
+        ("edition", "ET"),
+        ("language", "LA"),
+        ("abstract", "AB"),
+    ] {
+        if let Some(value) = get(field) {
+            tags.push((tag, value));
+        }
+    }
+    if let Some(keywords) = get("keywords") {
+        for keyword in keywords.split(&[',', ';'][..]).map(str::trim) {
+            if !keyword.is_empty() {
+                tags.push(("KW", keyword.to_string()));
+            }
+        }
+    }
+    if let Some(note) = get("note") {
+        tags.push(("N1", note));
+    }
+    // Values cannot span more than one line
+    tags.into_iter()
+        .map(|(tag, value)| (tag, value.split_whitespace().collect::<Vec<&str>>().join(" ")))
+        .collect()
+}
+
+/// Write a name in the "Last, First, Suffix" form RIS uses (with any particle in the last name)
+fn name_to_ris(person: &Person) -> String {
+    let last_name = match person.prefix.is_empty() {
+        true => person.name.clone(),
+        false => format!("{} {}", person.prefix, person.name),
+    };
+    [last_name, person.given_name.clone(), person.suffix.clone()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_ris() {
+        let src = fs::read_to_string("bib_files/ris_files/references.ris").unwrap();
+        let records = parse_ris(&src);
+        assert_eq!(records.len(), 3);
+
+        let prior = records[0].as_ref().unwrap();
+        assert_eq!(prior.key, "Prior1960");
+        assert_eq!(prior.entry_type, EntryType::Article);
+        assert_eq!(
+            prior.title().unwrap().format_verbatim(),
+            "The Runabout Inference-Ticket"
+        );
+        assert_eq!(prior.journal().unwrap().format_verbatim(), "Analysis");
+        assert_eq!(prior.author().unwrap()[0].name, "Prior");
+        assert_eq!(prior.get("pages").unwrap().format_verbatim(), "38--39");
+        assert_eq!(prior.get("month").unwrap().format_verbatim(), "12");
+        assert_eq!(prior.doi().unwrap(), "10.1093/analys/21.2.38");
+
+        let chapter = records[1].as_ref().unwrap();
+        assert_eq!(chapter.key, "BPS2018-WIAPL");
+        assert_eq!(chapter.entry_type, EntryType::InCollection);
+        assert_eq!(chapter.author().unwrap().len(), 3);
+        assert_eq!(
+            chapter.book_title().unwrap().format_verbatim(),
+            "Between Consistency and Inconsistency"
+        );
+        assert_eq!(chapter.editors()[0].0.len(), 2);
+        // The title continues in the next line
+        assert_eq!(
+            chapter.title().unwrap().format_verbatim(),
+            "What is a Paraconsistent Logic?"
+        );
+
+        let error = records[2].as_ref().unwrap_err();
+        assert_eq!(error.line, 31);
+        assert_eq!(error.message, "Record is never closed with ER");
+    }
+
+    #[test]
+    fn test_to_ris_string() {
+        let bibliography = Bibliography::parse(
+            "@incollection{chapter, author = {van Benthem, Johan and {ACME}}, title = {Chapter},
+                crossref = {book}, pages = {89--108}, month = {10}, year = {2018},
+                keywords = {logic, modality}, file = {chapter.pdf}}
+            @collection{book, title = {The Book}, editor = {Smith, John}, publisher = {Springer},
+                isbn = {978-3-319-98797-2}}",
+        )
+        .unwrap();
+        let ris = to_ris_string(&bibliography);
+        let expected_chapter = "TY  - CHAP\nID  - chapter\nAU  - van Benthem, Johan\nAU  - ACME\n\
+            ED  - Smith, John\nTI  - Chapter\nT2  - The Book\nPY  - 2018\nDA  - 2018/10//\n\
+            SP  - 89\nEP  - 108\nPB  - Springer\nSN  - 978-3-319-98797-2\nKW  - logic\n\
+            KW  - modality\nER  - \n\n";
+        assert!(ris.starts_with(expected_chapter));
+        assert_eq!(dropped_ris_fields(&bibliography), vec!["file"]);
+
+        // Reading the output back gives the same entries
+        let entries: Vec<Entry> = parse_ris(&ris).into_iter().flatten().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "chapter");
+        assert_eq!(entries[0].entry_type, EntryType::InCollection);
+        assert_eq!(entries[0].author().unwrap()[0].prefix, "van");
+        assert_eq!(entries[0].get("pages").unwrap().format_verbatim(), "89--108");
+        assert_eq!(entries[1].entry_type, EntryType::Collection);
+    }
+
+    #[test]
+    fn test_split_tag() {
+        assert_eq!(split_tag("TY  - JOUR"), Some(("TY", "JOUR")));
+        assert_eq!(split_tag("ER  -"), Some(("ER", "")));
+        assert_eq!(split_tag("Logic?"), None);
+    }
+}
@@ -0,0 +1,686 @@
+use biblatex::Entry;
+use clap::ArgEnum;
+use serde_json::Value;
+
+use super::{
+    get_arxiv_id, get_date_parts, get_field, is_part_of_book, normalize_arxiv_id, normalize_doi,
+    normalize_isbns, normalize_issns, normalize_pages, normalize_pubmed_id, normalize_title,
+    normalize_url, title_similarity, AuthorsComparison, Config, EntryPair, MatchCriterion,
+};
+
+/// Name of the pseudo-field with the citation key of an entry
+pub const ENTRY_KEY_FIELD: &str = "entrykey";
+/// Name of the pseudo-field with the type of an entry (e.g. "article")
+pub const ENTRY_TYPE_FIELD: &str = "entrytype";
+/// Name of the pseudo-field with a whole entry (two entries are only the same if they are equal
+/// in all fields and in key)
+pub const ENTRY_FIELD: &str = "entry";
+/// Name of the pseudo-field with the authors (or editors) of an entry, compared as in
+/// `AuthorList::compare` (or by `AuthorList::overlap`, if they are to be similar)
+pub const ENTRY_AUTHORS_FIELD: &str = "entryauthors";
+/// Name of the pseudo-field with the year of an entry (from its `date` or `year` field)
+pub const ENTRY_YEAR_FIELD: &str = "entryyear";
+/// Name of the pseudo-field with the arXiv identifier of an entry (see `get_arxiv_id`)
+pub const ENTRY_ARXIV_FIELD: &str = "entryarxiv";
+/// Name of the pseudo-field with the score of two entries (see `score_entries`), which is the
+/// same if it is at least the threshold of the condition, whatever its comparator
+pub const ENTRY_SCORE_FIELD: &str = "entryscore";
+/// Item of a rules file that stands for the built-in rules (see `default_rules`)
+pub const DEFAULT_RULES_ITEM: &str = "defaults";
+
+/// What a rule says about two entries its conditions hold for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum RuleAction {
+    /// They are the same: the first is kept without asking
+    Duplicate,
+    /// They are not the same, whatever the rules after it say
+    NotDuplicate,
+    /// They may be the same: the user is asked (in silent mode, both are kept)
+    Ask,
+    /// They are the same: the user is asked which to keep (in silent mode, the first is kept)
+    Choose,
+}
+
+/// How the values of the fields are normalized before comparing them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum RuleNormalizer {
+    /// Only the whitespace at the ends is removed
+    None,
+    /// Lowercase, and without whitespace at the ends
+    Lowercase,
+    /// As titles are (see `normalize_title`), with the steps of the config
+    Title,
+    /// See `normalize_doi`
+    Doi,
+    /// See `normalize_pubmed_id`
+    Pubmed,
+    /// Every ISBN in the field, as ISBN-13 (see `normalize_isbns`). Entries that are part of a
+    /// book (e.g. a chapter) have none, as they have the ISBN of the book
+    Isbn,
+    /// Every ISSN in the field (see `normalize_issns`)
+    Issn,
+    /// See `normalize_arxiv_id`
+    Arxiv,
+    /// See `normalize_url`
+    Url,
+    /// See `normalize_pages`
+    Pages,
+}
+
+/// How the values of the fields of two entries are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum RuleComparator {
+    /// Both have every field, with the same value (or, for fields with several values, such as
+    /// ISBNs, with a value in common)
+    Same,
+    /// Both have every field, and they differ in at least one
+    Different,
+    /// Both have every field, and the values of each are similar according to the threshold of
+    /// the condition and the algorithm of the config (with a threshold of 1, they must be the
+    /// same)
+    Similar,
+    /// Either entry lacks one of the fields
+    Missing,
+}
+
+/// Threshold of a condition, for `RuleComparator::Similar` and `ENTRY_SCORE_FIELD`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleThreshold {
+    /// A number between 0 and 1
+    Value(f64),
+    /// The config's `similarity_threshold`
+    Similarity,
+    /// The config's `same_authors_threshold`, unless its `similarity_threshold` is lower
+    SameAuthors,
+    /// The `same_score` of the config's `scoring`
+    SameScore,
+    /// The `maybe_score` of the config's `scoring`
+    MaybeScore,
+}
+
+/// A condition about some fields of two entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleCondition {
+    /// Names of the fields (including the pseudo-fields, such as `ENTRY_KEY_FIELD`)
+    pub fields: Vec<String>,
+    pub normalizer: RuleNormalizer,
+    pub comparator: RuleComparator,
+    pub threshold: RuleThreshold,
+}
+
+/// A rule about when two entries are the same: if all its conditions hold, its action applies
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchRule {
+    /// Shown when the rule applies (optional)
+    pub name: Option<String>,
+    /// A rule without conditions applies to every pair of entries
+    pub conditions: Vec<RuleCondition>,
+    pub action: RuleAction,
+    /// The reason given when the rule applies (if None, `MatchCriterion::Rule` with its
+    /// position)
+    pub criterion: Option<MatchCriterion>,
+}
+
+/// Rules about when two entries are the same, evaluated in order
+///
+/// They are read from a JSON array of rules such as
+/// `{"name": "...", "conditions": [{"fields": ["doi"], "normalizer": "doi", "comparator":
+/// "different"}], "action": "not-duplicate"}`. A rule with a single condition may have its
+/// `fields`, `normalizer`, `comparator` and `threshold` instead of `conditions`. The built-in
+/// rules (see `default_rules`) go where the array has the item `DEFAULT_RULES_ITEM`, or else
+/// after the rules of the array. By default, there are only the built-in rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchRules {
+    pub rules: Vec<MatchRule>,
+}
+
+impl RuleThreshold {
+    /// The threshold with a name, as given in a rules file (None if there is none)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "similarity" => Some(RuleThreshold::Similarity),
+            "same-authors" => Some(RuleThreshold::SameAuthors),
+            "same-score" => Some(RuleThreshold::SameScore),
+            "maybe-score" => Some(RuleThreshold::MaybeScore),
+            _ => None,
+        }
+    }
+
+    /// The value of the threshold with the options of the config
+    pub fn value(&self, config: &Config) -> f64 {
+        match self {
+            RuleThreshold::Value(threshold) => *threshold,
+            RuleThreshold::Similarity => config.similarity_threshold,
+            RuleThreshold::SameAuthors => config
+                .same_authors_threshold
+                .map_or(config.similarity_threshold, |threshold| {
+                    threshold.min(config.similarity_threshold)
+                }),
+            RuleThreshold::SameScore => config.scoring.same_score,
+            RuleThreshold::MaybeScore => config.scoring.maybe_score,
+        }
+    }
+}
+
+impl RuleCondition {
+    /// The values of a field of an entry, normalized (empty if it does not have it)
+    fn values(&self, entry: &Entry, field: &str, config: &Config) -> Vec<String> {
+        let value = if field.eq_ignore_ascii_case(ENTRY_KEY_FIELD) {
+            Some(entry.key.clone())
+        } else if field.eq_ignore_ascii_case(ENTRY_TYPE_FIELD) {
+            Some(entry.entry_type.to_string().to_lowercase())
+        } else if field.eq_ignore_ascii_case(ENTRY_YEAR_FIELD) {
+            get_date_parts(entry).map(|(year, _, _)| year.to_string())
+        } else if field.eq_ignore_ascii_case(ENTRY_ARXIV_FIELD) {
+            get_arxiv_id(entry)
+        } else {
+            get_field(entry, field)
+        };
+        let value = match value {
+            Some(value) => value,
+            None => return vec![],
+        };
+        let values = match self.normalizer {
+            RuleNormalizer::None => vec![value.trim().to_string()],
+            RuleNormalizer::Lowercase => vec![value.trim().to_lowercase()],
+            RuleNormalizer::Title => vec![normalize_title(&value, &config.title_normalization)],
+            RuleNormalizer::Doi => normalize_doi(&value).into_iter().collect(),
+            RuleNormalizer::Pubmed => normalize_pubmed_id(&value).into_iter().collect(),
+            RuleNormalizer::Isbn if is_part_of_book(entry) => vec![],
+            RuleNormalizer::Isbn => normalize_isbns(&value),
+            RuleNormalizer::Issn => normalize_issns(&value),
+            RuleNormalizer::Arxiv => normalize_arxiv_id(&value).into_iter().collect(),
+            RuleNormalizer::Url => normalize_url(&value).into_iter().collect(),
+            RuleNormalizer::Pages => vec![normalize_pages(&value)],
+        };
+        values
+            .into_iter()
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+
+    /// Whether a field of two entries is the same (or similar, if that is the comparator). None
+    /// if either entry lacks it
+    fn compare(&self, pair: &EntryPair, field: &str, config: &Config) -> Option<bool> {
+        let threshold = self.threshold.value(config);
+        // Values similar with a threshold of 1 are the same, which is much faster to check
+        let similar = self.comparator == RuleComparator::Similar && threshold < 1.0;
+        let is = |name: &str| field.eq_ignore_ascii_case(name);
+        if is(ENTRY_FIELD) {
+            return Some(pair.prev_entry == pair.entry);
+        }
+        if is(ENTRY_AUTHORS_FIELD) {
+            if config.ignore_authors {
+                return None;
+            }
+            let (prev_authors, authors) = (&pair.prev_data.authors, &pair.data.authors);
+            return match similar {
+                true => prev_authors
+                    .overlap(authors)
+                    .map(|overlap| overlap >= threshold),
+                false => match prev_authors.compare(authors) {
+                    AuthorsComparison::Same => Some(true),
+                    AuthorsComparison::Different => Some(false),
+                    AuthorsComparison::Unknown => None,
+                },
+            };
+        }
+        if is(ENTRY_SCORE_FIELD) {
+            // Entries that cannot be scored have a score of 0
+            return match config.scoring.scoring {
+                true => Some(pair.score(config).unwrap_or_default() >= threshold),
+                false => None,
+            };
+        }
+        // The titles normalized as the config says are those of the entries' `EntryData`
+        if is("title") && self.normalizer == RuleNormalizer::Title {
+            let (prev_title, title) = (pair.prev_data.title.as_ref()?, pair.data.title.as_ref()?);
+            return Some(match similar {
+                true => pair.title_similarity(config).unwrap_or_default() >= threshold,
+                false => prev_title == title,
+            });
+        }
+        let prev_values = self.values(pair.prev_entry, field, config);
+        let values = self.values(pair.entry, field, config);
+        if prev_values.is_empty() || values.is_empty() {
+            return None;
+        }
+        Some(prev_values.iter().any(|prev_value| {
+            values.iter().any(|value| match similar {
+                true => title_similarity(prev_value, value, config) >= threshold,
+                false => prev_value == value,
+            })
+        }))
+    }
+
+    /// Whether the condition holds for two entries
+    pub fn holds(&self, pair: &EntryPair, config: &Config) -> bool {
+        let compare = |field: &String| self.compare(pair, field, config);
+        match self.comparator {
+            RuleComparator::Same | RuleComparator::Similar => {
+                self.fields.iter().all(|field| compare(field) == Some(true))
+            }
+            RuleComparator::Different => {
+                let compared: Option<Vec<bool>> = self.fields.iter().map(compare).collect();
+                compared.unwrap_or_default().contains(&false)
+            }
+            RuleComparator::Missing => self.fields.iter().any(|field| compare(field).is_none()),
+        }
+    }
+}
+
+impl MatchRule {
+    /// Name of the rule to show, given its position in the list (e.g. "rule 2 (same ISBN)")
+    pub fn describe(&self, idx: usize) -> String {
+        match &self.name {
+            Some(name) => format!("rule {} ({})", idx + 1, name),
+            None => format!("rule {}", idx + 1),
+        }
+    }
+}
+
+/// The built-in rules, which find repeated entries when no rules are given. Two entries are the
+/// same (and the user is asked which to keep, unless they are identical) if, in this order:
+///
+/// - They are equal in all fields & in key
+/// - They have the same cite key
+/// - They have the same DOI (must be present in both), ignoring case and the resolver prefix
+/// - They have the same PMID or PMCID (must be present in both)
+/// - They share an ISBN, unless either is part of a book (e.g. a chapter, which has the ISBN of
+///   the book)
+/// - They have the same arXiv identifier, ignoring its version (from the `eprint`, `url` or
+///   `doi` fields)
+/// - They have the same ISSN, volume and pages
+/// - They have the same URL, ignoring the scheme, "www." and the trailing slash
+/// - They have the same title
+/// - The similarity threshold is <1 and they have similar titles
+/// - They have the same authors and year, the config's `same_authors_threshold` is <1 and their
+///   titles are similar according to it
+///
+/// Entries whose authors differ (see `AuthorList::compare`) are not compared by title, unless
+/// the config says to `ignore_authors`. If the config enables `scoring`, the last three are
+/// replaced by the score of the entries (see `score_entries`): they are the same (without
+/// asking) if it is at least `same_score`, and may be the same if it is at least `maybe_score`.
+pub fn default_rules() -> Vec<MatchRule> {
+    let condition = |field: &str, normalizer, comparator, threshold| RuleCondition {
+        fields: vec![String::from(field)],
+        normalizer,
+        comparator,
+        threshold,
+    };
+    let same = |field: &str, normalizer| {
+        condition(
+            field,
+            normalizer,
+            RuleComparator::Same,
+            RuleThreshold::Similarity,
+        )
+    };
+    let similar = |field: &str, normalizer, threshold| {
+        condition(field, normalizer, RuleComparator::Similar, threshold)
+    };
+    let score = |threshold| {
+        condition(
+            ENTRY_SCORE_FIELD,
+            RuleNormalizer::None,
+            RuleComparator::Similar,
+            threshold,
+        )
+    };
+    let rule = |name: &str, conditions, action, criterion| MatchRule {
+        name: Some(String::from(name)),
+        conditions,
+        action,
+        criterion,
+    };
+    vec![
+        rule(
+            "identical entries",
+            vec![same(ENTRY_FIELD, RuleNormalizer::None)],
+            RuleAction::Duplicate,
+            Some(MatchCriterion::Identical),
+        ),
+        rule(
+            "same key",
+            vec![same(ENTRY_KEY_FIELD, RuleNormalizer::None)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Key),
+        ),
+        rule(
+            "same DOI",
+            vec![same("doi", RuleNormalizer::Doi)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Doi),
+        ),
+        rule(
+            "same PMID",
+            vec![same("pmid", RuleNormalizer::Pubmed)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Pmid),
+        ),
+        rule(
+            "same PMCID",
+            vec![same("pmcid", RuleNormalizer::Pubmed)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Pmcid),
+        ),
+        rule(
+            "same ISBN",
+            vec![same("isbn", RuleNormalizer::Isbn)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Isbn),
+        ),
+        rule(
+            "same arXiv identifier",
+            vec![same(ENTRY_ARXIV_FIELD, RuleNormalizer::None)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Arxiv),
+        ),
+        rule(
+            "same ISSN, volume and pages",
+            vec![
+                same("issn", RuleNormalizer::Issn),
+                same("volume", RuleNormalizer::Lowercase),
+                same("pages", RuleNormalizer::Pages),
+            ],
+            RuleAction::Choose,
+            Some(MatchCriterion::IssnVolumePages),
+        ),
+        rule(
+            "same URL",
+            vec![same("url", RuleNormalizer::Url)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Url),
+        ),
+        rule(
+            "score of at least --same-score",
+            vec![score(RuleThreshold::SameScore)],
+            RuleAction::Duplicate,
+            Some(MatchCriterion::Score),
+        ),
+        rule(
+            "score of at least --maybe-score",
+            vec![score(RuleThreshold::MaybeScore)],
+            RuleAction::Ask,
+            Some(MatchCriterion::MaybeScore),
+        ),
+        // When scoring, titles are not compared by themselves
+        rule(
+            "lower score",
+            vec![score(RuleThreshold::Value(0.0))],
+            RuleAction::NotDuplicate,
+            None,
+        ),
+        rule(
+            "different authors",
+            vec![condition(
+                ENTRY_AUTHORS_FIELD,
+                RuleNormalizer::None,
+                RuleComparator::Different,
+                RuleThreshold::Similarity,
+            )],
+            RuleAction::NotDuplicate,
+            None,
+        ),
+        rule(
+            "same title",
+            vec![same("title", RuleNormalizer::Title)],
+            RuleAction::Choose,
+            Some(MatchCriterion::Title),
+        ),
+        rule(
+            "similar titles",
+            vec![similar(
+                "title",
+                RuleNormalizer::Title,
+                RuleThreshold::Similarity,
+            )],
+            RuleAction::Choose,
+            Some(MatchCriterion::SimilarTitle),
+        ),
+        rule(
+            "same authors and year, and similar titles",
+            vec![
+                same(ENTRY_AUTHORS_FIELD, RuleNormalizer::None),
+                same(ENTRY_YEAR_FIELD, RuleNormalizer::None),
+                similar(
+                    "title",
+                    RuleNormalizer::Title,
+                    RuleThreshold::SameAuthors,
+                ),
+            ],
+            RuleAction::Choose,
+            Some(MatchCriterion::SimilarTitleSameAuthors),
+        ),
+    ]
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules {
+            rules: default_rules(),
+        }
+    }
+}
+
+impl MatchRules {
+    /// Parse the contents of a rules file
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let items: Vec<Value> = serde_json::from_str(src).map_err(|e| e.to_string())?;
+        let mut rules = vec![];
+        let mut defaults = false;
+        for (idx, item) in items.iter().enumerate() {
+            let error = |message: String| format!("Rule {}: {}", idx + 1, message);
+            if item.as_str() == Some(DEFAULT_RULES_ITEM) {
+                if defaults {
+                    return Err(error(format!(
+                        "\"{}\" can only be given once",
+                        DEFAULT_RULES_ITEM
+                    )));
+                }
+                rules.extend(default_rules());
+                defaults = true;
+                continue;
+            }
+            let action = item["action"]
+                .as_str()
+                .and_then(|action| RuleAction::from_str(action, true).ok())
+                .ok_or_else(|| {
+                    error(String::from(
+                        "\"action\" must be \"duplicate\", \"not-duplicate\", \"ask\" or \"choose\"",
+                    ))
+                })?;
+            let conditions = match &item["conditions"] {
+                Value::Array(conditions) => conditions
+                    .iter()
+                    .map(parse_condition)
+                    .collect::<Result<Vec<RuleCondition>, String>>()
+                    .map_err(error)?,
+                Value::Null if item["fields"].is_null() => vec![],
+                Value::Null => vec![parse_condition(item).map_err(error)?],
+                _ => return Err(error(String::from("\"conditions\" must be an array"))),
+            };
+            rules.push(MatchRule {
+                name: item["name"].as_str().map(String::from),
+                conditions,
+                action,
+                criterion: None,
+            });
+        }
+        // Without a place for them, the built-in rules go after the others
+        if !defaults {
+            rules.extend(default_rules());
+        }
+        Ok(MatchRules { rules })
+    }
+
+    /// The first rule whose conditions hold for two entries, with its position (None if none
+    /// does)
+    pub fn evaluate(&self, pair: &EntryPair, config: &Config) -> Option<(usize, &MatchRule)> {
+        self.rules.iter().enumerate().find(|(_, rule)| {
+            rule.conditions
+                .iter()
+                .all(|condition| condition.holds(pair, config))
+        })
+    }
+}
+
+/// Parse a condition of a rule
+fn parse_condition(item: &Value) -> Result<RuleCondition, String> {
+    let fields: Vec<String> = match &item["fields"] {
+        Value::String(field) => vec![field.clone()],
+        Value::Array(fields) => fields
+            .iter()
+            .filter_map(|field| field.as_str().map(String::from))
+            .collect(),
+        _ => vec![],
+    };
+    if fields.is_empty() {
+        return Err(String::from("a condition has no \"fields\""));
+    }
+    let normalizer = match item["normalizer"].as_str() {
+        Some(normalizer) => RuleNormalizer::from_str(normalizer, true)
+            .map_err(|_| format!("unknown normalizer \"{}\"", normalizer))?,
+        None => RuleNormalizer::None,
+    };
+    let comparator = match item["comparator"].as_str() {
+        Some(comparator) => RuleComparator::from_str(comparator, true)
+            .map_err(|_| format!("unknown comparator \"{}\"", comparator))?,
+        None => RuleComparator::Same,
+    };
+    let threshold = match &item["threshold"] {
+        Value::Null => RuleThreshold::Similarity,
+        Value::String(name) => RuleThreshold::from_name(name)
+            .ok_or_else(|| format!("unknown threshold \"{}\"", name))?,
+        threshold => match threshold.as_f64() {
+            Some(threshold) if (0.0..=1.0).contains(&threshold) => RuleThreshold::Value(threshold),
+            _ => {
+                return Err(String::from(
+                    "\"threshold\" must be a number between 0 and 1, or the name of one",
+                ))
+            }
+        },
+    };
+    Ok(RuleCondition {
+        fields,
+        normalizer,
+        comparator,
+        threshold,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntryData;
+    use biblatex::Bibliography;
+
+    #[test]
+    fn test_match_rules() {
+        let src = r#"[
+            {
+                "name": "different DOIs",
+                "fields": ["doi"],
+                "normalizer": "doi",
+                "comparator": "different",
+                "action": "not-duplicate"
+            },
+            {
+                "conditions": [
+                    {"fields": "entrykey"},
+                    {"fields": ["entrytype"], "comparator": "different"}
+                ],
+                "action": "ask"
+            },
+            {"fields": ["isbn"], "normalizer": "isbn", "action": "duplicate"},
+            {
+                "fields": ["title"],
+                "normalizer": "title",
+                "comparator": "similar",
+                "threshold": 0.8,
+                "action": "duplicate"
+            }
+        ]"#;
+        let rules = MatchRules::parse(src).unwrap();
+        // The built-in rules go after them
+        assert_eq!(rules.rules.len(), 4 + default_rules().len());
+        assert_eq!(rules.rules[4..], default_rules());
+        assert_eq!(rules.rules[0].describe(0), "rule 1 (different DOIs)");
+        assert_eq!(rules.rules[1].conditions.len(), 2);
+        assert_eq!(
+            rules.rules[2].conditions[0].comparator,
+            RuleComparator::Same
+        );
+
+        let config = Config::default();
+        let bibliography = Bibliography::parse(
+            "@article{a, title = {Logic of Paradox}, doi = {10.1000/A}, isbn = {0-306-40615-2}}
+            @article{b, title = {The Logic of Paradox}, doi = {https://doi.org/10.1000/b}, isbn = {9780306406157}}
+            @book{c, title = {Something else}, isbn = {9780306406157}}
+            @misc{d, title = {The Logic of Paradox}}",
+        )
+        .unwrap();
+        let other = Bibliography::parse("@book{a, title = {Yet another thing}}").unwrap();
+        let entries: Vec<&Entry> = bibliography.iter().chain(other.iter()).collect();
+        let data: Vec<EntryData> = entries
+            .iter()
+            .map(|entry| EntryData::new(entry, &config))
+            .collect();
+        let evaluate = |rules: &MatchRules, prev: usize, entry: usize| {
+            let pair = EntryPair::new(entries[prev], &data[prev], entries[entry], &data[entry]);
+            rules
+                .evaluate(&pair, &config)
+                .map(|(idx, rule)| (idx, rule.action))
+        };
+        let rule = |prev: usize, entry: usize| evaluate(&rules, prev, entry);
+        assert_eq!(rule(0, 1), Some((0, RuleAction::NotDuplicate)));
+        assert_eq!(rule(0, 4), Some((1, RuleAction::Ask)));
+        assert_eq!(rule(0, 2), Some((2, RuleAction::Duplicate)));
+        assert_eq!(rule(0, 3), Some((3, RuleAction::Duplicate)));
+        assert_eq!(rule(2, 3), None);
+
+        // The built-in rules can go first
+        let rules = MatchRules::parse(
+            r#"[
+                "defaults",
+                {"fields": ["title"], "normalizer": "title", "comparator": "similar",
+                    "threshold": "same-authors", "action": "ask"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(rules.rules.len(), default_rules().len() + 1);
+        assert_eq!(
+            rules.rules.last().unwrap().conditions[0].threshold,
+            RuleThreshold::SameAuthors
+        );
+        assert_eq!(evaluate(&rules, 0, 0), Some((0, RuleAction::Duplicate)));
+        // With a threshold of 1, similar titles must be the same
+        assert_eq!(evaluate(&rules, 0, 3), None);
+        let lower = Config {
+            similarity_threshold: 0.8,
+            same_authors_threshold: Some(0.5),
+            ..Config::default()
+        };
+        assert_eq!(RuleThreshold::SameAuthors.value(&lower), 0.5);
+        assert_eq!(RuleThreshold::SameScore.value(&lower), 0.9);
+        // A rule without conditions leaves out the built-in rules after it
+        let rules = MatchRules::parse(r#"[{"action": "not-duplicate"}]"#).unwrap();
+        assert_eq!(evaluate(&rules, 4, 0), Some((0, RuleAction::NotDuplicate)));
+
+        let error = MatchRules::parse(r#"[{"fields": ["doi"], "action": "merge"}]"#).unwrap_err();
+        assert_eq!(
+            error,
+            "Rule 1: \"action\" must be \"duplicate\", \"not-duplicate\", \"ask\" or \"choose\""
+        );
+        let error =
+            MatchRules::parse(r#"[{"fields": ["doi"], "comparator": "equal", "action": "ask"}]"#)
+                .unwrap_err();
+        assert_eq!(error, "Rule 1: unknown comparator \"equal\"");
+        let error = MatchRules::parse(r#"["defaults", "defaults"]"#).unwrap_err();
+        assert_eq!(error, "Rule 2: \"defaults\" can only be given once");
+        let error =
+            MatchRules::parse(r#"[{"fields": ["title"], "threshold": "high", "action": "ask"}]"#)
+                .unwrap_err();
+        assert_eq!(error, "Rule 1: unknown threshold \"high\"");
+    }
+}
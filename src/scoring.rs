@@ -0,0 +1,438 @@
+use std::fmt;
+
+use biblatex::Entry;
+use clap::{ArgEnum, Args};
+
+use super::{
+    get_date_parts, get_field, normalize_pages, normalize_title, title_similarity,
+    validate_threshold, Config, EntryData, EntryPair,
+};
+
+/// A field compared to compute the score of two entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum ScoreField {
+    Title,
+    /// The authors or, if there are none, the editors
+    Authors,
+    Year,
+    /// The journal (or `journaltitle`) or, if there is none, the `booktitle`
+    Venue,
+    Pages,
+    Volume,
+    /// The entry type (e.g. article)
+    Type,
+}
+
+/// How the values of a field are compared, from 0 (different) to 1 (the same)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum FieldComparator {
+    /// 1 if the values are equal, 0 otherwise
+    Exact,
+    /// Similarity of the values with the algorithm of the config (as for titles)
+    Similarity,
+    /// Fraction of the words (or, for authors, of the names) the values have in common
+    Overlap,
+    /// 1 / (1 + d), where d is the difference between the first numbers in the values
+    Numeric,
+}
+
+/// Weight and comparator of a field in the score of two entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldWeight {
+    pub field: ScoreField,
+    pub weight: f64,
+    pub comparator: FieldComparator,
+}
+
+/// Weights and comparators of the fields by default (their weights add up to 1)
+pub const DEFAULT_FIELD_WEIGHTS: [FieldWeight; 7] = [
+    FieldWeight {
+        field: ScoreField::Title,
+        weight: 0.4,
+        comparator: FieldComparator::Similarity,
+    },
+    FieldWeight {
+        field: ScoreField::Authors,
+        weight: 0.2,
+        comparator: FieldComparator::Overlap,
+    },
+    FieldWeight {
+        field: ScoreField::Year,
+        weight: 0.1,
+        comparator: FieldComparator::Numeric,
+    },
+    FieldWeight {
+        field: ScoreField::Venue,
+        weight: 0.1,
+        comparator: FieldComparator::Similarity,
+    },
+    FieldWeight {
+        field: ScoreField::Pages,
+        weight: 0.1,
+        comparator: FieldComparator::Exact,
+    },
+    FieldWeight {
+        field: ScoreField::Volume,
+        weight: 0.05,
+        comparator: FieldComparator::Exact,
+    },
+    FieldWeight {
+        field: ScoreField::Type,
+        weight: 0.05,
+        comparator: FieldComparator::Exact,
+    },
+];
+
+/// How entries are compared by their score, a weighted average of the similarity of several fields
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct ScoringOptions {
+    /// If true, entries are compared by their score instead of by their titles alone
+    #[clap(
+        long,
+        help = "If present, entries are compared by a weighted score of their title, authors, year, venue, pages, volume and type, instead of by their titles alone",
+        display_order = 19
+    )]
+    pub scoring: bool,
+
+    /// Weights and comparators of the fields, replacing those in `DEFAULT_FIELD_WEIGHTS` for the
+    /// same fields
+    #[clap(
+        long = "weight",
+        value_name = "FIELD=WEIGHT[:COMPARATOR]",
+        parse(try_from_str = parse_field_weight),
+        multiple_occurrences = true,
+        use_value_delimiter = true,
+        help = "Comma-separated weights of the fields in the score (e.g. title=0.5,year=0.2:exact). Fields: title, authors, year, venue, pages, volume, type. Comparators: exact, similarity, overlap, numeric",
+        display_order = 20
+    )]
+    pub weights: Vec<FieldWeight>,
+
+    /// Score from which entries are the same (without asking)
+    #[clap(
+        long,
+        value_name = "SCORE",
+        default_value_t = 0.9,
+        validator = validate_threshold,
+        help = "Score (between 0 and 1) from which entries are considered the same without asking",
+        display_order = 21
+    )]
+    pub same_score: f64,
+
+    /// Score from which entries may be the same (so the user is asked about them, unless in
+    /// silent mode, where both are kept)
+    #[clap(
+        long,
+        value_name = "SCORE",
+        default_value_t = 0.7,
+        validator = validate_threshold,
+        help = "Score (between 0 and 1) from which you are asked whether entries are the same (in silent mode, both are kept)",
+        display_order = 22
+    )]
+    pub maybe_score: f64,
+}
+
+impl Default for ScoringOptions {
+    fn default() -> Self {
+        ScoringOptions {
+            scoring: false,
+            weights: vec![],
+            same_score: 0.9,
+            maybe_score: 0.7,
+        }
+    }
+}
+
+impl ScoringOptions {
+    /// Weights and comparators of every field: the default ones, replaced by those in `weights`
+    pub fn field_weights(&self) -> Vec<FieldWeight> {
+        DEFAULT_FIELD_WEIGHTS
+            .iter()
+            .map(|default| {
+                self.weights
+                    .iter()
+                    .rev()
+                    .find(|weight| weight.field == default.field)
+                    .unwrap_or(default)
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Parse the weight of a field, as "field=weight" or "field=weight:comparator" (the comparator
+/// is the default one for the field if not given)
+pub fn parse_field_weight(src: &str) -> Result<FieldWeight, String> {
+    let (field, rest) = src
+        .split_once('=')
+        .ok_or_else(|| format!("\"{}\" must be of the form FIELD=WEIGHT[:COMPARATOR]", src))?;
+    let field = ScoreField::from_str(field.trim(), true)
+        .map_err(|_| format!("Unknown field \"{}\"", field.trim()))?;
+    let (weight, comparator) = match rest.split_once(':') {
+        Some((weight, comparator)) => (weight, Some(comparator.trim())),
+        None => (rest, None),
+    };
+    let weight = match weight.trim().parse::<f64>() {
+        Ok(weight) if weight >= 0.0 && weight.is_finite() => weight,
+        _ => {
+            return Err(format!(
+                "The weight \"{}\" must be a number >= 0",
+                weight.trim()
+            ))
+        }
+    };
+    let comparator = match comparator {
+        Some(comparator) => FieldComparator::from_str(comparator, true)
+            .map_err(|_| format!("Unknown comparator \"{}\"", comparator))?,
+        None => DEFAULT_FIELD_WEIGHTS
+            .iter()
+            .find(|default| default.field == field)
+            .map_or(FieldComparator::Exact, |default| default.comparator),
+    };
+    Ok(FieldWeight {
+        field,
+        weight,
+        comparator,
+    })
+}
+
+/// The similarity of one field of two entries, and its weight in their score
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldScore {
+    pub field: ScoreField,
+    pub weight: f64,
+    /// From 0 to 1 (None if either entry does not have the field)
+    pub similarity: Option<f64>,
+}
+
+/// The similarity of each field of two entries, from which their score is computed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreBreakdown {
+    pub fields: Vec<FieldScore>,
+}
+
+impl ScoreBreakdown {
+    /// Average of the similarities of the fields both entries have, weighted by their weights
+    ///
+    /// Returns None if the fields both entries have weigh less than half of the total, as there
+    /// is too little to compare them by.
+    pub fn score(&self) -> Option<f64> {
+        let total: f64 = self.fields.iter().map(|field| field.weight).sum();
+        let (compared, weighted) = self
+            .fields
+            .iter()
+            .filter_map(|field| Some((field.weight, field.weight * field.similarity?)))
+            .fold((0.0, 0.0), |(compared, weighted), (weight, similarity)| {
+                (compared + weight, weighted + similarity)
+            });
+        match compared > 0.0 && compared * 2.0 >= total {
+            true => Some(weighted / compared),
+            false => None,
+        }
+    }
+}
+
+impl fmt::Display for ScoreBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for field in self.fields.iter().filter(|field| field.weight > 0.0) {
+            let name = field
+                .field
+                .to_possible_value()
+                .map(|value| value.get_name())
+                .unwrap_or_default();
+            match field.similarity {
+                Some(similarity) => writeln!(
+                    f,
+                    "  {:<8} {:.2} (weight {:.2})",
+                    name, similarity, field.weight
+                )?,
+                None => writeln!(f, "  {:<8} -    (weight {:.2})", name, field.weight)?,
+            }
+        }
+        match self.score() {
+            Some(score) => write!(f, "  score    {:.2}", score),
+            None => write!(f, "  score    -"),
+        }
+    }
+}
+
+/// The value of a field of an entry, normalized as titles are (None if it does not have it)
+fn field_value(
+    entry: &Entry,
+    data: &EntryData,
+    field: ScoreField,
+    config: &Config,
+) -> Option<String> {
+    let normalize = |value: String| normalize_title(&value, &config.title_normalization);
+    let value = match field {
+        ScoreField::Title => data.title.clone(),
+        ScoreField::Authors => {
+            let names: Vec<String> = data
+                .authors
+                .names
+                .iter()
+                .map(|name| format!("{} {}", name.family, name.initials))
+                .collect();
+            Some(names.join(" "))
+        }
+        ScoreField::Year => get_date_parts(entry).map(|(year, _, _)| year.to_string()),
+        ScoreField::Venue => get_field(entry, "journal")
+            .or_else(|| get_field(entry, "journaltitle"))
+            .or_else(|| get_field(entry, "booktitle"))
+            .map(normalize),
+        ScoreField::Pages => get_field(entry, "pages").map(|pages| normalize_pages(&pages)),
+        ScoreField::Volume => get_field(entry, "volume").map(|volume| volume.trim().to_lowercase()),
+        ScoreField::Type => Some(entry.entry_type.to_string().to_lowercase()),
+    };
+    value.filter(|value| !value.trim().is_empty())
+}
+
+/// The first number in a text (e.g. 451 in "451-461")
+fn first_number(text: &str) -> Option<f64> {
+    let digits: String = text
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Compare a field of two entries with a comparator, from 0 to 1 (None if either does not have
+/// the field)
+pub fn compare_field(
+    pair: &EntryPair,
+    field: ScoreField,
+    comparator: FieldComparator,
+    config: &Config,
+) -> Option<f64> {
+    // Authors are compared by their names (see `AuthorList::overlap`), not as words
+    if field == ScoreField::Authors && comparator == FieldComparator::Overlap {
+        return pair.prev_data.authors.overlap(&pair.data.authors);
+    }
+    let prev_value = field_value(pair.prev_entry, pair.prev_data, field, config)?;
+    let value = field_value(pair.entry, pair.data, field, config)?;
+    let similarity = match comparator {
+        FieldComparator::Exact => match prev_value == value {
+            true => 1.0,
+            false => 0.0,
+        },
+        // The similarity of the titles may have been computed already
+        FieldComparator::Similarity if field == ScoreField::Title => {
+            pair.title_similarity(config).unwrap_or_default()
+        }
+        FieldComparator::Similarity => title_similarity(&prev_value, &value, config),
+        FieldComparator::Overlap => {
+            let prev_words: Vec<&str> = prev_value.split_whitespace().collect();
+            let words: Vec<&str> = value.split_whitespace().collect();
+            let common = prev_words
+                .iter()
+                .filter(|word| words.contains(word))
+                .count();
+            common as f64 / prev_words.len().max(words.len()) as f64
+        }
+        FieldComparator::Numeric => match (first_number(&prev_value), first_number(&value)) {
+            (Some(prev_number), Some(number)) => 1.0 / (1.0 + (prev_number - number).abs()),
+            _ => 0.0,
+        },
+    };
+    Some(similarity)
+}
+
+/// Compare every field of two entries with the weights and comparators of the config
+pub fn score_entries(pair: &EntryPair, config: &Config) -> ScoreBreakdown {
+    let fields = config
+        .scoring
+        .field_weights()
+        .into_iter()
+        .map(|weight| FieldScore {
+            field: weight.field,
+            weight: weight.weight,
+            similarity: match weight.weight > 0.0 {
+                true => compare_field(pair, weight.field, weight.comparator, config),
+                false => None,
+            },
+        })
+        .collect();
+    ScoreBreakdown { fields }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biblatex::Bibliography;
+
+    #[test]
+    fn test_parse_field_weight() {
+        assert_eq!(
+            parse_field_weight("title=0.5").unwrap(),
+            FieldWeight {
+                field: ScoreField::Title,
+                weight: 0.5,
+                comparator: FieldComparator::Similarity,
+            }
+        );
+        assert_eq!(
+            parse_field_weight("Year = 0.2:exact").unwrap().comparator,
+            FieldComparator::Exact
+        );
+        assert!(parse_field_weight("title").is_err());
+        assert!(parse_field_weight("isbn=1").is_err());
+        assert!(parse_field_weight("title=-1").is_err());
+        assert!(parse_field_weight("title=1:fuzzy").is_err());
+
+        let options = ScoringOptions {
+            weights: vec![parse_field_weight("type=0").unwrap()],
+            ..Default::default()
+        };
+        let weights = options.field_weights();
+        assert_eq!(weights.len(), DEFAULT_FIELD_WEIGHTS.len());
+        assert_eq!(weights[0], DEFAULT_FIELD_WEIGHTS[0]);
+        assert_eq!(weights[6].weight, 0.0);
+    }
+
+    #[test]
+    fn test_score_entries() {
+        let config = Config::default();
+        let bibliography = Bibliography::parse(
+            "@article{prior1960, author = {Prior, Arthur N.}, title = {The Runabout Inference-Ticket},
+                journal = {Analysis}, volume = {21}, pages = {38--39}, year = {1960}}
+            @article{Prior1961, author = {Prior, A. N.}, title = {The runabout inference ticket},
+                journal = {Analysis}, volume = {21}, pages = {38-9}, year = {1961}}
+            @book{belnap1962, author = {Belnap, Nuel}, title = {Tonk, Plonk and Plink}, year = {1962}}
+            @misc{untitled, note = {Nothing to compare}}",
+        )
+        .unwrap();
+        let entry = |key: &str| bibliography.get(key).unwrap();
+        let score = |key1: &str, key2: &str| {
+            let (data1, data2) = (
+                EntryData::new(entry(key1), &config),
+                EntryData::new(entry(key2), &config),
+            );
+            score_entries(
+                &EntryPair::new(entry(key1), &data1, entry(key2), &data2),
+                &config,
+            )
+        };
+
+        let breakdown = score("prior1960", "Prior1961");
+        let similarity = |field: ScoreField| {
+            breakdown
+                .fields
+                .iter()
+                .find(|score| score.field == field)
+                .and_then(|score| score.similarity)
+        };
+        assert_eq!(similarity(ScoreField::Title), Some(1.0));
+        assert_eq!(similarity(ScoreField::Authors), Some(1.0));
+        assert_eq!(similarity(ScoreField::Year), Some(0.5));
+        assert_eq!(similarity(ScoreField::Pages), Some(1.0));
+        // Every field but the year is the same
+        assert!((breakdown.score().unwrap() - 0.95).abs() < 1e-9);
+        assert!(breakdown
+            .to_string()
+            .contains("year     0.50 (weight 0.10)"));
+
+        assert!(score("prior1960", "belnap1962").score().unwrap() < 0.5);
+        // Only the type can be compared, which is too little
+        assert_eq!(score("prior1960", "untitled").score(), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::cmp::Ordering;
+
+use biblatex::{Bibliography, ChunksExt, Entry};
+use clap::ArgEnum;
+
+use super::get_date_parts;
+
+/// Order of the entries in the unified bibliography
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum SortOrder {
+    /// Citation key (ignoring case)
+    Key,
+    /// Family names of the authors (or editors), then year
+    AuthorYear,
+    /// Year (entries without one go last), then authors
+    Year,
+    /// Entry type (e.g. all the articles, then all the books)
+    Type,
+    /// The order in which the entries were read
+    Input,
+}
+
+/// Sort the entries of a bibliography
+///
+/// Ties are broken by title and then by key, so the result does not depend on the order in which
+/// the entries were read (except with `SortOrder::Input`, which leaves the bibliography as it is).
+pub fn sort_bibliography(bibliography: Bibliography, order: SortOrder) -> Bibliography {
+    if order == SortOrder::Input {
+        return bibliography;
+    }
+    let mut entries: Vec<(SortKey, Entry)> = bibliography
+        .into_iter()
+        .map(|entry| (SortKey::new(&entry), entry))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.compare(b, order));
+    let mut sorted = Bibliography::new();
+    for (_, entry) in entries {
+        sorted.insert(entry);
+    }
+    sorted
+}
+
+/// The values an entry is sorted by, computed once per entry
+struct SortKey {
+    key: String,
+    names: String,
+    year: Option<i32>,
+    entry_type: String,
+    title: String,
+}
+
+impl SortKey {
+    fn new(entry: &Entry) -> Self {
+        let persons = entry
+            .author()
+            .filter(|authors| !authors.is_empty())
+            .or_else(|| {
+                entry
+                    .editors()
+                    .into_iter()
+                    .next()
+                    .map(|(editors, _)| editors)
+            })
+            .unwrap_or_default();
+        let names = persons
+            .iter()
+            .map(|person| format!("{} {}", person.name, person.given_name))
+            .collect::<Vec<String>>()
+            .join("; ");
+        SortKey {
+            key: entry.key.clone(),
+            names: names.to_lowercase(),
+            year: get_date_parts(entry).map(|(year, _, _)| year),
+            entry_type: entry.entry_type.to_string(),
+            title: entry
+                .title()
+                .map(|title| title.format_verbatim().to_lowercase())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn compare(&self, other: &Self, order: SortOrder) -> Ordering {
+        // Entries without a year (or without names) go after the rest
+        let years = (self.year.is_none(), self.year).cmp(&(other.year.is_none(), other.year));
+        let names =
+            (self.names.is_empty(), &self.names).cmp(&(other.names.is_empty(), &other.names));
+        let primary = match order {
+            SortOrder::Key => Ordering::Equal,
+            SortOrder::AuthorYear => names.then(years),
+            SortOrder::Year => years.then(names),
+            SortOrder::Type => self.entry_type.cmp(&other.entry_type),
+            SortOrder::Input => return Ordering::Equal,
+        };
+        let keys = self
+            .key
+            .to_lowercase()
+            .cmp(&other.key.to_lowercase())
+            .then_with(|| self.key.cmp(&other.key));
+        match order {
+            SortOrder::Key => keys,
+            _ => primary
+                .then_with(|| self.title.cmp(&other.title))
+                .then(keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bibliography: &Bibliography) -> Vec<&str> {
+        bibliography
+            .iter()
+            .map(|entry| entry.key.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_bibliography() {
+        let src = "@book{carnap1942, author = {Carnap, Rudolf}, title = {Introduction to Semantics}, year = {1942}}
+            @article{Prior1960, author = {Prior, Arthur N.}, title = {The Runabout Inference-Ticket}, year = {1960}}
+            @collection{carnielli2018, editor = {Carnielli, Walter}, title = {Between Consistency and Inconsistency}, year = {2018}}
+            @misc{anonymous, title = {No author nor year}}
+            @book{carnap1947, author = {Carnap, Rudolf}, title = {Meaning and Necessity}, year = {1947}}";
+        let bibliography = Bibliography::parse(src).unwrap();
+
+        let sorted = sort_bibliography(bibliography.clone(), SortOrder::Key);
+        assert_eq!(
+            keys(&sorted),
+            vec![
+                "anonymous",
+                "carnap1942",
+                "carnap1947",
+                "carnielli2018",
+                "Prior1960"
+            ]
+        );
+        let sorted = sort_bibliography(bibliography.clone(), SortOrder::AuthorYear);
+        assert_eq!(
+            keys(&sorted),
+            vec![
+                "carnap1942",
+                "carnap1947",
+                "carnielli2018",
+                "Prior1960",
+                "anonymous"
+            ]
+        );
+        let sorted = sort_bibliography(bibliography.clone(), SortOrder::Year);
+        assert_eq!(
+            keys(&sorted),
+            vec![
+                "carnap1942",
+                "carnap1947",
+                "Prior1960",
+                "carnielli2018",
+                "anonymous"
+            ]
+        );
+        let sorted = sort_bibliography(bibliography.clone(), SortOrder::Type);
+        assert_eq!(
+            keys(&sorted),
+            vec![
+                "Prior1960",
+                "carnap1942",
+                "carnap1947",
+                "carnielli2018",
+                "anonymous"
+            ]
+        );
+        let sorted = sort_bibliography(bibliography.clone(), SortOrder::Input);
+        assert_eq!(keys(&sorted), keys(&bibliography));
+
+        // The result does not depend on the order of the input
+        let mut reversed = Bibliography::new();
+        for entry in bibliography.clone().into_vec().into_iter().rev() {
+            reversed.insert(entry);
+        }
+        for order in [
+            SortOrder::Key,
+            SortOrder::AuthorYear,
+            SortOrder::Year,
+            SortOrder::Type,
+        ] {
+            assert_eq!(
+                keys(&sort_bibliography(reversed.clone(), order)),
+                keys(&sort_bibliography(bibliography.clone(), order))
+            );
+        }
+    }
+}
@@ -1,43 +1,144 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+
 use biblatex::{Bibliography, ChunksExt, Entry};
 use read_input::prelude::*;
-use strsim;
-use bunt;
 
-use super::{Algorithm, Config};
+use super::{
+    entry_fingerprint, is_part_of_book_type, normalize_arxiv_id, normalize_doi, normalize_title,
+    order_parents_after_children, rewrite_references, score_entries, sort_bibliography, Algorithm,
+    AuthorList, BibtexWriter, Config, Decider, KeyRename, MatchReport, RuleAction, ScoreBreakdown,
+    UnificationReport,
+};
 
 /// Takes a vec of Bibliography and returns a single Bibliography file with repetitions deleted
+///
+/// The entries are sorted as the config says. Entries that other entries inherit data from
+/// (through `crossref` or `xdata`) are placed after them, as BibTeX requires.
 pub fn unify_bibliography(bibliographies: Vec<Bibliography>, config: &Config) -> Bibliography {
-    println!("Unifiying bibliography...");
+    unify_bibliography_with_report(bibliographies, config).0
+}
+
+/// Same as `unify_bibliography`, but also returns a report of the repeated entries found and of
+/// what was done with them
+pub fn unify_bibliography_with_report(
+    bibliographies: Vec<Bibliography>,
+    config: &Config,
+) -> (Bibliography, UnificationReport) {
+    status!(config, "Unifiying bibliography...");
     let mut unified_bibliography = Bibliography::new();
+    let mut report = UnificationReport::default();
+    let mut entry_data = HashMap::new();
     let mut repetitions_found = 0;
-    for bibliography in bibliographies {
-        repetitions_found +=
-            add_bibliography_to_unified(bibliography, &mut unified_bibliography, config);
+    for (idx, bibliography) in bibliographies.into_iter().enumerate() {
+        report.current = idx;
+        repetitions_found += add_bibliography_with_data(
+            bibliography,
+            &mut unified_bibliography,
+            config,
+            &mut report,
+            &mut entry_data,
+        );
     }
-    println!(
+    status!(
+        config,
         "Found {} repetitions in the bibliography.",
         repetitions_found
     );
-    unified_bibliography
+    let sorted_bibliography = sort_bibliography(unified_bibliography, config.sort);
+    (order_parents_after_children(sorted_bibliography), report)
 }
 
 /// Adds a Bibliography to another Bibliography. Checks for repetitions in the process
+///
+/// References between entries (`crossref`, `xref`, `xdata` and `related`) are kept pointing to
+/// the right entry when an entry is deleted for being repeated, or added with a different key.
 pub fn add_bibliography_to_unified(
     to_add: Bibliography,
     unified_bibliography: &mut Bibliography,
     config: &Config,
 ) -> i32 {
+    let mut report = UnificationReport::default();
+    add_bibliography_with_report(to_add, unified_bibliography, config, &mut report)
+}
+
+/// Same as `add_bibliography_to_unified`, but also records in the report every match found, and
+/// every entry added with a new key
+///
+/// When an entry is replaced by a repeated one, the matches in which the former had been kept are
+/// updated to say that the latter was, so that each deleted entry points to one in the result.
+pub fn add_bibliography_with_report(
+    to_add: Bibliography,
+    unified_bibliography: &mut Bibliography,
+    config: &Config,
+    report: &mut UnificationReport,
+) -> i32 {
+    let mut entry_data = HashMap::new();
+    add_bibliography_with_data(
+        to_add,
+        unified_bibliography,
+        config,
+        report,
+        &mut entry_data,
+    )
+}
+
+/// Same as `add_bibliography_with_report`, keeping the `EntryData` of the entries of the unified
+/// bibliography (by key) between calls, so that it is only computed once for each entry
+fn add_bibliography_with_data(
+    to_add: Bibliography,
+    unified_bibliography: &mut Bibliography,
+    config: &Config,
+    report: &mut UnificationReport,
+    entry_data: &mut HashMap<String, EntryData>,
+) -> i32 {
+    // The similarity of the titles of entries that match by something else is only needed to
+    // report it
+    let reported = config.report.is_some() || config.dry_run;
     // to_add will be consumed by this function
     let mut repetitions = 0;
+    // Keys of to_add that correspond to a different key in the unified bibliography
+    let mut renames = HashMap::new();
+    // Keys (in the unified bibliography) of the entries added from to_add
+    let mut added_keys = vec![];
     // For each entry in the bibliography to add
     for entry in to_add.into_iter() {
         let mut add_entry = true;
         let mut delete_prev = false;
         let mut delete_prev_key = String::new();
+        let data = EntryData::new(&entry, config);
 
         // Compare it to each entry already added to the unified bibliography
         for prev_entry in unified_bibliography.iter() {
-            match compare_entries(prev_entry, &entry, config) {
+            if !entry_data.contains_key(&prev_entry.key) {
+                entry_data.insert(prev_entry.key.clone(), EntryData::new(prev_entry, config));
+            }
+            let pair = EntryPair::new(prev_entry, &entry_data[&prev_entry.key], &entry, &data);
+            let found = match pair.find_match(config) {
+                Some(found) => found,
+                None => continue,
+            };
+            let (result, decided_by) = pair.decide_match(&found, config);
+            report.matches.push(MatchReport {
+                prev_key: prev_entry.key.clone(),
+                prev_source: report.source_of(&prev_entry.key),
+                prev_fingerprint: pair.prev_data.fingerprint.clone().unwrap_or_default(),
+                entry_key: entry.key.clone(),
+                entry_source: report.current,
+                entry_fingerprint: data.fingerprint.clone().unwrap_or_default(),
+                criterion: found.criterion,
+                similarity: match (found.similarity, reported) {
+                    (None, true) => pair.title_similarity(config),
+                    (similarity, _) => similarity,
+                },
+                score: found.score.as_ref().and_then(|score| score.score()),
+                result,
+                decided_by,
+                // Set below if the entry is kept, as it may be added with another key
+                kept: None,
+            });
+            match result {
                 // If KeepBoth maintain the defaults (add entry and dont delete prev)
                 // Do not break (continue looking for similaritiy with the next entries)
                 ComparisonResult::KeepBoth => continue,
@@ -45,6 +146,11 @@ pub fn add_bibliography_to_unified(
                 ComparisonResult::KeepPrev => {
                     repetitions += 1;
                     add_entry = false;
+                    // References to the entry not added will point to the one kept
+                    renames.insert(entry.key.clone(), prev_entry.key.clone());
+                    if let Some(last_match) = report.matches.last_mut() {
+                        last_match.kept = Some(prev_entry.key.clone());
+                    }
                     break;
                 }
                 // If KeepEntry, do add and do delete prev (and break)
@@ -63,101 +169,430 @@ pub fn add_bibliography_to_unified(
         // same key
         if delete_prev {
             unified_bibliography.remove(&delete_prev_key);
+            report.sources.remove(&delete_prev_key);
+            entry_data.remove(&delete_prev_key);
         }
         if add_entry {
-            add_entry_to_bibliography(entry, unified_bibliography);
+            let old_key = entry.key.clone();
+            let new_key = add_entry_to_bibliography(entry, unified_bibliography);
             // entry is owned, the to_add Bibliography will be consumed after the outer loop ends
+            report.sources.insert(new_key.clone(), report.current);
+            entry_data.insert(new_key.clone(), data);
+            if delete_prev {
+                // The entries deleted in favour of the previous one now have this one instead
+                for entry_match in report.matches.iter_mut() {
+                    if entry_match.kept.as_ref() == Some(&delete_prev_key) {
+                        entry_match.kept = Some(new_key.clone());
+                    }
+                }
+                if let Some(last_match) = report.matches.last_mut() {
+                    last_match.kept = Some(new_key.clone());
+                }
+                // References to the deleted entry will point to this one
+                if added_keys.contains(&delete_prev_key) {
+                    renames.insert(delete_prev_key.clone(), new_key.clone());
+                } else {
+                    let prev_renames = HashMap::from([(delete_prev_key, new_key.clone())]);
+                    for prev_entry in unified_bibliography.iter_mut() {
+                        if !added_keys.contains(&prev_entry.key) {
+                            rewrite_references(prev_entry, &prev_renames);
+                        }
+                    }
+                }
+            }
+            if new_key != old_key {
+                report.renames.push(KeyRename {
+                    old_key: old_key.clone(),
+                    new_key: new_key.clone(),
+                    source: report.current,
+                });
+                renames.insert(old_key, new_key.clone());
+            }
+            added_keys.push(new_key);
+        }
+    }
+
+    // Only now are all the keys known, so the references of the added entries can be rewritten
+    for key in added_keys.iter() {
+        if let Some(entry) = unified_bibliography.get_mut(key) {
+            rewrite_references(entry, &renames);
         }
     }
     repetitions
 }
 
 /// Possible results of a comparison between two Entry objects
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ComparisonResult {
     KeepBoth,
     KeepPrev,
     KeepEntry,
 }
 
-/// Checks if two entries are similar. If they are, decides what to do
-///
-/// Entries will be considered the same if they are equal in all fields & in key. In that case,
-/// this function will not ask which to keep and just return `KeepPrev`.
-/// Otherwise, they will be considered similar if (in this order):
+/// The reason why two entries are considered the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchCriterion {
+    /// They are equal in all fields and in key
+    Identical,
+    Key,
+    Doi,
+    Pmid,
+    Pmcid,
+    /// They share an ISBN (after converting ISBN-10s to ISBN-13)
+    Isbn,
+    /// They have the same arXiv identifier (ignoring its version)
+    Arxiv,
+    /// They have the same ISSN, volume and pages
+    IssnVolumePages,
+    Url,
+    Title,
+    /// Their titles are similar according to the threshold and algorithm of the config
+    SimilarTitle,
+    /// They have the same authors and year, and their titles are similar according to the
+    /// config's `same_authors_threshold`
+    SimilarTitleSameAuthors,
+    /// Their score is at least the config's `same_score` (see `ScoringOptions`)
+    Score,
+    /// Their score is at least the config's `maybe_score`, but lower than `same_score`
+    MaybeScore,
+    /// A rule of the config without a criterion of its own (at this position) says they are, or
+    /// may be, the same
+    Rule(usize),
+}
+
+impl fmt::Display for MatchCriterion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            MatchCriterion::Identical => "identical",
+            MatchCriterion::Key => "same key",
+            MatchCriterion::Doi => "same DOI",
+            MatchCriterion::Pmid => "same PMID",
+            MatchCriterion::Pmcid => "same PMCID",
+            MatchCriterion::Isbn => "same ISBN",
+            MatchCriterion::Arxiv => "same arXiv identifier",
+            MatchCriterion::IssnVolumePages => "same ISSN, volume and pages",
+            MatchCriterion::Url => "same URL",
+            MatchCriterion::Title => "same title",
+            MatchCriterion::SimilarTitle => "similar title",
+            MatchCriterion::SimilarTitleSameAuthors => "similar title, same authors and year",
+            MatchCriterion::Score => "high score",
+            MatchCriterion::MaybeScore => "possibly the same by score",
+            MatchCriterion::Rule(idx) => return write!(f, "rule {}", idx + 1),
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// Two entries that were found to be the same, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryMatch {
+    pub criterion: MatchCriterion,
+    /// Action of the rule that found them (see `MatchRules`)
+    pub action: RuleAction,
+    /// Similarity of their titles with the algorithm of the config (only if they were found to be
+    /// the same by their titles)
+    pub similarity: Option<f64>,
+    /// The similarity of each of their fields (only if they were compared by score)
+    pub score: Option<ScoreBreakdown>,
+}
+
+/// What is compared of an entry, computed once for all the comparisons it takes part in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntryData {
+    /// Title, normalized with the steps of the config's `title_normalization` (None if it has
+    /// none, or nothing remains of it)
+    pub title: Option<String>,
+    /// Authors (or editors), as they are compared
+    pub authors: AuthorList,
+    /// Fingerprint, as returned by `entry_fingerprint` (only if the config records or applies
+    /// decisions, which are the only ones that need it)
+    pub fingerprint: Option<String>,
+}
+
+impl EntryData {
+    pub fn new(entry: &Entry, config: &Config) -> Self {
+        let uses_decisions =
+            config.decisions.is_some() || !config.recorded_decisions.decisions.is_empty();
+        EntryData {
+            title: entry
+                .title()
+                .map(|title| normalize_title(&title.format_verbatim(), &config.title_normalization))
+                .filter(|title| !title.is_empty()),
+            authors: AuthorList::new(entry),
+            fingerprint: match uses_decisions {
+                true => Some(entry_fingerprint(entry)),
+                false => None,
+            },
+        }
+    }
+}
+
+/// Two entries being compared, along with their `EntryData`
 ///
-/// - They have the same cite key
-/// - They have the same doi (must be present in both)
-/// - They have the same title
-/// - The similarity threshold is <1 and they have similar titles
+/// The similarity of their titles and their score are computed the first time they are needed, as
+/// running the strsim algorithms is slow.
+pub struct EntryPair<'a> {
+    pub prev_entry: &'a Entry,
+    pub prev_data: &'a EntryData,
+    pub entry: &'a Entry,
+    pub data: &'a EntryData,
+    similarity: Cell<Option<Option<f64>>>,
+    breakdown: RefCell<Option<ScoreBreakdown>>,
+}
+
+/// Checks if two entries are similar. If they are, decides what to do
 ///
-/// In all these cases, it will call `decide_which_to_keep`.
-/// If none of the above apply, it will return `KeepBoth`.
+/// Entries are compared with `find_match`, and `decide_match` decides which to keep when they
+/// match. If they do not, it will return `KeepBoth`.
 pub fn compare_entries(prev_entry: &Entry, entry: &Entry, config: &Config) -> ComparisonResult {
-    // If both entries are equal in all fields, retain the old one (it is simpler)
-    if prev_entry == entry {
-        return ComparisonResult::KeepPrev;
+    let prev_data = EntryData::new(prev_entry, config);
+    let data = EntryData::new(entry, config);
+    let pair = EntryPair::new(prev_entry, &prev_data, entry, &data);
+    match pair.find_match(config) {
+        Some(found) => pair.decide_match(&found, config).0,
+        None => ComparisonResult::KeepBoth,
     }
+}
+
+/// Checks if two entries are the same, and why (see `EntryPair::find_match`)
+pub fn find_match(prev_entry: &Entry, entry: &Entry, config: &Config) -> Option<EntryMatch> {
+    let prev_data = EntryData::new(prev_entry, config);
+    let data = EntryData::new(entry, config);
+    EntryPair::new(prev_entry, &prev_data, entry, &data).find_match(config)
+}
 
-    // Both have the same key
-    if prev_entry.key == entry.key {
-        if !config.silent {
-            bunt::println!("{$bold+red}The following entries have the same key:{/$}");
-            bunt::println!("{$green}Note: If you wish to keep both, the key to the second entry will be automatically changed.{/$}\n");
+/// Given two entries found to be the same by `find_match`, decides which to keep (see
+/// `EntryPair::decide_match`)
+pub fn decide_match(
+    prev_entry: &Entry,
+    entry: &Entry,
+    found: &EntryMatch,
+    config: &Config,
+) -> (ComparisonResult, Decider) {
+    let prev_data = EntryData::new(prev_entry, config);
+    let data = EntryData::new(entry, config);
+    EntryPair::new(prev_entry, &prev_data, entry, &data).decide_match(found, config)
+}
+
+impl<'a> EntryPair<'a> {
+    pub fn new(
+        prev_entry: &'a Entry,
+        prev_data: &'a EntryData,
+        entry: &'a Entry,
+        data: &'a EntryData,
+    ) -> Self {
+        EntryPair {
+            prev_entry,
+            prev_data,
+            entry,
+            data,
+            similarity: Cell::new(None),
+            breakdown: RefCell::new(None),
         }
-        return decide_which_to_keep(prev_entry, entry, config);
     }
 
-    // Both have the doi field set
-    if let (Some(prev_doi), Some(entry_doi)) = (&prev_entry.doi(), &entry.doi()) {
-        // Same doi is condidered the same entry
-        if prev_doi == entry_doi {
-            // decide_which_to_keep returns false only if the user decides to preserve prev_entry
-            if !config.silent {
-                bunt::println!("{$bold+red}The following entries have the same DOI:{/$}\n");
-            }
-            return decide_which_to_keep(prev_entry, entry, config);
+    /// Similarity of their normalized titles with the algorithm of the config (None if either
+    /// has no title)
+    pub fn title_similarity(&self, config: &Config) -> Option<f64> {
+        if let Some(similarity) = self.similarity.get() {
+            return similarity;
         }
+        let similarity = match (&self.prev_data.title, &self.data.title) {
+            // Equal titles have a similarity of 1 for every metric, and checking that is much
+            // faster than actually running the strsim algorithms
+            (Some(prev_title), Some(title)) if prev_title == title => Some(1.0),
+            (Some(prev_title), Some(title)) => Some(title_similarity(prev_title, title, config)),
+            _ => None,
+        };
+        self.similarity.set(Some(similarity));
+        similarity
     }
 
-    // Both have the title field set
-    if let (Some(prev_title), Some(entry_title)) = (&prev_entry.title(), &entry.title()) {
-        // Turn them into Strings instead of the default [&Chunk]
-        let prev_title = prev_title.format_verbatim();
-        let entry_title = entry_title.format_verbatim();
-        // First check for equality between the titles. If they are equal should return true
-        // independently of the similarity threshold (for every metric, will be 1).
-        // Should be much faster than actually running the strsim algorithms.
-        if prev_title == entry_title {
-            // decide_which_to_keep returns false only if the user decides to preserve prev_entry
-            if !config.silent {
-                bunt::println!("{$bold+red}The following entries have the same title:{/$}\n");
-            }
-            return decide_which_to_keep(prev_entry, entry, config);
-        } else if config.similarity_threshold < 1.0
-            && test_title_similarity(&prev_title, &entry_title, config)
+    /// Score of the entries with the weights of the config (see `score_entries`)
+    pub fn score(&self, config: &Config) -> Option<f64> {
+        let mut breakdown = self.breakdown.borrow_mut();
+        breakdown
+            .get_or_insert_with(|| score_entries(self, config))
+            .score()
+    }
+
+    /// Checks if two entries are the same, and why
+    ///
+    /// The first of the config's `match_rules` that applies to them decides (see `MatchRules`,
+    /// and `default_rules` for the rules used by default). If none does, or it says that they are
+    /// not the same, it will return None.
+    pub fn find_match(&self, config: &Config) -> Option<EntryMatch> {
+        let (idx, rule) = config.match_rules.evaluate(self, config)?;
+        if rule.action == RuleAction::NotDuplicate {
+            return None;
+        }
+        // The similarity of the titles is given for the rules that compare them
+        let compares_titles = rule.conditions.iter().any(|condition| {
+            condition
+                .fields
+                .iter()
+                .any(|field| field.eq_ignore_ascii_case("title"))
+        });
+        Some(EntryMatch {
+            criterion: rule.criterion.unwrap_or(MatchCriterion::Rule(idx)),
+            action: rule.action,
+            similarity: match compares_titles {
+                true => self.title_similarity(config),
+                false => None,
+            },
+            score: self.breakdown.borrow().clone(),
+        })
+    }
+
+    /// Given two entries found to be the same by `find_match`, decides which to keep (and says who
+    /// decided)
+    ///
+    /// Identical entries are not asked about: the old one is kept. Neither are entries about which a
+    /// decision was recorded on a previous run (in the config's `recorded_decisions`): the recorded
+    /// decision is applied. Otherwise, the action of the rule that found them decides (see
+    /// `RuleAction`), and if the user is to be asked, the reason why they match is shown before
+    /// calling `decide_which_to_keep`.
+    pub fn decide_match(&self, found: &EntryMatch, config: &Config) -> (ComparisonResult, Decider) {
+        let (prev_entry, entry) = (self.prev_entry, self.entry);
+        if found.criterion == MatchCriterion::Identical {
+            return (ComparisonResult::KeepPrev, Decider::Automatic);
+        }
+        if let (Some(prev_fingerprint), Some(fingerprint)) =
+            (&self.prev_data.fingerprint, &self.data.fingerprint)
         {
-            // decide_which_to_keep returns false only if the user decides to preserve prev_entry
-            if !config.silent {
-                bunt::println!("{$bold+red}The following entries have the similar titles:{/$}\n");
+            if let Some(result) = config
+                .recorded_decisions
+                .lookup_fingerprints(prev_fingerprint, fingerprint)
+            {
+                return (result, Decider::Recorded);
+            }
+        }
+        match found.action {
+            RuleAction::Duplicate => return (ComparisonResult::KeepPrev, Decider::Automatic),
+            // Entries that may be the same are only deleted if the user says so
+            RuleAction::Ask if config.silent => {
+                return (ComparisonResult::KeepBoth, Decider::Automatic)
             }
-            return decide_which_to_keep(prev_entry, entry, config);
+            _ if config.silent => return (ComparisonResult::KeepPrev, Decider::Automatic),
+            _ => {}
         }
+        match found.criterion {
+            MatchCriterion::Key => {
+                bunt_status!(config, "{$bold+red}The following entries have the same key:{/$}");
+                bunt_status!(config, "{$green}Note: If you wish to keep both, the key to the second entry will be automatically changed.{/$}\n");
+            }
+            MatchCriterion::Doi => {
+                bunt_status!(config, "{$bold+red}The following entries have the same DOI:{/$}\n");
+            }
+            MatchCriterion::Pmid | MatchCriterion::Pmcid => {
+                let name = match found.criterion {
+                    MatchCriterion::Pmid => "PMID",
+                    _ => "PMCID",
+                };
+                bunt_status!(config, "{$bold+red}The following entries have the same {}:{/$}\n", name);
+            }
+            MatchCriterion::Isbn | MatchCriterion::Arxiv | MatchCriterion::Url => {
+                let name = match found.criterion {
+                    MatchCriterion::Isbn => "ISBN",
+                    MatchCriterion::Arxiv => "arXiv identifier",
+                    _ => "URL",
+                };
+                bunt_status!(config, "{$bold+red}The following entries have the same {}:{/$}\n", name);
+            }
+            MatchCriterion::IssnVolumePages => {
+                bunt_status!(config, "{$bold+red}The following entries have the same ISSN, volume and pages:{/$}\n");
+            }
+            MatchCriterion::Title => {
+                bunt_status!(config, "{$bold+red}The following entries have the same title:{/$}\n");
+            }
+            MatchCriterion::Rule(idx) => {
+                let rule = config.match_rules.rules.get(idx).map(|rule| rule.describe(idx));
+                bunt_status!(config, "{$bold+red}The following entries may be the same, according to {}:{/$}\n", rule.unwrap_or_default());
+            }
+            MatchCriterion::MaybeScore => {
+                let score = found.score.as_ref();
+                bunt_status!(config, "{$bold+red}The following entries may be the same (score {:.2}):{/$}", score.and_then(|score| score.score()).unwrap_or_default());
+                if let Some(score) = score {
+                    status!(config, "{}\n", score);
+                }
+            }
+            MatchCriterion::SimilarTitleSameAuthors => {
+                bunt_status!(config, "{$bold+red}The following entries have the same authors and year, and similar titles:{/$}\n");
+            }
+            _ => {
+                bunt_status!(config, "{$bold+red}The following entries have the similar titles:{/$}\n");
+            }
+        }
+        (decide_which_to_keep(prev_entry, entry, config), Decider::User)
     }
+}
+
+/// Get an identifier field of an entry (e.g. `pmid`), whatever the case of the field name
+///
+/// Identifiers are compared ignoring case, so they are returned in uppercase (e.g. "PMC6193465").
+pub fn get_identifier(entry: &Entry, field: &str) -> Option<String> {
+    get_field(entry, field).map(|value| value.trim().to_uppercase())
+}
+
+/// Get a field of an entry as a String, whatever the case of the field name (None if it is
+/// missing or empty)
+pub(crate) fn get_field(entry: &Entry, field: &str) -> Option<String> {
+    entry
+        .fields
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(field))
+        .map(|(_, value)| value.format_verbatim())
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// Whether an entry is part of a book (e.g. a chapter or a paper in proceedings), and so has the
+/// ISBN of the book
+pub(crate) fn is_part_of_book(entry: &Entry) -> bool {
+    is_part_of_book_type(&entry.entry_type) || get_field(entry, "chapter").is_some()
+}
 
-    ComparisonResult::KeepBoth
+/// Get the arXiv identifier of an entry, without its version (see `normalize_arxiv_id`)
+///
+/// It is taken from the `eprint` field (with `eprinttype` or `archiveprefix` "arXiv", or if it
+/// looks like an arXiv identifier), from a `url` on arxiv.org, or from an arXiv DOI.
+pub fn get_arxiv_id(entry: &Entry) -> Option<String> {
+    let eprint = get_field(entry, "eprint").and_then(|eprint| {
+        let archive = get_field(entry, "eprinttype").or_else(|| get_field(entry, "archiveprefix"));
+        match archive {
+            Some(archive) if !archive.trim().eq_ignore_ascii_case("arxiv") => None,
+            _ => normalize_arxiv_id(&eprint),
+        }
+    });
+    let url = || {
+        get_field(entry, "url")
+            .filter(|url| url.to_lowercase().contains("arxiv.org/"))
+            .and_then(|url| normalize_arxiv_id(&url))
+    };
+    let doi = || {
+        get_field(entry, "doi")
+            .and_then(|doi| normalize_doi(&doi))
+            .and_then(|doi| {
+                doi.strip_prefix("10.48550/arxiv.")
+                    .and_then(normalize_arxiv_id)
+            })
+    };
+    eprint.or_else(url).or_else(doi)
 }
 
 /// Apply similarity metrics to see if two titles (Strings) are sufficiently similar
 pub fn test_title_similarity(title1: &str, title2: &str, config: &Config) -> bool {
-    let similarity = match config.algorithm {
+    title_similarity(title1, title2, config) >= config.similarity_threshold
+}
+
+/// Similarity between two titles, from 0 to 1, according to the algorithm of the config
+pub fn title_similarity(title1: &str, title2: &str, config: &Config) -> f64 {
+    match config.algorithm {
         Algorithm::Levenshtein => strsim::normalized_levenshtein(title1, title2),
         Algorithm::DamerauLevenshtein => strsim::normalized_damerau_levenshtein(title1, title2),
         Algorithm::Jaro => strsim::jaro(title1, title2),
         Algorithm::JaroWinkler => strsim::jaro_winkler(title1, title2),
         Algorithm::SorensenDice => strsim::sorensen_dice(title1, title2),
-    };
-    similarity >= config.similarity_threshold
+    }
 }
 
 /// Given two entries which we have previously decided are similar, decides which to keep
@@ -171,47 +606,55 @@ pub fn decide_which_to_keep(prev_entry: &Entry, entry: &Entry, config: &Config)
     }
 
     // Otherwise, ask which
-    let (prev_entry_string, entry_string) = match config.biblatex {
-        true => (prev_entry.to_biblatex_string(), entry.to_biblatex_string()),
-        false => (prev_entry.to_bibtex_string(), entry.to_bibtex_string()),
-    };
-    bunt::println!(
+    // The entries are shown in the output format (or as BibTeX, if the format is unknown)
+    let default_writer = BibtexWriter::default();
+    let writer = config.writer().unwrap_or(&default_writer);
+    let (prev_entry_string, entry_string) =
+        (writer.format_entry(prev_entry), writer.format_entry(entry));
+    bunt_status!(
+        config,
         "{$green}1-{/$} {}\n\n{$green}2-{/$} {}\n\n{$blue}Do you wish to keep the first (1), the second (2) or both (3)?{/$}",
         prev_entry_string,
         entry_string
     );
-    let input: u32 = input()
+    let mut input_builder = input();
+    if config.writes_to_stdout() {
+        input_builder = input_builder.prompting_on_stderr();
+    }
+    let input: u32 = input_builder
         .repeat_msg("Enter your choice: ")
         .err("The value must be either 1, 2 or 3.")
         .min_max(1, 3)
         .get();
-    println!();
+    status!(config, "");
 
     if input == 3 {
         // 3 means keep both
-        return ComparisonResult::KeepBoth;
+        ComparisonResult::KeepBoth
     } else if input == 1 {
         // 1 means keep the old entry
-        return ComparisonResult::KeepPrev;
+        ComparisonResult::KeepPrev
     } else {
         // 2 means keep the new one
-        return ComparisonResult::KeepEntry;
+        ComparisonResult::KeepEntry
     }
 }
 
 /// Adds an Entry to a Bibliography, checking that the key is not repeated
 ///
 /// If the cite key is already present in the bibliography, it will get a new
-/// non-repeated key.
-pub fn add_entry_to_bibliography(mut entry: Entry, bibliography: &mut Bibliography) {
+/// non-repeated key. Returns the key with which the entry was added.
+pub fn add_entry_to_bibliography(mut entry: Entry, bibliography: &mut Bibliography) -> String {
     // If it is not present, we add it to the unified bibliography
     // First check if the citation key is already present
     if bibliography.get(&entry.key).is_some() {
         // If it is, get a new key, otherwise it won't be added correctly
-        entry.key = get_new_citation_key(&entry.key, &bibliography);
+        entry.key = get_new_citation_key(&entry.key, bibliography);
     }
     // Add it
+    let key = entry.key.clone();
     bibliography.insert(entry);
+    key
 }
 
 /// Gets a new, non-repeated, citation key for an Entry.
@@ -236,6 +679,10 @@ pub fn get_new_citation_key(old_key: &str, bibliography: &Bibliography) -> Strin
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        Keep, MatchRules, RecordedDecision, RecordedEntry, SortOrder, TitleNormalization,
+        DEFAULT_TITLE_NORMALIZATION,
+    };
     use std::fs;
     use std::path::PathBuf;
 
@@ -243,12 +690,10 @@ mod tests {
         let file = fs::read_to_string("bib_files/test_files/test.bib").unwrap();
         let bibliography = Bibliography::parse(&file).unwrap();
         let config = Config {
-            path_dir: PathBuf::from(r"bib_files/test_files/"),
+            inputs: vec![PathBuf::from(r"bib_files/test_files/")],
             similarity_threshold: 1.0,
-            algorithm: Algorithm::Levenshtein,
             silent: true,
-            output: None,
-            biblatex: false,
+            ..Default::default()
         };
         (bibliography, config)
     }
@@ -276,12 +721,10 @@ mod tests {
         let file = fs::read_to_string("bib_files/test_files/rep_in_file.bib").unwrap();
         let bibliography1 = Bibliography::parse(&file).unwrap();
         let config = Config {
-            path_dir: PathBuf::from(r"bib_files/test_files/"),
+            inputs: vec![PathBuf::from(r"bib_files/test_files/")],
             similarity_threshold: 1.0,
-            algorithm: Algorithm::Levenshtein,
             silent: true,
-            output: None,
-            biblatex: false,
+            ..Default::default()
         };
         let mut bibliography = Bibliography::new();
 
@@ -357,6 +800,73 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_only_same_pubmed_id() {
+        let (_, config) = setup();
+        let bibliography = Bibliography::parse(
+            "@article{watson1953, title = {Molecular structure of nucleic acids}, pmid = {13054692}}
+            @article{WatsonCrick1953, title = {A structure for deoxyribose nucleic acid}, PMID = {13054692}}
+            @article{smith2018, title = {An example}, pmcid = {PMC6193465}}
+            @article{Smith2018a, title = {An example of an article}, pmcid = {pmc6193465}}
+            @article{other, title = {Another example}, pmid = {30333588}}",
+        )
+        .unwrap();
+        let entry = |key: &str| bibliography.get(key).unwrap();
+        assert_eq!(
+            compare_entries(entry("watson1953"), entry("WatsonCrick1953"), &config),
+            ComparisonResult::KeepPrev
+        );
+        assert_eq!(
+            compare_entries(entry("smith2018"), entry("Smith2018a"), &config),
+            ComparisonResult::KeepPrev
+        );
+        assert_eq!(
+            compare_entries(entry("watson1953"), entry("other"), &config),
+            ComparisonResult::KeepBoth
+        );
+    }
+
+    #[test]
+    fn test_persistent_identifiers() {
+        let (_, config) = setup();
+        let bibliography = Bibliography::parse(
+            r"@article{a1, title = {One}, doi = {10.1000/XYZ}}
+            @article{a2, title = {Two}, doi = {https://doi.org/10.1000/xyz}}
+            @article{a3, title = {Three}, doi = {doi:10.1000/xyz}}
+            @book{b1, title = {Four}, isbn = {0-306-40615-2}}
+            @book{b2, title = {Five}, isbn = {978-0-306-40615-7}}
+            @incollection{b3, title = {Six}, isbn = {9780306406157}}
+            @article{c1, title = {Seven}, eprint = {2101.00001v1}, eprinttype = {arXiv}}
+            @misc{c2, title = {Eight}, url = {https://arxiv.org/abs/2101.00001v2}}
+            @misc{c3, title = {Nine}, doi = {10.48550/arXiv.2101.00001}}
+            @article{c4, title = {Ten}, eprint = {2101.00001}, eprinttype = {hal}}
+            @article{d1, title = {Eleven}, issn = {0028-0836}, volume = {171}, pages = {737--738}}
+            @article{d2, title = {Twelve}, issn = {00280836}, volume = {171}, pages = {737-8}}
+            @article{d3, title = {Thirteen}, issn = {0028-0836}, volume = {172}, pages = {737--738}}
+            @online{e1, title = {Fourteen}, url = {https://www.example.org/paper/}}
+            @online{e2, title = {Fifteen}, url = {http://example.org/paper}}
+            @article{f1, title = {Sixteen}, pmid = {PMID: 13054692}}
+            @article{f2, title = {Seventeen}, pmid = {13054692}}",
+        )
+        .unwrap();
+        let entry = |key: &str| bibliography.get(key).unwrap();
+        let criterion = |key1: &str, key2: &str| {
+            find_match(entry(key1), entry(key2), &config).map(|found| found.criterion)
+        };
+        assert_eq!(criterion("a1", "a2"), Some(MatchCriterion::Doi));
+        assert_eq!(criterion("a2", "a3"), Some(MatchCriterion::Doi));
+        assert_eq!(criterion("b1", "b2"), Some(MatchCriterion::Isbn));
+        // A chapter has the ISBN of the book it is part of
+        assert_eq!(criterion("b2", "b3"), None);
+        assert_eq!(criterion("c1", "c2"), Some(MatchCriterion::Arxiv));
+        assert_eq!(criterion("c2", "c3"), Some(MatchCriterion::Arxiv));
+        assert_eq!(criterion("c1", "c4"), None);
+        assert_eq!(criterion("d1", "d2"), Some(MatchCriterion::IssnVolumePages));
+        assert_eq!(criterion("d1", "d3"), None);
+        assert_eq!(criterion("e1", "e2"), Some(MatchCriterion::Url));
+        assert_eq!(criterion("f1", "f2"), Some(MatchCriterion::Pmid));
+    }
+
     #[test]
     fn test_only_same_title() {
         let (mut bibliography1, config) = setup();
@@ -407,6 +917,267 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_title_normalization() {
+        let (bibliography1, mut config) = setup();
+        let file = fs::read_to_string("bib_files/test_files/similar_title.bib").unwrap();
+        let bibliography2 = Bibliography::parse(&file).unwrap();
+        let bps1 = bibliography1.get("BPS2018-WIAPL").unwrap();
+        let bps2 = bibliography2.get("BPS2018-WIAPL_1").unwrap();
+
+        // The titles only differ in case (and braces), so they are the same once normalized
+        assert_eq!(
+            find_match(bps1, bps2, &config).unwrap().criterion,
+            MatchCriterion::Title
+        );
+        config.title_normalization = vec![TitleNormalization::None];
+        assert_eq!(find_match(bps1, bps2, &config), None);
+
+        // Articles are only removed if asked to
+        let carnap1 = bibliography1.get("Carnap1942").unwrap();
+        let carnap2 = bibliography2.get("Carnap1942_1").unwrap();
+        config.title_normalization = DEFAULT_TITLE_NORMALIZATION.to_vec();
+        assert_eq!(find_match(carnap1, carnap2, &config), None);
+        config.title_normalization.push(TitleNormalization::Articles);
+        assert_eq!(
+            find_match(carnap1, carnap2, &config).unwrap().criterion,
+            MatchCriterion::Title
+        );
+    }
+
+    #[test]
+    fn test_authors() {
+        let (_, mut config) = setup();
+        let bibliography = Bibliography::parse(
+            r"@article{priest1979, author = {Priest, Graham}, title = {The Logic of Paradox}, year = {1979}}
+            @article{Priest1979a, author = {G. Priest}, title = {Logic of Paradox}, year = {1979}}
+            @article{Priest1979b, author = {Priest, Graham}, title = {A Logic of Paradox}, year = {1980}}
+            @incollection{smith2001, author = {Smith, John}, title = {Introduction}, year = {2001}}
+            @incollection{jones2005, author = {Jones, Mary}, title = {Introduction}, year = {2005}}
+            @incollection{anonymous, title = {Introduction}}",
+        )
+        .unwrap();
+        let entry = |key: &str| bibliography.get(key).unwrap();
+
+        // The same title by different authors is not the same entry
+        assert_eq!(find_match(entry("smith2001"), entry("jones2005"), &config), None);
+        assert_eq!(
+            find_match(entry("smith2001"), entry("anonymous"), &config)
+                .unwrap()
+                .criterion,
+            MatchCriterion::Title
+        );
+        config.ignore_authors = true;
+        assert!(find_match(entry("smith2001"), entry("jones2005"), &config).is_some());
+        config.ignore_authors = false;
+
+        // A lower threshold applies to entries with the same authors and year
+        assert_eq!(find_match(entry("priest1979"), entry("Priest1979a"), &config), None);
+        config.same_authors_threshold = Some(0.8);
+        let found = find_match(entry("priest1979"), entry("Priest1979a"), &config).unwrap();
+        assert_eq!(found.criterion, MatchCriterion::SimilarTitleSameAuthors);
+        assert!(found.similarity.unwrap() < 1.0);
+        assert_eq!(find_match(entry("priest1979"), entry("Priest1979b"), &config), None);
+        config.similarity_threshold = 0.8;
+        assert_eq!(
+            find_match(entry("priest1979"), entry("Priest1979b"), &config)
+                .unwrap()
+                .criterion,
+            MatchCriterion::SimilarTitle
+        );
+    }
+
+    #[test]
+    fn test_scoring() {
+        let (_, mut config) = setup();
+        config.scoring.scoring = true;
+        let bibliography = Bibliography::parse(
+            "@article{prior1960, author = {Prior, Arthur N.}, title = {The Runabout Inference-Ticket},
+                journal = {Analysis}, volume = {21}, pages = {38--39}, year = {1960}}
+            @article{Prior1961, author = {Prior, A. N.}, title = {The runabout inference ticket},
+                journal = {Analysis}, volume = {21}, pages = {38-9}, year = {1961}}
+            @article{prior1960b, author = {Prior, Arthur N.}, title = {The Runabout Inference-Ticket},
+                journal = {Analysis}, volume = {22}, pages = {1--2}, year = {1962}}
+            @incollection{smith2001, author = {Smith, John}, title = {Introduction}, year = {2001}}
+            @incollection{jones2005, author = {Jones, Mary}, title = {Introduction}, year = {2005}}",
+        )
+        .unwrap();
+        let entry = |key: &str| bibliography.get(key).unwrap();
+
+        // Only the year differs: definitely the same, so they are not asked about
+        let found = find_match(entry("prior1960"), entry("Prior1961"), &config).unwrap();
+        assert_eq!(found.criterion, MatchCriterion::Score);
+        assert_eq!(
+            decide_match(entry("prior1960"), entry("Prior1961"), &found, &config),
+            (ComparisonResult::KeepPrev, Decider::Automatic)
+        );
+
+        // Same title and authors, but a different year, volume and pages: maybe the same
+        let found = find_match(entry("prior1960"), entry("prior1960b"), &config).unwrap();
+        assert_eq!(found.criterion, MatchCriterion::MaybeScore);
+        let score = found.score.as_ref().unwrap().score().unwrap();
+        assert!((config.scoring.maybe_score..config.scoring.same_score).contains(&score));
+        // In silent mode, both are kept
+        assert_eq!(
+            decide_match(entry("prior1960"), entry("prior1960b"), &found, &config),
+            (ComparisonResult::KeepBoth, Decider::Automatic)
+        );
+
+        // The same title is not enough
+        assert_eq!(find_match(entry("smith2001"), entry("jones2005"), &config), None);
+    }
+
+    #[test]
+    fn test_match_rules() {
+        let (_, mut config) = setup();
+        config.match_rules = MatchRules::parse(
+            r#"[
+                {"fields": ["doi"], "normalizer": "doi", "comparator": "different", "action": "not-duplicate"},
+                {
+                    "conditions": [
+                        {"fields": ["entrykey"]},
+                        {"fields": ["entrytype"], "comparator": "different"}
+                    ],
+                    "action": "ask"
+                },
+                {"fields": ["isbn"], "normalizer": "isbn", "action": "duplicate"}
+            ]"#,
+        )
+        .unwrap();
+        let bibliography1 = Bibliography::parse(
+            "@article{priest1979, title = {The Logic of Paradox}, doi = {10.2307/30227165}}
+            @book{carnap1942, title = {Introduction to Semantics}, isbn = {0-674-46050-8}}
+            @book{prior1960, title = {The Runabout Inference-Ticket}}",
+        )
+        .unwrap();
+        let bibliography2 = Bibliography::parse(
+            "@article{Priest1979, title = {The Logic of Paradox}, doi = {10.1000/other}}
+            @book{Carnap1942a, title = {Semantics}, isbn = {9780674460508}}
+            @article{prior1960, title = {Something else}}",
+        )
+        .unwrap();
+        let entry1 = |key: &str| bibliography1.get(key).unwrap();
+        let entry2 = |key: &str| bibliography2.get(key).unwrap();
+
+        // Different DOIs are never the same, even with the same title
+        assert_eq!(find_match(entry1("priest1979"), entry2("Priest1979"), &config), None);
+        // The same key but a different type may be the same: in silent mode, both are kept
+        let found = find_match(entry1("prior1960"), entry2("prior1960"), &config).unwrap();
+        assert_eq!(found.criterion, MatchCriterion::Rule(1));
+        assert_eq!(
+            decide_match(entry1("prior1960"), entry2("prior1960"), &found, &config).0,
+            ComparisonResult::KeepBoth
+        );
+        // The same ISBN is the same entry, although the titles differ
+        let found = find_match(entry1("carnap1942"), entry2("Carnap1942a"), &config).unwrap();
+        assert_eq!(found.criterion, MatchCriterion::Rule(2));
+
+        let (unified, report) =
+            unify_bibliography_with_report(vec![bibliography1, bibliography2], &config);
+        assert_eq!(unified.len(), 5);
+        assert_eq!(report.matches.len(), 2);
+        assert!(unified.get("prior1960_1").is_some());
+    }
+
+    #[test]
+    fn test_report() {
+        let (bibliography1, mut config) = setup();
+        config.similarity_threshold = 0.7;
+        let file = fs::read_to_string("bib_files/test_files/similar_title.bib").unwrap();
+        let bibliography2 = Bibliography::parse(&file).unwrap();
+
+        let carnap1 = bibliography1.get("Carnap1942").unwrap();
+        let carnap2 = bibliography2.get("Carnap1942_1").unwrap();
+        let found = find_match(carnap1, carnap2, &config).unwrap();
+        assert_eq!(found.criterion, MatchCriterion::SimilarTitle);
+        assert!(found.similarity.unwrap() >= 0.7 && found.similarity.unwrap() < 1.0);
+
+        let (unified, report) = unify_bibliography_with_report(
+            vec![bibliography1.clone(), bibliography2, bibliography1],
+            &config,
+        );
+        assert_eq!(unified.len(), 6);
+        assert_eq!(report.matches.len(), 8);
+        assert_eq!(report.matches[1].kept.as_deref(), Some("Carnap1942"));
+        assert_eq!(report.matches[1].deleted(), Some("Carnap1942_1"));
+        assert_eq!(report.matches[1].criterion, MatchCriterion::SimilarTitle);
+        assert_eq!(report.matches[2].criterion, MatchCriterion::Identical);
+        assert_eq!(report.matches[2].prev_source, 0);
+        assert_eq!(report.matches[2].entry_source, 2);
+
+        // Each entry kept is in a single group, with the entries deleted in its favour
+        let groups = report.group_duplicates();
+        assert_eq!(groups.len(), 6);
+        let (kept, group) = &groups[1];
+        assert_eq!(*kept, "Carnap1942");
+        let deleted: Vec<&str> = group.iter().filter_map(|m| m.deleted()).collect();
+        assert_eq!(deleted, vec!["Carnap1942_1", "Carnap1942"]);
+    }
+
+    #[test]
+    fn test_recorded_decisions() {
+        let (mut bibliography1, mut config) = setup();
+        let file = fs::read_to_string("bib_files/test_files/only_same_doi.bib").unwrap();
+        let bibliography2 = Bibliography::parse(&file).unwrap();
+
+        // The decision recorded is to keep the second entry, even though in silent mode the first
+        // one would be kept
+        let hardegree1 = bibliography1.get("Hardegree2005completeness").unwrap();
+        let hardegree2 = bibliography2.get("Hardegree2005completeness1").unwrap();
+        let recorded_entry = |entry: &Entry| RecordedEntry {
+            fingerprint: entry_fingerprint(entry),
+            key: entry.key.clone(),
+        };
+        config.recorded_decisions.decisions.push(RecordedDecision {
+            first: recorded_entry(hardegree1),
+            second: recorded_entry(hardegree2),
+            keep: Keep::Second,
+        });
+        assert_eq!(
+            compare_entries(hardegree1, hardegree2, &config),
+            ComparisonResult::KeepEntry
+        );
+
+        let mut report = UnificationReport::default();
+        add_bibliography_with_report(bibliography2, &mut bibliography1, &config, &mut report);
+        assert!(bibliography1.get("Hardegree2005completeness").is_none());
+        assert!(bibliography1.get("Hardegree2005completeness1").is_some());
+        assert_eq!(report.matches[0].decided_by, Decider::Recorded);
+    }
+
+    #[test]
+    fn test_crossref() {
+        let (_, config) = setup();
+        let file = fs::read_to_string("bib_files/crossref_files/a.bib").unwrap();
+        let bibliography1 = Bibliography::parse(&file).unwrap();
+        let file = fs::read_to_string("bib_files/crossref_files/b.bib").unwrap();
+        let bibliography2 = Bibliography::parse(&file).unwrap();
+
+        let unified = unify_bibliography(vec![bibliography1, bibliography2], &config);
+        // The repeated collection is deleted, and the references to it now point to the other
+        assert_eq!(unified.len(), 3);
+        assert!(unified.get("CarnielliMalinowski2018").is_none());
+        let priest = unified.get("Priest2018").unwrap();
+        assert_eq!(priest.get_as::<String>("crossref").unwrap(), "Carnielli2018");
+        assert_eq!(
+            priest.get_as::<String>("related").unwrap(),
+            "BPS2018-WIAPL,Carnielli2018"
+        );
+
+        // The collection goes after the entries that reference it
+        let keys: Vec<&str> = unified.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["BPS2018-WIAPL", "Priest2018", "Carnielli2018"]);
+
+        // Even when sorting, which would put it between them
+        let config = Config {
+            sort: SortOrder::Key,
+            ..config
+        };
+        let unified = unify_bibliography(vec![unified], &config);
+        let keys: Vec<&str> = unified.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["BPS2018-WIAPL", "Priest2018", "Carnielli2018"]);
+    }
+
     #[test]
     fn test_get_new_citation_key() {
         let (mut bibliography1, _config) = setup();